@@ -1,10 +1,5 @@
 use async_trait::async_trait;
 
-#[cfg(feature = "file-transport")]
-use std::io::Result as IoResult;
-#[cfg(feature = "file-transport")]
-use std::path::Path;
-
 #[cfg(all(
     feature = "smtp-transport",
     any(feature = "tokio02", feature = "tokio1", feature = "async-std1")
@@ -14,6 +9,11 @@ use crate::transport::smtp::client::AsyncSmtpConnection;
     feature = "smtp-transport",
     any(feature = "tokio02", feature = "tokio1", feature = "async-std1")
 ))]
+use crate::transport::smtp::client::ConnectionOptions;
+#[cfg(all(
+    feature = "smtp-transport",
+    any(feature = "tokio02", feature = "tokio1", feature = "async-std1")
+))]
 use crate::transport::smtp::client::Tls;
 #[cfg(all(
     feature = "smtp-transport",
@@ -25,6 +25,13 @@ use crate::transport::smtp::extension::ClientId;
     any(feature = "tokio02", feature = "tokio1", feature = "async-std1")
 ))]
 use crate::transport::smtp::Error;
+#[cfg(feature = "file-transport")]
+use std::io::Result as IoResult;
+#[cfg(feature = "file-transport")]
+use std::path::Path;
+#[cfg(feature = "file-transport-envelope")]
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[async_trait]
 pub trait Executor: Send + Sync + private::Sealed {
@@ -35,8 +42,12 @@ pub trait Executor: Send + Sync + private::Sealed {
         port: u16,
         hello_name: &ClientId,
         tls: &Tls,
+        options: &ConnectionOptions,
     ) -> Result<AsyncSmtpConnection, Error>;
 
+    #[doc(hidden)]
+    async fn sleep(duration: Duration);
+
     #[doc(hidden)]
     #[cfg(feature = "file-transport-envelope")]
     async fn fs_read(path: &Path) -> IoResult<Vec<u8>>;
@@ -44,6 +55,40 @@ pub trait Executor: Send + Sync + private::Sealed {
     #[doc(hidden)]
     #[cfg(feature = "file-transport")]
     async fn fs_write(path: &Path, contents: &[u8]) -> IoResult<()>;
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_write_synced(path: &Path, contents: &[u8]) -> IoResult<()>;
+
+    /// Waits for the entries of a directory to reach the disk, only on Unix
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_sync_dir(path: &Path) -> IoResult<()>;
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_create_dir_all(path: &Path) -> IoResult<()>;
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_rename(from: &Path, to: &Path) -> IoResult<()>;
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_create_new(path: &Path) -> IoResult<()>;
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_append(path: &Path, contents: &[u8]) -> IoResult<u64>;
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_remove_file(path: &Path) -> IoResult<()>;
+
+    /// Returns the paths of the entries, and whether they are directories
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport-envelope")]
+    async fn fs_read_dir(path: &Path) -> IoResult<Vec<(PathBuf, bool)>>;
 }
 
 #[allow(missing_copy_implementations)]
@@ -62,6 +107,7 @@ impl Executor for Tokio02Executor {
         port: u16,
         hello_name: &ClientId,
         tls: &Tls,
+        options: &ConnectionOptions,
     ) -> Result<AsyncSmtpConnection, Error> {
         #[allow(clippy::match_single_binding)]
         let tls_parameters = match tls {
@@ -70,9 +116,14 @@ impl Executor for Tokio02Executor {
             _ => None,
         };
         #[allow(unused_mut)]
-        let mut conn =
-            AsyncSmtpConnection::connect_tokio02(hostname, port, hello_name, tls_parameters)
-                .await?;
+        let mut conn = AsyncSmtpConnection::connect_tokio02(
+            hostname,
+            port,
+            hello_name,
+            tls_parameters,
+            options,
+        )
+        .await?;
 
         #[cfg(any(feature = "tokio02-native-tls", feature = "tokio02-rustls-tls"))]
         match tls {
@@ -90,6 +141,11 @@ impl Executor for Tokio02Executor {
         Ok(conn)
     }
 
+    #[doc(hidden)]
+    async fn sleep(duration: Duration) {
+        tokio02_crate::time::delay_for(duration).await;
+    }
+
     #[doc(hidden)]
     #[cfg(feature = "file-transport-envelope")]
     async fn fs_read(path: &Path) -> IoResult<Vec<u8>> {
@@ -101,6 +157,87 @@ impl Executor for Tokio02Executor {
     async fn fs_write(path: &Path, contents: &[u8]) -> IoResult<()> {
         tokio02_crate::fs::write(path, contents).await
     }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_write_synced(path: &Path, contents: &[u8]) -> IoResult<()> {
+        use tokio02_crate::io::AsyncWriteExt;
+
+        let mut file = tokio02_crate::fs::File::create(path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_sync_dir(path: &Path) -> IoResult<()> {
+        // Directories can't be opened as files elsewhere
+        #[cfg(unix)]
+        tokio02_crate::fs::File::open(path)
+            .await?
+            .sync_all()
+            .await?;
+        #[cfg(not(unix))]
+        let _ = path;
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_create_dir_all(path: &Path) -> IoResult<()> {
+        tokio02_crate::fs::create_dir_all(path).await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_rename(from: &Path, to: &Path) -> IoResult<()> {
+        tokio02_crate::fs::rename(from, to).await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_create_new(path: &Path) -> IoResult<()> {
+        tokio02_crate::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await
+            .map(drop)
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_append(path: &Path, contents: &[u8]) -> IoResult<u64> {
+        use tokio02_crate::io::AsyncWriteExt;
+
+        let mut file = tokio02_crate::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .await?;
+        let offset = file.metadata().await?.len();
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        Ok(offset)
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_remove_file(path: &Path) -> IoResult<()> {
+        tokio02_crate::fs::remove_file(path).await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport-envelope")]
+    async fn fs_read_dir(path: &Path) -> IoResult<Vec<(PathBuf, bool)>> {
+        let mut dir = tokio02_crate::fs::read_dir(path).await?;
+        let mut paths = Vec::new();
+        while let Some(entry) = dir.next_entry().await? {
+            let is_dir = entry.file_type().await?.is_dir();
+            paths.push((entry.path(), is_dir));
+        }
+        Ok(paths)
+    }
 }
 
 #[allow(missing_copy_implementations)]
@@ -119,6 +256,7 @@ impl Executor for Tokio1Executor {
         port: u16,
         hello_name: &ClientId,
         tls: &Tls,
+        options: &ConnectionOptions,
     ) -> Result<AsyncSmtpConnection, Error> {
         #[allow(clippy::match_single_binding)]
         let tls_parameters = match tls {
@@ -127,8 +265,14 @@ impl Executor for Tokio1Executor {
             _ => None,
         };
         #[allow(unused_mut)]
-        let mut conn =
-            AsyncSmtpConnection::connect_tokio1(hostname, port, hello_name, tls_parameters).await?;
+        let mut conn = AsyncSmtpConnection::connect_tokio1(
+            hostname,
+            port,
+            hello_name,
+            tls_parameters,
+            options,
+        )
+        .await?;
 
         #[cfg(any(feature = "tokio1-native-tls", feature = "tokio1-rustls-tls"))]
         match tls {
@@ -146,6 +290,11 @@ impl Executor for Tokio1Executor {
         Ok(conn)
     }
 
+    #[doc(hidden)]
+    async fn sleep(duration: Duration) {
+        tokio1_crate::time::sleep(duration).await;
+    }
+
     #[doc(hidden)]
     #[cfg(feature = "file-transport-envelope")]
     async fn fs_read(path: &Path) -> IoResult<Vec<u8>> {
@@ -157,6 +306,84 @@ impl Executor for Tokio1Executor {
     async fn fs_write(path: &Path, contents: &[u8]) -> IoResult<()> {
         tokio1_crate::fs::write(path, contents).await
     }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_write_synced(path: &Path, contents: &[u8]) -> IoResult<()> {
+        use tokio1_crate::io::AsyncWriteExt;
+
+        let mut file = tokio1_crate::fs::File::create(path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_sync_dir(path: &Path) -> IoResult<()> {
+        // Directories can't be opened as files elsewhere
+        #[cfg(unix)]
+        tokio1_crate::fs::File::open(path).await?.sync_all().await?;
+        #[cfg(not(unix))]
+        let _ = path;
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_create_dir_all(path: &Path) -> IoResult<()> {
+        tokio1_crate::fs::create_dir_all(path).await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_rename(from: &Path, to: &Path) -> IoResult<()> {
+        tokio1_crate::fs::rename(from, to).await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_create_new(path: &Path) -> IoResult<()> {
+        tokio1_crate::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await
+            .map(drop)
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_append(path: &Path, contents: &[u8]) -> IoResult<u64> {
+        use tokio1_crate::io::AsyncWriteExt;
+
+        let mut file = tokio1_crate::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .await?;
+        let offset = file.metadata().await?.len();
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        Ok(offset)
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_remove_file(path: &Path) -> IoResult<()> {
+        tokio1_crate::fs::remove_file(path).await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport-envelope")]
+    async fn fs_read_dir(path: &Path) -> IoResult<Vec<(PathBuf, bool)>> {
+        let mut dir = tokio1_crate::fs::read_dir(path).await?;
+        let mut paths = Vec::new();
+        while let Some(entry) = dir.next_entry().await? {
+            let is_dir = entry.file_type().await?.is_dir();
+            paths.push((entry.path(), is_dir));
+        }
+        Ok(paths)
+    }
 }
 
 #[allow(missing_copy_implementations)]
@@ -175,6 +402,7 @@ impl Executor for AsyncStd1Executor {
         port: u16,
         hello_name: &ClientId,
         tls: &Tls,
+        options: &ConnectionOptions,
     ) -> Result<AsyncSmtpConnection, Error> {
         #[allow(clippy::match_single_binding)]
         let tls_parameters = match tls {
@@ -183,9 +411,14 @@ impl Executor for AsyncStd1Executor {
             _ => None,
         };
         #[allow(unused_mut)]
-        let mut conn =
-            AsyncSmtpConnection::connect_asyncstd1(hostname, port, hello_name, tls_parameters)
-                .await?;
+        let mut conn = AsyncSmtpConnection::connect_asyncstd1(
+            hostname,
+            port,
+            hello_name,
+            tls_parameters,
+            options,
+        )
+        .await?;
 
         #[cfg(any(feature = "async-std1-native-tls", feature = "async-std1-rustls-tls"))]
         match tls {
@@ -203,6 +436,11 @@ impl Executor for AsyncStd1Executor {
         Ok(conn)
     }
 
+    #[doc(hidden)]
+    async fn sleep(duration: Duration) {
+        async_std::task::sleep(duration).await;
+    }
+
     #[doc(hidden)]
     #[cfg(feature = "file-transport-envelope")]
     async fn fs_read(path: &Path) -> IoResult<Vec<u8>> {
@@ -214,6 +452,87 @@ impl Executor for AsyncStd1Executor {
     async fn fs_write(path: &Path, contents: &[u8]) -> IoResult<()> {
         async_std::fs::write(path, contents).await
     }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_write_synced(path: &Path, contents: &[u8]) -> IoResult<()> {
+        use async_std::io::WriteExt;
+
+        let mut file = async_std::fs::File::create(path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_sync_dir(path: &Path) -> IoResult<()> {
+        // Directories can't be opened as files elsewhere
+        #[cfg(unix)]
+        async_std::fs::File::open(path).await?.sync_all().await?;
+        #[cfg(not(unix))]
+        let _ = path;
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_create_dir_all(path: &Path) -> IoResult<()> {
+        async_std::fs::create_dir_all(path).await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_rename(from: &Path, to: &Path) -> IoResult<()> {
+        async_std::fs::rename(from, to).await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_create_new(path: &Path) -> IoResult<()> {
+        async_std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await
+            .map(drop)
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_append(path: &Path, contents: &[u8]) -> IoResult<u64> {
+        use async_std::io::WriteExt;
+
+        let mut file = async_std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .await?;
+        let offset = file.metadata().await?.len();
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        Ok(offset)
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport")]
+    async fn fs_remove_file(path: &Path) -> IoResult<()> {
+        async_std::fs::remove_file(path).await
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "file-transport-envelope")]
+    async fn fs_read_dir(path: &Path) -> IoResult<Vec<(PathBuf, bool)>> {
+        use futures_util::stream::StreamExt;
+
+        let mut dir = async_std::fs::read_dir(path).await?;
+        let mut paths = Vec::new();
+        while let Some(entry) = dir.next().await {
+            let entry = entry?;
+            let is_dir = entry.file_type().await?.is_dir();
+            paths.push((entry.path().into(), is_dir));
+        }
+        Ok(paths)
+    }
 }
 
 mod private {
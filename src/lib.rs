@@ -12,9 +12,12 @@
 //! * **builder**: Message builder
 //! * **file-transport**: Transport that write messages into a file
 //! * **file-transport-envelope**: Allow writing the envelope into a JSON file
+//! * **queue-transport**: Durable queue of messages, sent through another transport by a worker
 //! * **smtp-transport**: Transport over SMTP
 //! * **sendmail-transport**: Transport over SMTP
+//! * **smtp-test-server**: In-process SMTP server to test the SMTP transports
 //! * **rustls-tls**: TLS support with the `rustls` crate
+//! * **rustls-native-certs**: Allow loading the operating system root certificates with `rustls`
 //! * **native-tls**: TLS support with the `native-tls` crate
 //! * **tokio02**: Allow to asyncronously send emails using tokio 0.2.x
 //! * **tokio02-rustls-tls**: Async TLS support with the `rustls` crate using tokio 0.2
@@ -29,6 +32,7 @@
 //! * **tracing**: Logging using the `tracing` crate
 //! * **serde**: Serialization/Deserialization of entities
 //! * **hostname**: Ability to try to use actual hostname in SMTP transaction
+//! * **trust-dns-resolver**: System DNS resolver for direct delivery to mail exchangers
 
 #![doc(html_root_url = "https://docs.rs/crate/lettre/0.10.0-beta.1")]
 #![doc(html_favicon_url = "https://lettre.rs/favicon.ico")]
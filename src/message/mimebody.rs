@@ -1,6 +1,6 @@
 use crate::message::{
     header::{ContentTransferEncoding, ContentType, Header, Headers},
-    EmailFormat, IntoBody,
+    EmailFormat, Generator, IntoBody,
 };
 use mime::Mime;
 use rand::Rng;
@@ -173,6 +173,15 @@ fn make_boundary() -> String {
         .collect()
 }
 
+/// Replace the boundary of a multipart `Content-Type` header
+fn set_boundary(headers: &mut Headers, boundary: &str) {
+    let kind = {
+        let mime = &headers.get::<ContentType>().unwrap().0;
+        MultiPartKind::from_mime(mime).unwrap()
+    };
+    headers.set(ContentType(kind.to_mime(Some(boundary))));
+}
+
 impl MultiPartKind {
     fn to_mime<S: Into<String>>(&self, boundary: Option<S>) -> Mime {
         let boundary = boundary.map_or_else(make_boundary, |s| s.into());
@@ -229,6 +238,8 @@ impl From<MultiPartKind> for Mime {
 #[derive(Debug, Clone)]
 pub struct MultiPartBuilder {
     headers: Headers,
+    custom_boundary: bool,
+    boundaries: Option<Generator<String>>,
 }
 
 impl MultiPartBuilder {
@@ -236,6 +247,8 @@ impl MultiPartBuilder {
     pub fn new() -> Self {
         Self {
             headers: Headers::new(),
+            custom_boundary: false,
+            boundaries: None,
         }
     }
 
@@ -246,18 +259,36 @@ impl MultiPartBuilder {
     }
 
     /// Set `Content-Type` header using [`MultiPartKind`]
-    pub fn kind(self, kind: MultiPartKind) -> Self {
-        self.header(ContentType(kind.into()))
+    pub fn kind(mut self, kind: MultiPartKind) -> Self {
+        let boundary = match &self.boundaries {
+            Some(boundaries) => boundaries.generate(),
+            None => make_boundary(),
+        };
+        self.custom_boundary = false;
+        self.header(ContentType(kind.to_mime(Some(boundary))))
     }
 
     /// Set custom boundary
-    pub fn boundary<S: AsRef<str>>(self, boundary: S) -> Self {
-        let kind = {
-            let mime = &self.headers.get::<ContentType>().unwrap().0;
-            MultiPartKind::from_mime(mime).unwrap()
-        };
-        let mime = kind.to_mime(Some(boundary.as_ref()));
-        self.header(ContentType(mime))
+    pub fn boundary<S: AsRef<str>>(mut self, boundary: S) -> Self {
+        set_boundary(&mut self.headers, boundary.as_ref());
+        self.custom_boundary = true;
+        self
+    }
+
+    /// Set the generator of the boundary
+    ///
+    /// Replaces the current boundary, unless it was set with
+    /// [`boundary`](MultiPartBuilder::boundary). Defaults to random boundaries.
+    pub fn boundary_generator<F>(mut self, generator: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        let boundaries = Generator::new(generator);
+        if self.headers.has::<ContentType>() && !self.custom_boundary {
+            set_boundary(&mut self.headers, &boundaries.generate());
+        }
+        self.boundaries = Some(boundaries);
+        self
     }
 
     /// Creates multipart without parts
@@ -265,6 +296,7 @@ impl MultiPartBuilder {
         MultiPart {
             headers: self.headers,
             parts: Vec::new(),
+            custom_boundary: self.custom_boundary,
         }
     }
 
@@ -295,6 +327,7 @@ impl Default for MultiPartBuilder {
 pub struct MultiPart {
     headers: Headers,
     parts: Parts,
+    custom_boundary: bool,
 }
 
 impl MultiPart {
@@ -362,6 +395,18 @@ impl MultiPart {
         content_type.get_param("boundary").unwrap().as_str().into()
     }
 
+    /// Replace the generated boundaries of this multipart and the nested ones
+    pub(crate) fn replace_boundaries(&mut self, boundaries: &Generator<String>) {
+        if !self.custom_boundary {
+            set_boundary(&mut self.headers, &boundaries.generate());
+        }
+        for part in &mut self.parts {
+            if let Part::Multi(multipart) = part {
+                multipart.replace_boundaries(boundaries);
+            }
+        }
+    }
+
     /// Get the headers from the multipart
     pub fn headers(&self) -> &Headers {
         &self.headers
@@ -728,6 +773,26 @@ mod test {
                            "--F2mTKN843loAAAAA8porEdAjCKhArPxGeahYoZYSftse1GT/84tup+O0bs8eueVuAlMK--\r\n"));
     }
 
+    #[test]
+    fn multi_part_boundary_generator() {
+        let part = MultiPart::mixed()
+            .boundary_generator(|| String::from("generated"))
+            .build();
+        assert_eq!(part.boundary(), "generated");
+
+        let part = MultiPart::builder()
+            .boundary_generator(|| String::from("generated"))
+            .kind(MultiPartKind::Alternative)
+            .build();
+        assert_eq!(part.boundary(), "generated");
+
+        let part = MultiPart::mixed()
+            .boundary("custom")
+            .boundary_generator(|| String::from("generated"))
+            .build();
+        assert_eq!(part.boundary(), "custom");
+    }
+
     #[test]
     fn test_make_boundary() {
         let mut boundaries = std::collections::HashSet::with_capacity(10);
@@ -248,9 +248,17 @@ mod body;
 pub mod header;
 mod mailbox;
 mod mimebody;
-mod utf8_b;
-
-use std::{convert::TryFrom, time::SystemTime};
+pub(crate) mod utf8_b;
+
+use std::{
+    convert::TryFrom,
+    fmt::{self, Debug},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
 
 use uuid::Uuid;
 
@@ -268,11 +276,63 @@ trait EmailFormat {
     fn format(&self, out: &mut Vec<u8>);
 }
 
+/// Source of a value which is random or time-dependent by default
+///
+/// Used to inject the clock, Message-ID and boundary generators of the builders.
+#[derive(Clone)]
+pub(crate) struct Generator<T> {
+    generate: Arc<dyn Fn(usize) -> T + Send + Sync>,
+    /// Number of values generated since the last restart
+    count: Arc<AtomicUsize>,
+}
+
+impl<T> Generator<T> {
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+    {
+        Self::counting(move |_| f())
+    }
+
+    /// Creates a generator given the number of the value, counting from 1
+    fn counting<F>(f: F) -> Self
+    where
+        F: Fn(usize) -> T + Send + Sync + 'static,
+    {
+        Self {
+            generate: Arc::new(f),
+            count: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns a generator counting from 1 again, not shared with the clones of this one
+    pub(crate) fn restart(&self) -> Self {
+        Self {
+            generate: Arc::clone(&self.generate),
+            count: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub(crate) fn generate(&self) -> T {
+        (self.generate)(self.count.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+}
+
+impl<T> Debug for Generator<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Generator")
+    }
+}
+
 /// A builder for messages
 #[derive(Debug, Clone)]
 pub struct MessageBuilder {
     headers: Headers,
     envelope: Option<Envelope>,
+    generate_message_id: bool,
+    clock: Option<Generator<SystemTime>>,
+    message_ids: Option<Generator<String>>,
+    boundaries: Option<Generator<String>>,
 }
 
 impl MessageBuilder {
@@ -281,6 +341,10 @@ impl MessageBuilder {
         Self {
             headers: Headers::new(),
             envelope: None,
+            generate_message_id: false,
+            clock: None,
+            message_ids: None,
+            boundaries: None,
         }
     }
 
@@ -309,9 +373,60 @@ impl MessageBuilder {
 
     /// Set `Date` header using current date/time
     ///
-    /// Shortcut for `self.date(SystemTime::now())`.
-    pub fn date_now(self) -> Self {
-        self.date(SystemTime::now().into())
+    /// The date is read from the [clock](MessageBuilder::clock) when building the message.
+    pub fn date_now(mut self) -> Self {
+        self.headers.remove::<header::Date>();
+        self
+    }
+
+    /// Set the clock used to generate the `Date` header
+    ///
+    /// Defaults to `SystemTime::now`.
+    pub fn clock<F>(mut self, clock: F) -> Self
+    where
+        F: Fn() -> SystemTime + Send + Sync + 'static,
+    {
+        self.clock = Some(Generator::new(clock));
+        self
+    }
+
+    /// Set the generator of `Message-ID` header values, including the angle brackets
+    ///
+    /// Used when [`message_id`](MessageBuilder::message_id) is called with `None`.
+    /// Defaults to `<UUID@HOSTNAME>`.
+    pub fn message_id_generator<F>(mut self, generator: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.message_ids = Some(Generator::new(generator));
+        self
+    }
+
+    /// Set the generator of multipart boundaries
+    ///
+    /// When building the message, the boundaries of the multiparts of the body are
+    /// replaced by generated ones, in the order they appear in the message.
+    /// Boundaries set with [`MultiPartBuilder::boundary`] are kept.
+    /// Defaults to random boundaries.
+    pub fn boundary_generator<F>(mut self, generator: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.boundaries = Some(Generator::new(generator));
+        self
+    }
+
+    /// Make the formatted message only depend on the builder inputs
+    ///
+    /// Sets the clock to the Unix epoch, and generates `<N@localhost>` Message-IDs and
+    /// `lettre-boundary-N` boundaries, counting from 1 for every message. Useful
+    /// to compare messages against golden files in tests.
+    pub fn deterministic(mut self) -> Self {
+        self.message_ids = Some(Generator::counting(|n| {
+            format!("<{}@{}>", n, DEFAULT_MESSAGE_ID_DOMAIN)
+        }));
+        self.boundaries = Some(Generator::counting(|n| format!("lettre-boundary-{}", n)));
+        self.clock(|| SystemTime::UNIX_EPOCH)
     }
 
     /// Set `Subject` header to message
@@ -395,24 +510,16 @@ impl MessageBuilder {
     ///
     /// Should generally be inserted by the mail relay.
     ///
-    /// If `None` is provided, an id will be generated in the
-    /// `<UUID@HOSTNAME>`.
-    pub fn message_id(self, id: Option<String>) -> Self {
+    /// If `None` is provided, an id will be generated when building the message, in the
+    /// `<UUID@HOSTNAME>` format unless a
+    /// [generator](MessageBuilder::message_id_generator) is set.
+    pub fn message_id(mut self, id: Option<String>) -> Self {
+        self.generate_message_id = id.is_none();
         match id {
             Some(i) => self.header(header::MessageId(i)),
             None => {
-                #[cfg(feature = "hostname")]
-                let hostname = hostname::get()
-                    .map_err(|_| ())
-                    .and_then(|s| s.into_string().map_err(|_| ()))
-                    .unwrap_or_else(|_| DEFAULT_MESSAGE_ID_DOMAIN.to_string());
-                #[cfg(not(feature = "hostname"))]
-                let hostname = DEFAULT_MESSAGE_ID_DOMAIN.to_string();
-
-                self.header(header::MessageId(
-                    // https://tools.ietf.org/html/rfc5322#section-3.6.4
-                    format!("<{}@{}>", Uuid::new_v4(), hostname),
-                ))
+                self.headers.remove::<header::MessageId>();
+                self
             }
         }
     }
@@ -437,12 +544,24 @@ impl MessageBuilder {
         // https://tools.ietf.org/html/rfc5322#section-3.6
 
         // Insert Date if missing
-        let res = if self.headers.get::<header::Date>().is_none() {
-            self.date_now()
+        let mut res = if self.headers.get::<header::Date>().is_none() {
+            let now = self
+                .clock
+                .as_ref()
+                .map_or_else(SystemTime::now, Generator::generate);
+            self.date(now.into())
         } else {
             self
         };
 
+        if res.generate_message_id {
+            let id = res
+                .message_ids
+                .as_ref()
+                .map_or_else(make_message_id, |ids| ids.restart().generate());
+            res.headers.set(header::MessageId(id));
+        }
+
         // Fail is missing correct originator (Sender or From)
         match res.headers.get::<header::From>() {
             Some(header::From(f)) => {
@@ -481,7 +600,10 @@ impl MessageBuilder {
     }
 
     /// Create message using mime body ([`MultiPart`][self::MultiPart])
-    pub fn multipart(self, part: MultiPart) -> Result<Message, EmailError> {
+    pub fn multipart(self, mut part: MultiPart) -> Result<Message, EmailError> {
+        if let Some(boundaries) = &self.boundaries {
+            part.replace_boundaries(&boundaries.restart());
+        }
         self.mime_1_0().build(MessageBody::Mime(Part::Multi(part)))
     }
 
@@ -491,6 +613,20 @@ impl MessageBuilder {
     }
 }
 
+/// Create a `Message-ID` header value in the `<UUID@HOSTNAME>` format
+fn make_message_id() -> String {
+    #[cfg(feature = "hostname")]
+    let hostname = hostname::get()
+        .map_err(|_| ())
+        .and_then(|s| s.into_string().map_err(|_| ()))
+        .unwrap_or_else(|_| DEFAULT_MESSAGE_ID_DOMAIN.to_string());
+    #[cfg(not(feature = "hostname"))]
+    let hostname = DEFAULT_MESSAGE_ID_DOMAIN.to_string();
+
+    // https://tools.ietf.org/html/rfc5322#section-3.6.4
+    format!("<{}@{}>", Uuid::new_v4(), hostname)
+}
+
 /// Email message which can be formatted
 #[derive(Clone, Debug)]
 pub struct Message {
@@ -658,4 +794,111 @@ mod test {
             assert_eq!(line.0, line.1)
         }
     }
+
+    #[test]
+    fn email_deterministic() {
+        let email = || {
+            Message::builder()
+                .deterministic()
+                .from("NoBody <nobody@domain.tld>".parse().unwrap())
+                .to("Hei <hei@domain.tld>".parse().unwrap())
+                .message_id(None)
+                .multipart(
+                    MultiPart::mixed()
+                        .multipart(
+                            MultiPart::alternative()
+                                .singlepart(
+                                    SinglePart::builder()
+                                        .header(header::ContentType(
+                                            "text/plain; charset=utf8".parse().unwrap(),
+                                        ))
+                                        .body(String::from("Hello")),
+                                )
+                                .singlepart(
+                                    SinglePart::builder()
+                                        .header(header::ContentType(
+                                            "text/html; charset=utf8".parse().unwrap(),
+                                        ))
+                                        .body(String::from("<p>Hello</p>")),
+                                ),
+                        )
+                        .multipart(
+                            MultiPart::related().boundary("custom").singlepart(
+                                SinglePart::builder()
+                                    .header(header::ContentType(
+                                        "text/plain; charset=utf8".parse().unwrap(),
+                                    ))
+                                    .body(String::from("World")),
+                            ),
+                        ),
+                )
+                .unwrap()
+                .formatted()
+        };
+
+        assert_eq!(email(), email());
+        assert_eq!(
+            String::from_utf8(email()).unwrap(),
+            concat!(
+                "From: NoBody <nobody@domain.tld>\r\n",
+                "To: Hei <hei@domain.tld>\r\n",
+                "MIME-Version: 1.0\r\n",
+                "Date: Thu, 01 Jan 1970 00:00:00 GMT\r\n",
+                "Message-Id: <1@localhost>\r\n",
+                "Content-Type: multipart/mixed; boundary=\"lettre-boundary-1\"\r\n",
+                "\r\n",
+                "--lettre-boundary-1\r\n",
+                "Content-Type: multipart/alternative; boundary=\"lettre-boundary-2\"\r\n",
+                "\r\n",
+                "--lettre-boundary-2\r\n",
+                "Content-Type: text/plain; charset=utf8\r\n",
+                "Content-Transfer-Encoding: 7bit\r\n",
+                "\r\n",
+                "Hello\r\n",
+                "--lettre-boundary-2\r\n",
+                "Content-Type: text/html; charset=utf8\r\n",
+                "Content-Transfer-Encoding: 7bit\r\n",
+                "\r\n",
+                "<p>Hello</p>\r\n",
+                "--lettre-boundary-2--\r\n",
+                "--lettre-boundary-1\r\n",
+                "Content-Type: multipart/related; boundary=\"custom\"\r\n",
+                "\r\n",
+                "--custom\r\n",
+                "Content-Type: text/plain; charset=utf8\r\n",
+                "Content-Transfer-Encoding: 7bit\r\n",
+                "\r\n",
+                "World\r\n",
+                "--custom--\r\n",
+                "--lettre-boundary-1--\r\n"
+            )
+        );
+    }
+
+    #[test]
+    fn email_deterministic_template() {
+        let template = Message::builder()
+            .deterministic()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .message_id(None);
+        let email = |to: &str| {
+            template
+                .clone()
+                .to(to.parse().unwrap())
+                .multipart(
+                    MultiPart::mixed().singlepart(SinglePart::builder().body(String::from("Hi"))),
+                )
+                .unwrap()
+        };
+
+        for to in &["Hei <hei@domain.tld>", "Yuin <yuin@domain.tld>"] {
+            let email = email(to);
+            assert_eq!(
+                email.headers().get::<header::MessageId>(),
+                Some(&header::MessageId("<1@localhost>".to_string()))
+            );
+            let formatted = String::from_utf8(email.formatted()).unwrap();
+            assert!(formatted.contains("boundary=\"lettre-boundary-1\""));
+        }
+    }
 }
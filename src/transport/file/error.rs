@@ -1,6 +1,7 @@
 //! Error and result type for file transport
 
 use self::Error::*;
+use crate::transport::metrics::FailureClass;
 use std::{
     error::Error as StdError,
     fmt::{self, Display, Formatter},
@@ -41,6 +42,15 @@ impl StdError for Error {
     }
 }
 
+impl FailureClass for Error {
+    fn failure_class(&self) -> &'static str {
+        match *self {
+            Io(_) => "io",
+            _ => "client",
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         Error::Io(err)
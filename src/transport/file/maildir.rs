@@ -0,0 +1,228 @@
+//! Delivery to a [Maildir](https://cr.yp.to/proto/maildir.html)
+//!
+//! Messages are written to `tmp/`, then renamed into `new/`, so that readers never see
+//! partially written messages. Mail clients move them to `cur/` once seen, appending
+//! flags to their name after a colon.
+
+#[cfg(feature = "file-transport-envelope")]
+use super::outbox::read_dir;
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+use crate::Executor;
+#[cfg(feature = "file-transport-envelope")]
+use std::path::PathBuf;
+use std::{
+    fs, io,
+    path::Path,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const TMP: &str = "tmp";
+const NEW: &str = "new";
+const CUR: &str = "cur";
+
+/// Deliveries made by this process, to make names unique within the same microsecond
+static DELIVERIES: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates a unique file name, in the `time.MusecPpidQn.hostname` format
+///
+/// Microseconds are padded, so that names sort chronologically.
+pub(super) fn unique_name() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    #[cfg(feature = "hostname")]
+    let hostname = hostname::get()
+        .ok()
+        .and_then(|s| s.into_string().ok())
+        .unwrap_or_else(|| "localhost".to_string());
+    #[cfg(not(feature = "hostname"))]
+    let hostname = "localhost".to_string();
+
+    format!(
+        "{}.M{:06}P{}Q{}.{}",
+        now.as_secs(),
+        now.subsec_micros(),
+        process::id(),
+        DELIVERIES.fetch_add(1, Ordering::Relaxed),
+        // `/` and `:` can't appear in the names
+        hostname.replace('/', "\\057").replace(':', "\\072")
+    )
+}
+
+/// Writes a message to `tmp/` and moves it to `new/` once synced to disk
+///
+/// Returns once the move itself is synced to disk.
+pub(super) fn deliver(root: &Path, name: &str, email: &[u8]) -> io::Result<()> {
+    for dir in &[TMP, NEW, CUR] {
+        fs::create_dir_all(root.join(dir))?;
+    }
+
+    let tmp = root.join(TMP).join(name);
+    write_synced(&tmp, email)?;
+
+    fs::rename(tmp, root.join(NEW).join(name))?;
+    sync_dir(&root.join(NEW))
+}
+
+/// Writes a file and waits for its content to reach the disk
+pub(super) fn write_synced(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut file = fs::File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Waits for the entries of a directory to reach the disk, only on Unix
+pub(super) fn sync_dir(path: &Path) -> io::Result<()> {
+    // Directories can't be opened as files elsewhere
+    #[cfg(unix)]
+    fs::File::open(path)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Asynchronous version of [`deliver`]
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+pub(super) async fn deliver_async<E: Executor>(
+    root: &Path,
+    name: &str,
+    email: &[u8],
+) -> io::Result<()> {
+    for dir in &[TMP, NEW, CUR] {
+        E::fs_create_dir_all(&root.join(dir)).await?;
+    }
+
+    let tmp = root.join(TMP).join(name);
+    E::fs_write_synced(&tmp, email).await?;
+
+    E::fs_rename(&tmp, &root.join(NEW).join(name)).await?;
+    E::fs_sync_dir(&root.join(NEW)).await
+}
+
+/// Finds a delivered message, in `new/` or `cur/`
+#[cfg(feature = "file-transport-envelope")]
+pub(super) fn locate(root: &Path, name: &str) -> io::Result<PathBuf> {
+    let new = root.join(NEW).join(name);
+    if new.is_file() {
+        Ok(new)
+    } else {
+        find(or_empty(read_dir(&root.join(CUR)))?, name)
+    }
+}
+
+/// Asynchronous version of [`locate`]
+#[cfg(all(
+    feature = "file-transport-envelope",
+    any(feature = "async-std1", feature = "tokio02", feature = "tokio1")
+))]
+pub(super) async fn locate_async<E: Executor>(root: &Path, name: &str) -> io::Result<PathBuf> {
+    let mut entries = or_empty(E::fs_read_dir(&root.join(NEW)).await)?;
+    entries.extend(or_empty(E::fs_read_dir(&root.join(CUR)).await)?);
+    find(entries, name)
+}
+
+/// Lists the names of the delivered messages, without flags, in order
+#[cfg(feature = "file-transport-envelope")]
+pub(super) fn list(root: &Path) -> io::Result<Vec<String>> {
+    let mut entries = or_empty(read_dir(&root.join(NEW)))?;
+    entries.extend(or_empty(read_dir(&root.join(CUR)))?);
+    Ok(names(entries))
+}
+
+/// Asynchronous version of [`list`]
+#[cfg(all(
+    feature = "file-transport-envelope",
+    any(feature = "async-std1", feature = "tokio02", feature = "tokio1")
+))]
+pub(super) async fn list_async<E: Executor>(root: &Path) -> io::Result<Vec<String>> {
+    let mut entries = or_empty(E::fs_read_dir(&root.join(NEW)).await)?;
+    entries.extend(or_empty(E::fs_read_dir(&root.join(CUR)).await)?);
+    Ok(names(entries))
+}
+
+/// Considers a directory which doesn't exist as empty, as nothing was delivered to it
+#[cfg(feature = "file-transport-envelope")]
+fn or_empty(result: io::Result<Vec<(PathBuf, bool)>>) -> io::Result<Vec<(PathBuf, bool)>> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        result => result,
+    }
+}
+
+/// Returns the file names of the entries, without flags
+#[cfg(feature = "file-transport-envelope")]
+fn names(entries: Vec<(PathBuf, bool)>) -> Vec<String> {
+    let mut names: Vec<String> = entries
+        .into_iter()
+        .filter(|(_, is_dir)| !is_dir)
+        .filter_map(|(path, _)| {
+            let file_name = path.file_name()?.to_str()?;
+            // Skip hidden files, such as the files of mail servers
+            if file_name.starts_with('.') {
+                return None;
+            }
+            file_name.split(':').next().map(ToString::to_string)
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Finds the message named `name` in the entries, with or without flags
+#[cfg(feature = "file-transport-envelope")]
+fn find(entries: Vec<(PathBuf, bool)>, name: &str) -> io::Result<PathBuf> {
+    entries
+        .into_iter()
+        .filter(|(_, is_dir)| !is_dir)
+        .map(|(path, _)| path)
+        .find(|path| match path.file_name().and_then(|file_name| file_name.to_str()) {
+            Some(file_name) => {
+                file_name == name
+                    || matches!(file_name.strip_prefix(name), Some(flags) if flags.starts_with(':'))
+            }
+            None => false,
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "message not found in Maildir"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unique_names() {
+        let first = unique_name();
+        let second = unique_name();
+        assert_ne!(first, second);
+        assert!(!first.contains('/') && !first.contains(':'));
+    }
+
+    #[cfg(feature = "file-transport-envelope")]
+    #[test]
+    fn find_and_list_names() {
+        let entries = vec![
+            (PathBuf::from("cur/1.M2P3Q4.host:2,S"), false),
+            (PathBuf::from("cur/1.M2P3Q40.host"), false),
+            (PathBuf::from("cur/1.M2P3Q5.host"), true),
+        ];
+        assert_eq!(
+            find(entries.clone(), "1.M2P3Q4.host").unwrap(),
+            entries[0].0
+        );
+        assert_eq!(
+            find(entries.clone(), "1.M2P3Q40.host").unwrap(),
+            entries[1].0
+        );
+        assert!(find(entries.clone(), "1.M2P3Q5.host").is_err());
+
+        assert_eq!(
+            names(entries),
+            vec!["1.M2P3Q4.host".to_string(), "1.M2P3Q40.host".to_string()]
+        );
+    }
+}
@@ -0,0 +1,353 @@
+//! Appending to an [mbox](https://www.loc.gov/preservation/digital/formats/fdd/fdd000383.shtml)
+//! file, in the `mboxrd` variant
+//!
+//! Each message starts with a `From sender date` separator line, and the message lines
+//! matching `>*From ` are quoted with an additional `>`, so that quoting can be reversed.
+//! Writers lock the file by creating a `.lock` file next to it, as mail delivery agents do.
+//! Lock files older than [`STALE_LOCK`] are left over by crashed writers, and are removed.
+//! They are renamed first, and only removed if still stale once renamed, so that a writer
+//! doesn't remove a lock another writer took after breaking the same stale lock.
+
+use super::naming::Utc;
+use crate::address::Envelope;
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+use crate::Executor;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Time to wait for other writers to release the lock
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval between attempts to take the lock
+const LOCK_RETRY: Duration = Duration::from_millis(10);
+/// Age after which a lock is considered abandoned
+///
+/// Writers only hold the lock for the time of a single append.
+const STALE_LOCK: Duration = Duration::from_secs(60);
+
+/// Stale locks broken by this process, to give them unique names
+static BROKEN_LOCKS: AtomicUsize = AtomicUsize::new(0);
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats a message as an mbox entry, delivered at `date`
+///
+/// Line endings are converted to LF, and the entry ends with an empty line.
+pub(super) fn entry(envelope: &Envelope, email: &[u8], date: SystemTime) -> Vec<u8> {
+    let sender = envelope
+        .from()
+        .map_or_else(|| "MAILER-DAEMON".to_string(), ToString::to_string);
+    let mut out = format!("From {} {}\n", sender, asctime(date)).into_bytes();
+
+    let mut lines: Vec<&[u8]> = email.split(|byte| *byte == b'\n').collect();
+    if email.ends_with(b"\n") {
+        lines.pop();
+    }
+    for line in lines {
+        let line = match line.last() {
+            Some(b'\r') => &line[..line.len() - 1],
+            _ => line,
+        };
+        let quotes = line.iter().take_while(|byte| **byte == b'>').count();
+        if line[quotes..].starts_with(b"From ") {
+            out.push(b'>');
+        }
+        out.extend_from_slice(line);
+        out.push(b'\n');
+    }
+
+    out.push(b'\n');
+    out
+}
+
+/// Formats a date like the C `asctime` function, in UTC
+fn asctime(date: SystemTime) -> String {
+    let time = Utc::new(date);
+    format!(
+        "{} {} {:2} {:02}:{:02}:{:02} {}",
+        // 1970-01-01 was a Thursday
+        DAYS[((time.days + 4) % 7) as usize],
+        MONTHS[time.month as usize - 1],
+        time.day,
+        time.hour,
+        time.minute,
+        time.second,
+        time.year
+    )
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    lock.into()
+}
+
+fn lock_timeout(lock: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("mbox lock {} is held by another writer", lock.display()),
+    )
+}
+
+/// Tells if a lock modified at `modified` was abandoned
+fn is_stale(modified: SystemTime, now: SystemTime) -> bool {
+    // A lock from the future is not stale
+    now.duration_since(modified).unwrap_or_default() >= STALE_LOCK
+}
+
+/// Returns the modification time of a file, or `None` if it doesn't exist
+fn modified(path: &Path) -> io::Result<Option<SystemTime>> {
+    match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => Ok(Some(modified)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Removes the lock if it was abandoned, returning whether it is gone
+fn break_stale(lock: &Path) -> io::Result<bool> {
+    let now = SystemTime::now();
+    match modified(lock)? {
+        // Released in the meantime
+        None => Ok(true),
+        Some(modified) if is_stale(modified, now) => break_lock(lock, now),
+        Some(_) => Ok(false),
+    }
+}
+
+/// Removes a lock seen stale, unless another writer took it again in the meantime
+///
+/// The lock is moved away before checking it again, so that no other writer can take it
+/// between the check and the removal. A lock which turns out to be fresh is put back.
+fn break_lock(lock: &Path, now: SystemTime) -> io::Result<bool> {
+    let mut broken = lock.as_os_str().to_owned();
+    broken.push(format!(
+        ".{}.{}.broken",
+        process::id(),
+        BROKEN_LOCKS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let broken = PathBuf::from(broken);
+
+    match fs::rename(lock, &broken) {
+        // Broken by another writer in the meantime
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(true),
+        result => result?,
+    }
+
+    match modified(&broken)? {
+        Some(modified) if !is_stale(modified, now) => {
+            // Fails if yet another writer took the lock, which is held either way
+            let restored = fs::hard_link(&broken, lock);
+            fs::remove_file(&broken)?;
+            match restored {
+                Err(err) if err.kind() != io::ErrorKind::AlreadyExists => Err(err),
+                _ => Ok(false),
+            }
+        }
+        _ => match fs::remove_file(&broken) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(true),
+        },
+    }
+}
+
+/// Lock of an mbox file, removed when dropped
+///
+/// Dropping it, on error, panic or when an async append is cancelled, doesn't leave
+/// the lock behind.
+struct Lock {
+    path: PathBuf,
+    held: bool,
+}
+
+impl Lock {
+    fn new(path: PathBuf) -> Self {
+        Self { path, held: true }
+    }
+
+    /// Releases the lock, reporting failures to remove it
+    fn release(mut self) -> io::Result<()> {
+        self.held = false;
+        fs::remove_file(&self.path)
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if self.held {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Appends an entry to the mbox file under lock
+///
+/// Returns the offset of the entry in the file.
+pub(super) fn append(path: &Path, entry: &[u8]) -> io::Result<u64> {
+    let lock = lock_path(path);
+    let start = Instant::now();
+    let lock = loop {
+        match OpenOptions::new().write(true).create_new(true).open(&lock) {
+            Ok(_) => break Lock::new(lock),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                if break_stale(&lock)? {
+                    continue;
+                }
+                if start.elapsed() >= LOCK_TIMEOUT {
+                    return Err(lock_timeout(&lock));
+                }
+                thread::sleep(LOCK_RETRY);
+            }
+            Err(err) => return Err(err),
+        }
+    };
+
+    let result = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| {
+            let offset = file.metadata()?.len();
+            file.write_all(entry)?;
+            file.sync_all()?;
+            Ok(offset)
+        });
+    let unlocked = lock.release();
+
+    let offset = result?;
+    unlocked?;
+    Ok(offset)
+}
+
+/// Asynchronous version of [`append`]
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+pub(super) async fn append_async<E: Executor>(path: &Path, entry: &[u8]) -> io::Result<u64> {
+    let lock = lock_path(path);
+    let start = Instant::now();
+    let lock = loop {
+        match E::fs_create_new(&lock).await {
+            Ok(()) => break Lock::new(lock),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                if break_stale(&lock)? {
+                    continue;
+                }
+                if start.elapsed() >= LOCK_TIMEOUT {
+                    return Err(lock_timeout(&lock));
+                }
+                E::sleep(LOCK_RETRY).await;
+            }
+            Err(err) => return Err(err),
+        }
+    };
+
+    let result = E::fs_append(path, entry).await;
+    let unlocked = lock.release();
+
+    let offset = result?;
+    unlocked?;
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn stale_locks() {
+        let now = UNIX_EPOCH + Duration::from_secs(3600);
+        assert!(!is_stale(now, now));
+        assert!(!is_stale(now - Duration::from_secs(10), now));
+        assert!(is_stale(now - STALE_LOCK, now));
+        // Clock going backwards
+        assert!(!is_stale(now + Duration::from_secs(10), now));
+    }
+
+    #[test]
+    fn break_locks() {
+        let dir = std::env::temp_dir().join("lettre-mbox-break-lock");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let lock = dir.join("test.mbox.lock");
+        let entries = || fs::read_dir(&dir).unwrap().count();
+
+        // Taken again after being seen stale
+        fs::write(&lock, b"").unwrap();
+        assert!(!break_lock(&lock, SystemTime::now()).unwrap());
+        assert!(lock.exists());
+        assert_eq!(entries(), 1);
+
+        assert!(break_lock(&lock, SystemTime::now() + STALE_LOCK).unwrap());
+        assert_eq!(entries(), 0);
+
+        // Broken by another writer
+        assert!(break_lock(&lock, SystemTime::now()).unwrap());
+        assert!(break_stale(&lock).unwrap());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lock_removed_on_drop() {
+        let path = std::env::temp_dir().join("lettre-mbox-lock-drop.mbox.lock");
+        fs::write(&path, b"").unwrap();
+        drop(Lock::new(path.clone()));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn format_asctime() {
+        assert_eq!(asctime(UNIX_EPOCH), "Thu Jan  1 00:00:00 1970");
+        assert_eq!(
+            asctime(UNIX_EPOCH + Duration::from_secs(784_887_151)),
+            "Tue Nov 15 08:12:31 1994"
+        );
+        assert_eq!(
+            asctime(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "Tue Feb 29 00:00:00 2000"
+        );
+    }
+
+    #[test]
+    fn format_entry() {
+        let envelope = Envelope::new(
+            Some("nobody@domain.tld".parse().unwrap()),
+            vec!["hei@domain.tld".parse().unwrap()],
+        )
+        .unwrap();
+        let email = b"Subject: Hi\r\n\r\nFrom here\r\n>From there\r\n>>From everywhere\r\nFrom: me";
+
+        assert_eq!(
+            String::from_utf8(entry(&envelope, email, UNIX_EPOCH)).unwrap(),
+            concat!(
+                "From nobody@domain.tld Thu Jan  1 00:00:00 1970\n",
+                "Subject: Hi\n",
+                "\n",
+                ">From here\n",
+                ">>From there\n",
+                ">>>From everywhere\n",
+                "From: me\n",
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn format_entry_without_sender() {
+        let envelope = Envelope::new(None, vec!["hei@domain.tld".parse().unwrap()]).unwrap();
+
+        assert_eq!(
+            entry(&envelope, b"Subject: Hi\r\n\r\nBye\r\n", UNIX_EPOCH),
+            b"From MAILER-DAEMON Thu Jan  1 00:00:00 1970\nSubject: Hi\n\nBye\n\n".to_vec()
+        );
+    }
+}
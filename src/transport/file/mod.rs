@@ -1,5 +1,5 @@
 //! The file transport writes the emails to the given directory. The name of the file will be
-//! `message_id.eml`.
+//! `message_id.eml`, where the id is a random UUID unless another [`Naming`] is set.
 //! It can be useful for testing purposes, or if you want to keep track of sent messages.
 //!
 //! ## Sync example
@@ -62,6 +62,98 @@
 //! # fn main() {}
 //! ```
 //!
+//! ## Outbox
+//!
+//! When the envelopes are saved, the messages written by the transport can be listed,
+//! read, removed or moved to another directory.
+//!
+//! ```rust
+//! # use std::error::Error;
+//!
+//! # #[cfg(all(feature = "file-transport-envelope", feature = "builder"))]
+//! # fn main() -> Result<(), Box<dyn Error>> {
+//! use lettre::{transport::file::Naming, FileTransport};
+//! use std::env::temp_dir;
+//!
+//! let dir = temp_dir().join("outbox");
+//! std::fs::create_dir_all(&dir)?;
+//!
+//! let outbox = FileTransport::with_envelope(&dir).naming(Naming::Timestamp);
+//! for (id, envelope) in outbox.list()? {
+//!     let (_, email) = outbox.read(&id)?;
+//!     println!("{:?}: {} bytes", envelope, email.len());
+//!     outbox.move_to(&id, temp_dir().join("sent"))?;
+//! }
+//! # Ok(())
+//! # }
+//!
+//! # #[cfg(not(all(feature = "file-transport-envelope", feature = "builder")))]
+//! # fn main() {}
+//! ```
+//!
+//! ## Maildir
+//!
+//! The messages can also be delivered to a [Maildir](https://cr.yp.to/proto/maildir.html),
+//! to read them with a mail client. They are written to the `tmp` directory, then moved
+//! to the `new` directory once complete. When saved, the envelopes are written to an
+//! `envelope` directory next to them.
+//!
+//! ```rust
+//! # use std::error::Error;
+//!
+//! # #[cfg(all(feature = "file-transport", feature = "builder"))]
+//! # fn main() -> Result<(), Box<dyn Error>> {
+//! use lettre::{FileTransport, Message, Transport};
+//! use std::env::temp_dir;
+//!
+//! // Deliver to the `Maildir` directory of the local temp directory
+//! let sender = FileTransport::new(temp_dir().join("Maildir")).maildir();
+//! let email = Message::builder()
+//!     .from("NoBody <nobody@domain.tld>".parse()?)
+//!     .reply_to("Yuin <yuin@domain.tld>".parse()?)
+//!     .to("Hei <hei@domain.tld>".parse()?)
+//!     .subject("Happy new year")
+//!     .body(String::from("Be happy!"))?;
+//!
+//! let result = sender.send(&email);
+//! assert!(result.is_ok());
+//! # Ok(())
+//! # }
+//!
+//! # #[cfg(not(all(feature = "file-transport", feature = "builder")))]
+//! # fn main() {}
+//! ```
+//!
+//! ## mbox
+//!
+//! The messages can also be appended to a single mbox file, in the `mboxrd` format.
+//!
+//! ```rust
+//! # use std::error::Error;
+//!
+//! # #[cfg(all(feature = "file-transport", feature = "builder"))]
+//! # fn main() -> Result<(), Box<dyn Error>> {
+//! use lettre::{FileTransport, Message, Transport};
+//! use std::env::temp_dir;
+//!
+//! // Append to the `sent.mbox` file of the local temp directory
+//! let sender = FileTransport::new(temp_dir().join("sent.mbox")).mbox();
+//! let email = Message::builder()
+//!     .from("NoBody <nobody@domain.tld>".parse()?)
+//!     .reply_to("Yuin <yuin@domain.tld>".parse()?)
+//!     .to("Hei <hei@domain.tld>".parse()?)
+//!     .subject("Happy new year")
+//!     .body(String::from("Be happy!"))?;
+//!
+//! let result = sender.send(&email);
+//! assert!(result.is_ok());
+//! # Ok(())
+//! # }
+//!
+//! # #[cfg(not(all(feature = "file-transport", feature = "builder")))]
+//! # fn main() {}
+//! ```
+//!
 //! ## Async tokio 1.x
 //!
 //! ```rust,no_run
@@ -132,8 +224,11 @@
 //! {"forward_path":["hei@domain.tld"],"reverse_path":"nobody@domain.tld"}
 //! ```
 
-pub use self::error::Error;
-use crate::{address::Envelope, Transport};
+pub use self::{
+    error::Error,
+    naming::{Naming, NamingFn},
+};
+use crate::{address::Envelope, transport::instrument::SendScope, Transport};
 #[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
 use crate::{AsyncTransport, Executor};
 #[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
@@ -143,13 +238,21 @@ use std::marker::PhantomData;
 use std::{
     path::{Path, PathBuf},
     str,
+    time::SystemTime,
 };
-use uuid::Uuid;
 
 mod error;
+mod maildir;
+mod mbox;
+mod naming;
+#[cfg(feature = "file-transport-envelope")]
+mod outbox;
 
 type Id = String;
 
+#[cfg(feature = "file-transport-envelope")]
+const MBOX_READ: &str = "messages can't be read back from mbox files";
+
 /// Writes the content and the envelope information to a file
 #[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -157,6 +260,30 @@ pub struct FileTransport {
     path: PathBuf,
     #[cfg(feature = "file-transport-envelope")]
     save_envelope: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    layout: Layout,
+    #[cfg_attr(feature = "serde", serde(default))]
+    naming: Naming,
+}
+
+/// Organization of the files in the transport directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Layout {
+    /// `id.eml` and `id.json` files
+    Flat,
+    /// Maildir `tmp`, `new` and `cur` directories, and `envelope/id.json` files
+    Maildir,
+    /// A single mbox file
+    Mbox,
+}
+
+// Deriving with `#[default]` requires Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Flat
+    }
 }
 
 #[derive(Debug)]
@@ -176,6 +303,8 @@ impl FileTransport {
             path: PathBuf::from(path.as_ref()),
             #[cfg(feature = "file-transport-envelope")]
             save_envelope: false,
+            layout: Layout::Flat,
+            naming: Naming::Uuid,
         }
     }
 
@@ -189,9 +318,43 @@ impl FileTransport {
             path: PathBuf::from(path.as_ref()),
             #[cfg(feature = "file-transport-envelope")]
             save_envelope: true,
+            layout: Layout::Flat,
+            naming: Naming::Uuid,
         }
     }
 
+    /// Delivers the messages to a Maildir in the transport directory
+    ///
+    /// Each message is written to `tmp`, then moved to `new` with a unique name once
+    /// complete. The `tmp`, `new` and `cur` directories are created when missing.
+    /// Envelopes are written to an `envelope` directory, and synced to disk too.
+    pub fn maildir(mut self) -> Self {
+        self.layout = Layout::Maildir;
+        self
+    }
+
+    /// Sets the naming of the message files
+    ///
+    /// Defaults to [`Naming::Uuid`]. Only used when writing one file per message, as
+    /// Maildir and mbox have their own naming.
+    pub fn naming(mut self, naming: Naming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Appends the messages to the mbox file at the transport path
+    ///
+    /// Messages are written in the `mboxrd` format, with a `From` line built from the
+    /// envelope sender and the current date, and LF line endings. The file is locked by a
+    /// `.lock` file while writing, and sending fails if it stays locked for 10 seconds.
+    /// The returned id is the offset of the message in the file.
+    ///
+    /// Envelopes are not saved, and messages can't be read back.
+    pub fn mbox(mut self) -> Self {
+        self.layout = Layout::Mbox;
+        self
+    }
+
     /// Read a message that was written using the file transport.
     ///
     /// Reads the envelope and the raw message content.
@@ -199,19 +362,53 @@ impl FileTransport {
     pub fn read(&self, email_id: &str) -> Result<(Envelope, Vec<u8>), Error> {
         use std::fs;
 
-        let eml_file = self.path.join(format!("{}.eml", email_id));
-        let eml = fs::read(eml_file)?;
+        let eml = match self.layout {
+            Layout::Flat => fs::read(self.path(email_id, "eml"))?,
+            Layout::Maildir => fs::read(maildir::locate(&self.path, email_id)?)?,
+            Layout::Mbox => return Err(Error::Client(MBOX_READ)),
+        };
 
-        let json_file = self.path.join(format!("{}.json", email_id));
-        let json = fs::read(&json_file)?;
+        let json = fs::read(self.envelope_path(email_id))?;
         let envelope = serde_json::from_slice(&json)?;
 
         Ok((envelope, eml))
     }
 
-    fn path(&self, email_id: &Uuid, extension: &str) -> PathBuf {
+    fn path(&self, email_id: &str, extension: &str) -> PathBuf {
         self.path.join(format!("{}.{}", email_id, extension))
     }
+
+    #[cfg(feature = "file-transport-envelope")]
+    fn envelope_path(&self, email_id: &str) -> PathBuf {
+        match self.layout {
+            Layout::Flat | Layout::Mbox => self.path(email_id, "json"),
+            Layout::Maildir => self
+                .path
+                .join("envelope")
+                .join(format!("{}.json", email_id)),
+        }
+    }
+
+    fn new_id(&self, envelope: &Envelope, email: &[u8]) -> Id {
+        match self.layout {
+            Layout::Maildir => maildir::unique_name(),
+            _ => self.naming.name(envelope, email, SystemTime::now()),
+        }
+    }
+
+    /// Returns the directory to create before writing the files of a message, if any
+    fn new_dir(&self, email_id: &str) -> Option<PathBuf> {
+        match self.layout {
+            Layout::Flat if email_id.contains('/') => {
+                self.path(email_id, "eml").parent().map(Path::to_path_buf)
+            }
+            #[cfg(feature = "file-transport-envelope")]
+            Layout::Maildir if self.save_envelope => {
+                self.envelope_path(email_id).parent().map(Path::to_path_buf)
+            }
+            _ => None,
+        }
+    }
 }
 
 #[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
@@ -241,16 +438,44 @@ where
         }
     }
 
+    /// Delivers the messages to a Maildir in the transport directory
+    ///
+    /// See [`FileTransport::maildir`].
+    pub fn maildir(mut self) -> Self {
+        self.inner = self.inner.maildir();
+        self
+    }
+
+    /// Sets the naming of the message files
+    ///
+    /// See [`FileTransport::naming`].
+    pub fn naming(mut self, naming: Naming) -> Self {
+        self.inner = self.inner.naming(naming);
+        self
+    }
+
+    /// Appends the messages to the mbox file at the transport path
+    ///
+    /// See [`FileTransport::mbox`].
+    pub fn mbox(mut self) -> Self {
+        self.inner = self.inner.mbox();
+        self
+    }
+
     /// Read a message that was written using the file transport.
     ///
     /// Reads the envelope and the raw message content.
     #[cfg(feature = "file-transport-envelope")]
     pub async fn read(&self, email_id: &str) -> Result<(Envelope, Vec<u8>), Error> {
-        let eml_file = self.inner.path.join(format!("{}.eml", email_id));
-        let eml = E::fs_read(&eml_file).await?;
+        let eml = match self.inner.layout {
+            Layout::Flat => E::fs_read(&self.inner.path(email_id, "eml")).await?,
+            Layout::Maildir => {
+                E::fs_read(&maildir::locate_async::<E>(&self.inner.path, email_id).await?).await?
+            }
+            Layout::Mbox => return Err(Error::Client(MBOX_READ)),
+        };
 
-        let json_file = self.inner.path.join(format!("{}.json", email_id));
-        let json = E::fs_read(&json_file).await?;
+        let json = E::fs_read(&self.inner.envelope_path(email_id)).await?;
         let envelope = serde_json::from_slice(&json)?;
 
         Ok((envelope, eml))
@@ -262,24 +487,46 @@ impl Transport for FileTransport {
     type Error = Error;
 
     fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
-        use std::fs;
+        let scope = SendScope::new("file", envelope, email);
+        let result = scope.in_scope(|| -> Result<Self::Ok, Self::Error> {
+            use std::fs;
 
-        let email_id = Uuid::new_v4();
+            if self.layout == Layout::Mbox {
+                let entry = mbox::entry(envelope, email, SystemTime::now());
+                return Ok(mbox::append(&self.path, &entry)?.to_string());
+            }
 
-        let file = self.path(&email_id, "eml");
-        fs::write(file, email)?;
+            let email_id = self.new_id(envelope, email);
+            if let Some(dir) = self.new_dir(&email_id) {
+                fs::create_dir_all(dir)?;
+            }
 
-        #[cfg(feature = "file-transport-envelope")]
-        {
-            if self.save_envelope {
-                let file = self.path(&email_id, "json");
-                fs::write(file, serde_json::to_string(&envelope)?)?;
+            // Write the envelope first, so that it is there when the message appears
+            #[cfg(feature = "file-transport-envelope")]
+            {
+                if self.save_envelope {
+                    let file = self.envelope_path(&email_id);
+                    let buf = serde_json::to_vec(&envelope)?;
+                    // Maildir deliveries are durable, so is their envelope
+                    match self.layout {
+                        Layout::Maildir => {
+                            maildir::write_synced(&file, &buf)?;
+                            maildir::sync_dir(file.parent().unwrap())?;
+                        }
+                        _ => fs::write(file, buf)?,
+                    }
+                }
+            }
+
+            match self.layout {
+                Layout::Maildir => maildir::deliver(&self.path, &email_id, email)?,
+                _ => fs::write(self.path(&email_id, "eml"), email)?,
             }
-        }
-        // use envelope anyway
-        let _ = envelope;
 
-        Ok(email_id.to_string())
+            Ok(email_id)
+        });
+        scope.finish(&result);
+        result
     }
 }
 
@@ -293,22 +540,47 @@ where
     type Error = Error;
 
     async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
-        let email_id = Uuid::new_v4();
+        let scope = SendScope::new("file", envelope, email);
+        let result: Result<Self::Ok, Self::Error> = scope
+            .run(async {
+                if self.inner.layout == Layout::Mbox {
+                    let entry = mbox::entry(envelope, email, SystemTime::now());
+                    let offset = mbox::append_async::<E>(&self.inner.path, &entry).await?;
+                    return Ok(offset.to_string());
+                }
 
-        let file = self.inner.path(&email_id, "eml");
-        E::fs_write(&file, email).await?;
+                let email_id = self.inner.new_id(envelope, email);
+                if let Some(dir) = self.inner.new_dir(&email_id) {
+                    E::fs_create_dir_all(&dir).await?;
+                }
 
-        #[cfg(feature = "file-transport-envelope")]
-        {
-            if self.inner.save_envelope {
-                let file = self.inner.path(&email_id, "json");
-                let buf = serde_json::to_vec(&envelope)?;
-                E::fs_write(&file, &buf).await?;
-            }
-        }
-        // use envelope anyway
-        let _ = envelope;
+                // Write the envelope first, so that it is there when the message appears
+                #[cfg(feature = "file-transport-envelope")]
+                {
+                    if self.inner.save_envelope {
+                        let file = self.inner.envelope_path(&email_id);
+                        let buf = serde_json::to_vec(&envelope)?;
+                        match self.inner.layout {
+                            Layout::Maildir => {
+                                E::fs_write_synced(&file, &buf).await?;
+                                E::fs_sync_dir(file.parent().unwrap()).await?;
+                            }
+                            _ => E::fs_write(&file, &buf).await?,
+                        }
+                    }
+                }
+
+                match self.inner.layout {
+                    Layout::Maildir => {
+                        maildir::deliver_async::<E>(&self.inner.path, &email_id, email).await?
+                    }
+                    _ => E::fs_write(&self.inner.path(&email_id, "eml"), email).await?,
+                }
 
-        Ok(email_id.to_string())
+                Ok(email_id)
+            })
+            .await;
+        scope.finish(&result);
+        result
     }
 }
@@ -0,0 +1,233 @@
+//! Naming of the files written by the file transport
+
+use crate::{address::Envelope, transport::headers};
+use std::{
+    fmt::{self, Debug},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+/// Function naming a message from its envelope and raw content
+pub type NamingFn = Arc<dyn Fn(&Envelope, &[u8]) -> String + Send + Sync>;
+
+/// Naming of the messages written by the file transport
+///
+/// The name is the id returned when sending a message, and given to `read`. It can
+/// contain `/` to write messages to subdirectories, which are created when needed.
+/// The `.eml` and `.json` extensions are added to it.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Naming {
+    /// A random UUID
+    Uuid,
+    /// The `Message-ID` header of the message, without the angle brackets
+    ///
+    /// Characters which are not letters, digits or one of `.-_@+=` are replaced by `_`.
+    /// Messages without `Message-ID` get a random UUID, and a message sent twice is
+    /// overwritten.
+    MessageId,
+    /// The UTC time of sending followed by a random UUID, sorting chronologically
+    ///
+    /// For example `20261016T143015.123456Z-5f1c9a1e-0d5b-4bd5-9a9c-1b3b9e1f7c2d`.
+    Timestamp,
+    /// A random UUID in a directory for the UTC day of sending
+    ///
+    /// For example `2026/10/16/5f1c9a1e-0d5b-4bd5-9a9c-1b3b9e1f7c2d`.
+    Daily,
+    /// The name returned by a function of the envelope and raw message
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(NamingFn),
+}
+
+impl Naming {
+    /// Names a message sent at `date`
+    pub(super) fn name(&self, envelope: &Envelope, email: &[u8], date: SystemTime) -> String {
+        match self {
+            Naming::Uuid => Uuid::new_v4().to_string(),
+            Naming::MessageId => message_id(email)
+                .map(|id| {
+                    id.chars()
+                        .map(|c| match c {
+                            'a'..='z'
+                            | 'A'..='Z'
+                            | '0'..='9'
+                            | '.'
+                            | '-'
+                            | '_'
+                            | '@'
+                            | '+'
+                            | '=' => c,
+                            _ => '_',
+                        })
+                        .collect()
+                })
+                .unwrap_or_else(|| Uuid::new_v4().to_string()),
+            Naming::Timestamp => {
+                let time = Utc::new(date);
+                format!(
+                    "{:04}{:02}{:02}T{:02}{:02}{:02}.{:06}Z-{}",
+                    time.year,
+                    time.month,
+                    time.day,
+                    time.hour,
+                    time.minute,
+                    time.second,
+                    time.micros,
+                    Uuid::new_v4()
+                )
+            }
+            Naming::Daily => {
+                let time = Utc::new(date);
+                format!(
+                    "{:04}/{:02}/{:02}/{}",
+                    time.year,
+                    time.month,
+                    time.day,
+                    Uuid::new_v4()
+                )
+            }
+            Naming::Custom(f) => f(envelope, email),
+        }
+    }
+}
+
+impl Debug for Naming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Naming::Uuid => f.write_str("Uuid"),
+            Naming::MessageId => f.write_str("MessageId"),
+            Naming::Timestamp => f.write_str("Timestamp"),
+            Naming::Daily => f.write_str("Daily"),
+            Naming::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+// Deriving with `#[default]` requires Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for Naming {
+    fn default() -> Self {
+        Naming::Uuid
+    }
+}
+
+/// Finds the `Message-ID` header of a raw message, without the angle brackets
+fn message_id(email: &[u8]) -> Option<String> {
+    let id = headers::header(email, "Message-ID")?;
+    let id = id.trim_start_matches('<').trim_end_matches('>');
+    if id.is_empty() {
+        None
+    } else {
+        Some(id.to_string())
+    }
+}
+
+/// Components of a UTC date
+pub(super) struct Utc {
+    pub(super) year: u64,
+    pub(super) month: u64,
+    pub(super) day: u64,
+    pub(super) hour: u64,
+    pub(super) minute: u64,
+    pub(super) second: u64,
+    pub(super) micros: u32,
+    /// Days since 1970-01-01
+    pub(super) days: u64,
+}
+
+impl Utc {
+    pub(super) fn new(date: SystemTime) -> Self {
+        let since_epoch = date.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+        let days = secs / 86400;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: secs % 86400 / 3600,
+            minute: secs % 3600 / 60,
+            second: secs % 60,
+            micros: since_epoch.subsec_micros(),
+            days,
+        }
+    }
+}
+
+/// Converts days since 1970-01-01 to a `(year, month, day)` date
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn envelope() -> Envelope {
+        Envelope::new(None, vec!["hei@domain.tld".parse().unwrap()]).unwrap()
+    }
+
+    #[test]
+    fn message_id_names() {
+        let email = b"From: a@b.c\r\nMessage-ID: <123.abc/../x@domain.tld>\r\n\r\nMessage-ID: <no>";
+        assert_eq!(
+            Naming::MessageId.name(&envelope(), email, UNIX_EPOCH),
+            "123.abc_.._x@domain.tld"
+        );
+
+        let email = b"From: a@b.c\r\nmessage-id:\r\n <folded@domain.tld>\r\n\r\nHi";
+        assert_eq!(
+            Naming::MessageId.name(&envelope(), email, UNIX_EPOCH),
+            "folded@domain.tld"
+        );
+
+        let email = b"From: a@b.c\r\n\r\nMessage-ID: <no>";
+        assert_eq!(
+            Naming::MessageId.name(&envelope(), email, UNIX_EPOCH).len(),
+            36
+        );
+    }
+
+    #[test]
+    fn date_names() {
+        let date = UNIX_EPOCH + Duration::from_micros(1_792_161_015_123_456);
+        let name = Naming::Timestamp.name(&envelope(), b"", date);
+        assert!(name.starts_with("20261016T143015.123456Z-"));
+        assert_eq!(name.len(), 24 + 36);
+
+        let name = Naming::Daily.name(&envelope(), b"", date);
+        assert!(name.starts_with("2026/10/16/"));
+        assert_eq!(name.len(), 11 + 36);
+    }
+
+    #[test]
+    fn custom_names() {
+        let naming = Naming::Custom(Arc::new(|envelope, _| envelope.to()[0].to_string()));
+        assert_eq!(naming.name(&envelope(), b"", UNIX_EPOCH), "hei@domain.tld");
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(20742), (2026, 10, 16));
+    }
+}
@@ -0,0 +1,224 @@
+//! Listing and management of the messages written by the file transport
+
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+use super::AsyncFileTransport;
+use super::{maildir, Error, FileTransport, Id, Layout, Naming, MBOX_READ};
+use crate::address::Envelope;
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+use crate::Executor;
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+impl FileTransport {
+    /// Lists the messages written by the transport, with their envelopes
+    ///
+    /// Messages are sorted by id, which is chronological with [`Naming::Timestamp`],
+    /// [`Naming::Daily`] and Maildir. Subdirectories are only searched with
+    /// [`Naming::Daily`] and [`Naming::Custom`]. Messages without a readable envelope,
+    /// for example because they weren't written by the transport, are skipped.
+    pub fn list(&self) -> Result<Vec<(Id, Envelope)>, Error> {
+        Ok(self
+            .ids()?
+            .into_iter()
+            .filter_map(|id| {
+                let envelope = parse_envelope(fs::read(self.envelope_path(&id)))?;
+                Some((id, envelope))
+            })
+            .collect())
+    }
+
+    /// Lists the ids of the messages written by the transport, sorted like [`Self::list`],
+    /// including those without a readable envelope
+    pub(crate) fn ids(&self) -> Result<Vec<Id>, Error> {
+        match self.layout {
+            Layout::Flat => {
+                let mut dirs = vec![self.path.clone()];
+                let mut ids = Vec::new();
+                while let Some(dir) = dirs.pop() {
+                    self.visit(read_dir(&dir)?, &mut dirs, &mut ids);
+                }
+                ids.sort();
+                Ok(ids)
+            }
+            Layout::Maildir => Ok(maildir::list(&self.path)?),
+            Layout::Mbox => Err(Error::Client(MBOX_READ)),
+        }
+    }
+
+    /// Removes a message written by the transport
+    pub fn remove(&self, email_id: &str) -> Result<(), Error> {
+        let (message, envelope) = self.files(email_id)?;
+        fs::remove_file(message)?;
+        ignore_missing(fs::remove_file(envelope))?;
+        Ok(())
+    }
+
+    /// Moves a message written by the transport to another directory
+    ///
+    /// The files keep their path relative to the transport directory, so that a transport
+    /// with the same settings can read the message from `dir`, for example to keep
+    /// failed messages apart. Both directories must be on the same file system.
+    ///
+    /// The envelope is copied before the message is moved, and removed from this directory
+    /// last, so that the message stays readable from one of the directories if the process
+    /// stops in between.
+    pub fn move_to<P: AsRef<Path>>(&self, email_id: &str, dir: P) -> Result<(), Error> {
+        let (message, envelope) = self.files(email_id)?;
+
+        let target = self.relocate(&envelope, dir.as_ref());
+        fs::create_dir_all(target.parent().unwrap())?;
+        ignore_missing(fs::copy(&envelope, target).map(drop))?;
+
+        let target = self.relocate(&message, dir.as_ref());
+        fs::create_dir_all(target.parent().unwrap())?;
+        fs::rename(message, target)?;
+
+        ignore_missing(fs::remove_file(envelope))?;
+        Ok(())
+    }
+
+    /// Returns the message file and envelope file of a message
+    fn files(&self, email_id: &str) -> Result<(PathBuf, PathBuf), Error> {
+        let message = match self.layout {
+            Layout::Flat => self.path(email_id, "eml"),
+            Layout::Maildir => maildir::locate(&self.path, email_id)?,
+            Layout::Mbox => return Err(Error::Client(MBOX_READ)),
+        };
+        Ok((message, self.envelope_path(email_id)))
+    }
+
+    /// Returns the path of a file of the transport directory moved to `dir`
+    fn relocate(&self, file: &Path, dir: &Path) -> PathBuf {
+        dir.join(file.strip_prefix(&self.path).unwrap_or(file))
+    }
+
+    /// Sorts the entries of a directory into the subdirectories to search and the message ids
+    fn visit(&self, entries: Vec<(PathBuf, bool)>, dirs: &mut Vec<PathBuf>, ids: &mut Vec<Id>) {
+        let recursive = matches!(self.naming, Naming::Daily | Naming::Custom(_));
+        for (path, is_dir) in entries {
+            if is_dir {
+                if recursive {
+                    dirs.push(path);
+                }
+            } else if path.extension() == Some(OsStr::new("eml")) {
+                let relative = path
+                    .strip_prefix(&self.path)
+                    .unwrap_or(&path)
+                    .with_extension("");
+                let parts: Option<Vec<&str>> = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_str())
+                    .collect();
+                if let Some(parts) = parts {
+                    ids.push(parts.join("/"));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+impl<E> AsyncFileTransport<E>
+where
+    E: Executor,
+{
+    /// Lists the messages written by the transport, with their envelopes
+    ///
+    /// See [`FileTransport::list`].
+    pub async fn list(&self) -> Result<Vec<(Id, Envelope)>, Error> {
+        let ids = self.ids().await?;
+        let mut messages = Vec::with_capacity(ids.len());
+        for id in ids {
+            let json = E::fs_read(&self.inner.envelope_path(&id)).await;
+            if let Some(envelope) = parse_envelope(json) {
+                messages.push((id, envelope));
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Lists the ids of the messages written by the transport
+    ///
+    /// See [`FileTransport::ids`].
+    pub(crate) async fn ids(&self) -> Result<Vec<Id>, Error> {
+        match self.inner.layout {
+            Layout::Flat => {
+                let mut dirs = vec![self.inner.path.clone()];
+                let mut ids = Vec::new();
+                while let Some(dir) = dirs.pop() {
+                    self.inner
+                        .visit(E::fs_read_dir(&dir).await?, &mut dirs, &mut ids);
+                }
+                ids.sort();
+                Ok(ids)
+            }
+            Layout::Maildir => Ok(maildir::list_async::<E>(&self.inner.path).await?),
+            Layout::Mbox => Err(Error::Client(MBOX_READ)),
+        }
+    }
+
+    /// Removes a message written by the transport
+    pub async fn remove(&self, email_id: &str) -> Result<(), Error> {
+        let (message, envelope) = self.files(email_id).await?;
+        E::fs_remove_file(&message).await?;
+        ignore_missing(E::fs_remove_file(&envelope).await)?;
+        Ok(())
+    }
+
+    /// Moves a message written by the transport to another directory
+    ///
+    /// See [`FileTransport::move_to`].
+    pub async fn move_to<P: AsRef<Path>>(&self, email_id: &str, dir: P) -> Result<(), Error> {
+        let (message, envelope) = self.files(email_id).await?;
+
+        let target = self.inner.relocate(&envelope, dir.as_ref());
+        E::fs_create_dir_all(target.parent().unwrap()).await?;
+        match E::fs_read(&envelope).await {
+            Ok(json) => E::fs_write(&target, &json).await?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let target = self.inner.relocate(&message, dir.as_ref());
+        E::fs_create_dir_all(target.parent().unwrap()).await?;
+        E::fs_rename(&message, &target).await?;
+
+        ignore_missing(E::fs_remove_file(&envelope).await)?;
+        Ok(())
+    }
+
+    async fn files(&self, email_id: &str) -> Result<(PathBuf, PathBuf), Error> {
+        let message = match self.inner.layout {
+            Layout::Flat => self.inner.path(email_id, "eml"),
+            Layout::Maildir => maildir::locate_async::<E>(&self.inner.path, email_id).await?,
+            Layout::Mbox => return Err(Error::Client(MBOX_READ)),
+        };
+        Ok((message, self.inner.envelope_path(email_id)))
+    }
+}
+
+/// Returns the paths of the entries of a directory, and whether they are directories
+pub(super) fn read_dir(path: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
+    fs::read_dir(path)?
+        .map(|entry| {
+            let entry = entry?;
+            Ok((entry.path(), entry.file_type()?.is_dir()))
+        })
+        .collect()
+}
+
+/// Parses the envelope file of a listed message, if it could be read
+fn parse_envelope(json: io::Result<Vec<u8>>) -> Option<Envelope> {
+    serde_json::from_slice(&json.ok()?).ok()
+}
+
+/// Ignores the error of an operation on a file which doesn't exist
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
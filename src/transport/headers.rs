@@ -0,0 +1,84 @@
+//! Reading the headers of formatted messages
+
+/// Returns the headers of a formatted message, in order
+///
+/// Lines can end with CRLF or LF, and folded values are unfolded. The body is not read.
+pub(crate) fn headers(email: &[u8]) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let lines = email
+        .split(|&b| b == b'\n')
+        .map(|line| match line.split_last() {
+            Some((b'\r', line)) => line,
+            _ => line,
+        })
+        .take_while(|line| !line.is_empty())
+        .map(String::from_utf8_lossy);
+    for line in lines {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some((_, value)) = headers.last_mut() {
+                if !value.is_empty() {
+                    value.push(' ');
+                }
+                value.push_str(line.trim());
+            }
+        } else if let Some(colon) = line.find(':') {
+            headers.push((
+                line[..colon].trim().to_string(),
+                line[colon + 1..].trim().to_string(),
+            ));
+        }
+    }
+    headers
+}
+
+/// Returns the value of the first header with the name, ignoring case
+#[cfg(any(feature = "file-transport", feature = "tracing", test))]
+pub(crate) fn header(email: &[u8], name: &str) -> Option<String> {
+    headers(email)
+        .into_iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{header, headers};
+
+    #[test]
+    fn read_headers() {
+        assert_eq!(
+            headers(b"From: a@example.com\r\nSubject: a\r\n  folded\tsubject\r\n\r\nX: body"),
+            vec![
+                ("From".to_string(), "a@example.com".to_string()),
+                ("Subject".to_string(), "a folded\tsubject".to_string()),
+            ]
+        );
+        assert_eq!(
+            headers(b"message-id:\n <1@example.com>\nnot a header\n\n"),
+            vec![("message-id".to_string(), "<1@example.com>".to_string())]
+        );
+    }
+
+    #[test]
+    fn read_header() {
+        assert_eq!(
+            header(
+                b"From: a@example.com\r\nMessage-ID: <1@example.com>\r\n\r\nbody",
+                "Message-ID"
+            )
+            .as_deref(),
+            Some("<1@example.com>")
+        );
+        assert_eq!(
+            header(
+                b"Subject: test\r\n\r\nMessage-ID: <3@example.com>\r\n",
+                "Message-ID"
+            ),
+            None
+        );
+        assert_eq!(
+            header("Subject: \u{e9}t\u{e9}".as_bytes(), "subject").as_deref(),
+            Some("\u{e9}t\u{e9}")
+        );
+    }
+}
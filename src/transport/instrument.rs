@@ -0,0 +1,127 @@
+//! Tracing spans and metrics shared by the transports
+
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+use std::future::Future;
+use std::{fmt::Display, time::Instant};
+
+#[cfg(feature = "tracing")]
+use super::headers;
+use super::metrics::{self, FailureClass};
+use crate::Envelope;
+
+/// Runs the expression inside a `tracing` span, when the `tracing` feature is enabled
+///
+/// Takes the expression followed by the arguments of `tracing::debug_span!`.
+/// Followed by `=> await`, the expression is instrumented as a future.
+#[cfg(feature = "smtp-transport")]
+macro_rules! in_span {
+    ($future:expr => await, $($span:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument($future, tracing::debug_span!($($span)+));
+        #[cfg(not(feature = "tracing"))]
+        let future = $future;
+        future.await
+    }};
+    ($body:expr, $($span:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!($($span)+);
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+        $body
+    }};
+}
+
+/// Span, duration and metrics of a `send_raw` call
+pub(crate) struct SendScope {
+    transport: &'static str,
+    start: Instant,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+// `Span::record` takes the value by reference before tracing 0.1.36
+#[allow(clippy::needless_borrows_for_generic_args)]
+impl SendScope {
+    /// Starts a send through the given kind of transport
+    pub(crate) fn new(transport: &'static str, envelope: &Envelope, email: &[u8]) -> Self {
+        #[cfg(not(feature = "tracing"))]
+        let _ = (envelope, email);
+        Self {
+            transport,
+            start: Instant::now(),
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "send",
+                transport,
+                message_id = headers::header(email, "Message-ID")
+                    .unwrap_or_default()
+                    .as_str(),
+                recipients = envelope.to().len(),
+                relay = tracing::field::Empty,
+                code = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+                error = tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// Records the server the message is sent to
+    #[cfg(feature = "smtp-transport")]
+    pub(crate) fn record_relay(&self, relay: &str) {
+        #[cfg(feature = "tracing")]
+        self.span.record("relay", &relay);
+        #[cfg(not(feature = "tracing"))]
+        let _ = relay;
+    }
+
+    /// Records the reply code of the server
+    #[cfg(feature = "smtp-transport")]
+    pub(crate) fn record_code<C: Display>(&self, code: C) {
+        #[cfg(feature = "tracing")]
+        self.span.record("code", &tracing::field::display(code));
+        #[cfg(not(feature = "tracing"))]
+        let _ = code;
+    }
+
+    /// Runs the send inside the span
+    pub(crate) fn in_scope<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        #[cfg(feature = "tracing")]
+        let _enter = self.span.enter();
+        f()
+    }
+
+    /// Runs the send inside the span
+    #[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+    pub(crate) async fn run<F: Future>(&self, future: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument(future, self.span.clone());
+        future.await
+    }
+
+    /// Records the duration and outcome of the send
+    pub(crate) fn finish<T, E: FailureClass + Display>(self, result: &Result<T, E>) {
+        let duration = self.start.elapsed();
+        let labels = [("transport", self.transport)];
+        metrics::increment_counter(metrics::SENDS, &labels);
+        metrics::record_histogram(metrics::SEND_DURATION, duration.as_secs_f64(), &labels);
+
+        #[cfg(feature = "tracing")]
+        self.span
+            .record("duration_ms", &(duration.as_millis() as u64));
+
+        if let Err(err) = result {
+            let class = err.failure_class();
+            metrics::increment_counter(
+                metrics::SEND_FAILURES,
+                &[("transport", self.transport), ("class", class)],
+            );
+
+            #[cfg(feature = "tracing")]
+            {
+                self.span.record("error", &class);
+                let _enter = self.span.enter();
+                tracing::debug!("send failed: {}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Metrics hook for the transports
+//!
+//! Install a [`Recorder`] once at startup with [`set_recorder`], and every transport
+//! reports its activity to it. The recorder maps the counters and histograms to a metrics
+//! system, like a Prometheus registry.
+//!
+//! | Name | Kind | Labels |
+//! |------|------|--------|
+//! | [`SENDS`] | counter | `transport` |
+//! | [`SEND_FAILURES`] | counter | `transport`, `class` |
+//! | [`SEND_DURATION`] | histogram, in seconds | `transport` |
+//! | [`POOL_WAIT`] | histogram, in seconds | `transport` |
+//!
+//! `transport` is one of `smtp`, `mx`, `file`, `sendmail` and `stub`. Transports made of
+//! other transports, like the failover transport, only report through them.
+//!
+//! ```rust
+//! use lettre::transport::metrics::{self, Recorder};
+//!
+//! struct PrintRecorder;
+//!
+//! impl Recorder for PrintRecorder {
+//!     fn increment_counter(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+//!         println!("{} {:?} +1", name, labels);
+//!     }
+//!
+//!     fn record_histogram(&self, name: &'static str, value: f64, labels: &[(&'static str, &str)]) {
+//!         println!("{} {:?} {}", name, labels, value);
+//!     }
+//! }
+//!
+//! metrics::set_recorder(PrintRecorder).unwrap();
+//! ```
+
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+};
+
+use once_cell::sync::OnceCell;
+
+/// Number of messages submitted to a transport
+pub const SENDS: &str = "lettre_sends_total";
+/// Number of messages a transport failed to send, by failure class
+///
+/// The `class` label is one of:
+///
+/// * `transient` and `permanent` for 4xx and 5xx SMTP replies
+/// * `network` for connection and DNS errors
+/// * `tls` for TLS negotiation and certificate errors
+/// * `protocol` for unexpected server replies
+/// * `pool` for connection pool errors
+/// * `io` for file system and process errors
+/// * `client` for the other errors
+pub const SEND_FAILURES: &str = "lettre_send_failures_total";
+/// Time taken by a transport to send a message
+pub const SEND_DURATION: &str = "lettre_send_duration_seconds";
+/// Time spent waiting for a connection from the SMTP connection pool
+pub const POOL_WAIT: &str = "lettre_pool_wait_seconds";
+
+static RECORDER: OnceCell<Box<dyn Recorder>> = OnceCell::new();
+
+/// Receives the metrics of all the transports
+pub trait Recorder: Send + Sync + 'static {
+    /// Increments a counter by one
+    fn increment_counter(&self, name: &'static str, labels: &[(&'static str, &str)]);
+
+    /// Records a value in a histogram
+    fn record_histogram(&self, name: &'static str, value: f64, labels: &[(&'static str, &str)]);
+}
+
+/// Error returned by [`set_recorder`] when a recorder was already installed
+#[derive(Debug, Clone, Copy)]
+pub struct SetRecorderError(());
+
+impl Display for SetRecorderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("a metrics recorder is already installed")
+    }
+}
+
+impl StdError for SetRecorderError {}
+
+/// Installs the global metrics recorder
+///
+/// The recorder can only be installed once.
+pub fn set_recorder<R: Recorder>(recorder: R) -> Result<(), SetRecorderError> {
+    RECORDER
+        .set(Box::new(recorder))
+        .map_err(|_| SetRecorderError(()))
+}
+
+pub(crate) fn increment_counter(name: &'static str, labels: &[(&'static str, &str)]) {
+    if let Some(recorder) = RECORDER.get() {
+        recorder.increment_counter(name, labels);
+    }
+}
+
+pub(crate) fn record_histogram(name: &'static str, value: f64, labels: &[(&'static str, &str)]) {
+    if let Some(recorder) = RECORDER.get() {
+        recorder.record_histogram(name, value, labels);
+    }
+}
+
+/// Classification of transport errors for the [`SEND_FAILURES`] counter
+///
+/// Also used by the queue workers to recognize the permanent failures.
+pub trait FailureClass {
+    /// Returns the class of the error, one of those listed on [`SEND_FAILURES`]
+    fn failure_class(&self) -> &'static str;
+}
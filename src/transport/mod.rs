@@ -13,8 +13,10 @@
 //!   the SMTP transport.
 //! * The `FileTransport` creates a file containing the email content to be sent. It can be used
 //!   for debugging or if you want to keep all sent emails.
-//! * The `StubTransport` is useful for debugging, and only prints the content of the email in the
-//!   logs.
+//! * The `QueueTransport` stores the emails durably on disk, and a worker sends them later
+//!   through another transport, retrying after failures.
+//! * The `StubTransport` is useful for testing, and only records the emails it is given for
+//!   later inspection.
 
 #[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
 use async_trait::async_trait;
@@ -36,9 +38,23 @@ use crate::Envelope;
 #[cfg(feature = "builder")]
 use crate::Message;
 
+#[cfg(any(
+    feature = "builder",
+    feature = "file-transport",
+    feature = "tracing",
+    test
+))]
+mod headers;
+#[macro_use]
+mod instrument;
+
 #[cfg(feature = "file-transport")]
 #[cfg_attr(docsrs, doc(cfg(feature = "file-transport")))]
 pub mod file;
+pub mod metrics;
+#[cfg(feature = "queue-transport")]
+#[cfg_attr(docsrs, doc(cfg(feature = "queue-transport")))]
+pub mod queue;
 #[cfg(feature = "sendmail-transport")]
 #[cfg_attr(docsrs, doc(cfg(feature = "sendmail-transport")))]
 pub mod sendmail;
@@ -0,0 +1,252 @@
+//! The queue transport stores the messages durably on disk and returns immediately, leaving
+//! their sending to a worker. Queued messages survive crashes of the process and outages of
+//! the relay.
+//!
+//! * Messages are written like the file transport does in Maildir mode: synced to disk,
+//!   moved into place atomically, and with their envelope in a JSON file. On Unix, the
+//!   directories are synced too, so that the moves survive a power loss.
+//! * A [`QueueWorker`] sends the queued messages through another transport, and only removes
+//!   a message from the queue once the inner transport accepted it. Messages are sent at
+//!   least once, and may be sent twice if the process stops in between.
+//! * Failed messages are retried later, with delays doubling after each failure
+//! * Messages failing permanently, or too many times, are moved to a dead-letter directory,
+//!   along with those which can't be read
+//!
+//! The number of failed attempts of each message is stored on disk, but the time of the next
+//! retry is kept in memory, so after a restart queued messages are tried again immediately.
+//! Only one worker should drain a queue at a time.
+//!
+//! #### Queue Transport
+//!
+//! ```rust
+//! # #[cfg(feature = "builder")]
+//! # {
+//! use lettre::{
+//!     transport::{queue::QueueTransport, stub::StubTransport},
+//!     Message, Transport,
+//! };
+//! use std::env::temp_dir;
+//!
+//! # use std::error::Error;
+//! # fn main() -> Result<(), Box<dyn Error>> {
+//! let queue = QueueTransport::new(temp_dir().join("lettre-queue"));
+//! let email = Message::builder()
+//!     .from("NoBody <nobody@domain.tld>".parse()?)
+//!     .reply_to("Yuin <yuin@domain.tld>".parse()?)
+//!     .to("Hei <hei@domain.tld>".parse()?)
+//!     .subject("Happy new year")
+//!     .body(String::from("Be happy!"))?;
+//!
+//! // Returns as soon as the message is on disk
+//! let id = queue.send(&email)?;
+//!
+//! // Usually in another thread or process, with the SMTP transport
+//! let mut worker = queue.worker(StubTransport::new_ok());
+//! let drained = worker.run_once()?;
+//! assert_eq!(drained.sent(), 1);
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+//!
+//! #### Dead letters
+//!
+//! Messages failing with a permanent error, as told by its
+//! [`FailureClass`](crate::transport::metrics::FailureClass) or by
+//! [`QueueWorker::permanent_failures`], are moved to the dead-letter directory right away,
+//! and the others after [`DEFAULT_MAX_ATTEMPTS`] failures. Dead letters can be listed and read with
+//! [`QueueTransport::dead_letters`], and put back into the queue with
+//! [`QueueTransport::requeue`].
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "smtp-transport")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use lettre::{transport::queue::QueueTransport, SmtpTransport};
+//! use std::time::Duration;
+//!
+//! let queue = QueueTransport::new("/var/spool/my-app");
+//! let mut worker = queue
+//!     .worker(SmtpTransport::relay("smtp.example.com")?.build())
+//!     .retry_delay(Duration::from_secs(30), Duration::from_secs(3600))
+//!     .max_attempts(20);
+//! std::thread::spawn(move || worker.run(Duration::from_secs(5)));
+//!
+//! for (id, envelope) in queue.dead_letters().list()? {
+//!     println!("{} to {:?} could not be sent", id, envelope.to());
+//! }
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "smtp-transport"))]
+//! # fn main() {}
+//! ```
+
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+pub use self::worker::AsyncQueueWorker;
+pub use self::worker::{
+    Drained, QueueWorker, DEFAULT_MAX_ATTEMPTS, DEFAULT_MAX_RETRY_DELAY, DEFAULT_RETRY_DELAY,
+};
+use crate::{
+    address::Envelope,
+    transport::{
+        file::{Error, FileTransport},
+        metrics::FailureClass,
+    },
+    Transport,
+};
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+use crate::{transport::file::AsyncFileTransport, AsyncTransport, Executor};
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+
+mod worker;
+
+/// Subdirectory of the queued messages
+const QUEUE: &str = "queue";
+/// Subdirectory of the messages which could not be sent
+const DEAD_LETTER: &str = "dead-letter";
+/// Subdirectory of [`QUEUE`] with the number of failed attempts of each message
+const ATTEMPTS: &str = "attempts";
+
+/// Writes the messages to a durable queue, to be sent by a [`QueueWorker`]
+///
+/// Sending returns the id of the queued message.
+#[derive(Debug)]
+pub struct QueueTransport {
+    path: PathBuf,
+    queue: FileTransport,
+}
+
+impl QueueTransport {
+    /// Creates a queue in the given directory
+    ///
+    /// Messages are queued in its `queue` subdirectory, and moved to its `dead-letter`
+    /// subdirectory when they can't be sent. Both are created when missing.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let path = PathBuf::from(path.as_ref());
+        Self {
+            queue: queue(&path),
+            path,
+        }
+    }
+
+    /// Lists the queued messages, with their envelopes, oldest first
+    pub fn pending(&self) -> Result<Vec<(String, Envelope)>, Error> {
+        self.queue.list()
+    }
+
+    /// Returns a transport giving access to the messages which could not be sent
+    ///
+    /// They can be listed, read and removed with it.
+    pub fn dead_letters(&self) -> FileTransport {
+        FileTransport::with_envelope(self.path.join(DEAD_LETTER)).maildir()
+    }
+
+    /// Moves a dead letter back into the queue, to be sent again
+    pub fn requeue(&self, email_id: &str) -> Result<(), Error> {
+        self.dead_letters().move_to(email_id, self.path.join(QUEUE))
+    }
+
+    /// Creates a worker sending the queued messages through `transport`
+    pub fn worker<T>(&self, transport: T) -> QueueWorker<T>
+    where
+        T: Transport,
+        T::Error: FailureClass,
+    {
+        QueueWorker::new(
+            queue(&self.path),
+            self.path.join(DEAD_LETTER),
+            &self.path.join(QUEUE).join(ATTEMPTS),
+            transport,
+        )
+    }
+}
+
+impl Transport for QueueTransport {
+    type Ok = String;
+    type Error = Error;
+
+    /// Queues a message, returning once it is synced to disk, along with the directory
+    /// entries on Unix
+    fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
+        self.queue.send_raw(envelope, email)
+    }
+}
+
+/// Asynchronously writes the messages to a durable queue, to be sent by an
+/// [`AsyncQueueWorker`]
+#[derive(Debug)]
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+pub struct AsyncQueueTransport<E: Executor> {
+    path: PathBuf,
+    queue: AsyncFileTransport<E>,
+}
+
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+impl<E> AsyncQueueTransport<E>
+where
+    E: Executor,
+{
+    /// Creates a queue in the given directory
+    ///
+    /// See [`QueueTransport::new`].
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let path = PathBuf::from(path.as_ref());
+        Self {
+            queue: AsyncFileTransport::with_envelope(path.join(QUEUE)).maildir(),
+            path,
+        }
+    }
+
+    /// Lists the queued messages, with their envelopes, oldest first
+    pub async fn pending(&self) -> Result<Vec<(String, Envelope)>, Error> {
+        self.queue.list().await
+    }
+
+    /// Returns a transport giving access to the messages which could not be sent
+    pub fn dead_letters(&self) -> AsyncFileTransport<E> {
+        AsyncFileTransport::with_envelope(self.path.join(DEAD_LETTER)).maildir()
+    }
+
+    /// Moves a dead letter back into the queue, to be sent again
+    pub async fn requeue(&self, email_id: &str) -> Result<(), Error> {
+        self.dead_letters()
+            .move_to(email_id, self.path.join(QUEUE))
+            .await
+    }
+
+    /// Creates a worker sending the queued messages through `transport`
+    pub fn worker<T>(&self, transport: T) -> AsyncQueueWorker<T, E>
+    where
+        T: AsyncTransport,
+        T::Error: FailureClass,
+    {
+        AsyncQueueWorker::new(
+            AsyncFileTransport::with_envelope(self.path.join(QUEUE)).maildir(),
+            self.path.join(DEAD_LETTER),
+            &self.path.join(QUEUE).join(ATTEMPTS),
+            transport,
+        )
+    }
+}
+
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+#[async_trait]
+impl<E> AsyncTransport for AsyncQueueTransport<E>
+where
+    E: Executor,
+{
+    type Ok = String;
+    type Error = Error;
+
+    /// Queues a message, returning once it is synced to disk, along with the directory
+    /// entries on Unix
+    async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
+        self.queue.send_raw(envelope, email).await
+    }
+}
+
+/// Returns a file transport to the queue of the given directory
+fn queue(path: &Path) -> FileTransport {
+    FileTransport::with_envelope(path.join(QUEUE)).maildir()
+}
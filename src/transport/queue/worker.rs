@@ -0,0 +1,568 @@
+//! Draining of the queue through another transport
+
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+use crate::{transport::file::AsyncFileTransport, AsyncTransport, Executor};
+use crate::{
+    transport::{
+        file::{Error, FileTransport},
+        metrics::FailureClass,
+    },
+    Transport,
+};
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+use std::marker::PhantomData;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Default delay before retrying a message which failed once
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// Default maximum delay between two attempts to send a message
+pub const DEFAULT_MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+/// Default number of attempts after which a message is moved to the dead-letter directory
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+
+/// Function telling if an error of the inner transport is permanent
+type Classifier<E> = Box<dyn Fn(&E) -> bool + Send + Sync>;
+
+/// Outcome of a pass of a worker over the queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Drained {
+    sent: usize,
+    retried: usize,
+    dead_lettered: usize,
+    waiting: usize,
+}
+
+impl Drained {
+    /// Number of messages accepted by the inner transport, and removed from the queue
+    pub fn sent(&self) -> usize {
+        self.sent
+    }
+
+    /// Number of messages which failed, and will be retried later
+    pub fn retried(&self) -> usize {
+        self.retried
+    }
+
+    /// Number of messages which failed, and were moved to the dead-letter directory
+    pub fn dead_lettered(&self) -> usize {
+        self.dead_lettered
+    }
+
+    /// Number of messages left in the queue until their next retry
+    pub fn waiting(&self) -> usize {
+        self.waiting
+    }
+}
+
+/// Attempts made to send a message which failed
+///
+/// The number of attempts is also stored on disk, see [`Attempts`].
+struct Retry {
+    attempts: u32,
+    /// Time of the next attempt, `None` when too far in the future to be represented
+    next: Option<Instant>,
+}
+
+/// Retry schedule of the queued messages
+struct Schedule {
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    max_attempts: u32,
+    retries: HashMap<String, Retry>,
+}
+
+impl Schedule {
+    fn new() -> Self {
+        Self {
+            retry_delay: DEFAULT_RETRY_DELAY,
+            max_retry_delay: DEFAULT_MAX_RETRY_DELAY,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retries: HashMap::new(),
+        }
+    }
+
+    /// Forgets the messages which left the queue
+    fn retain(&mut self, queued: &[String]) {
+        self.retries.retain(|id, _| queued.contains(id));
+    }
+
+    /// Returns the number of failed attempts of a message known to the schedule
+    fn attempts(&self, email_id: &str) -> Option<u32> {
+        self.retries.get(email_id).map(|retry| retry.attempts)
+    }
+
+    /// Tells if a message should be sent now
+    fn is_due(&self, email_id: &str, now: Instant) -> bool {
+        match self.retries.get(email_id) {
+            Some(retry) => matches!(retry.next, Some(next) if next <= now),
+            None => true,
+        }
+    }
+
+    /// Records a successful send
+    fn sent(&mut self, email_id: &str, drained: &mut Drained) {
+        self.retries.remove(email_id);
+        drained.sent += 1;
+    }
+
+    /// Records a failed send, which was attempt number `attempts`
+    ///
+    /// Returns `true` when the message should be moved to the dead-letter directory.
+    fn failed(
+        &mut self,
+        email_id: &str,
+        attempts: u32,
+        permanent: bool,
+        drained: &mut Drained,
+    ) -> bool {
+        if permanent || attempts >= self.max_attempts {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                "queued message {} failed after {} attempts, moving it to the dead letters",
+                email_id,
+                attempts
+            );
+            self.retries.remove(email_id);
+            drained.dead_lettered += 1;
+            true
+        } else {
+            let next = Instant::now().checked_add(self.delay(attempts));
+            self.retries
+                .insert(email_id.to_string(), Retry { attempts, next });
+            drained.retried += 1;
+            false
+        }
+    }
+
+    /// Returns the delay before the next attempt, after `attempts` failures
+    fn delay(&self, attempts: u32) -> Duration {
+        1u32.checked_shl(attempts.saturating_sub(1))
+            .and_then(|factor| self.retry_delay.checked_mul(factor))
+            .map_or(self.max_retry_delay, |delay| {
+                delay.min(self.max_retry_delay)
+            })
+    }
+}
+
+/// Numbers of failed attempts stored on disk, one file per message
+///
+/// They let the messages which keep failing across restarts reach the dead-letter directory.
+struct Attempts {
+    dir: PathBuf,
+}
+
+impl Attempts {
+    fn path(&self, email_id: &str) -> PathBuf {
+        self.dir.join(email_id)
+    }
+
+    /// Reads the number of failed attempts of a message, zero when none was stored
+    fn read(&self, email_id: &str) -> u32 {
+        parse_attempts(fs::read(self.path(email_id)))
+    }
+
+    fn write(&self, email_id: &str, attempts: u32) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(email_id), attempts.to_string())
+    }
+
+    fn remove(&self, email_id: &str) -> io::Result<()> {
+        ignore_missing(fs::remove_file(self.path(email_id)))
+    }
+
+    #[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+    async fn read_async<E: Executor>(&self, email_id: &str) -> u32 {
+        parse_attempts(E::fs_read(&self.path(email_id)).await)
+    }
+
+    #[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+    async fn write_async<E: Executor>(&self, email_id: &str, attempts: u32) -> io::Result<()> {
+        E::fs_create_dir_all(&self.dir).await?;
+        E::fs_write(&self.path(email_id), attempts.to_string().as_bytes()).await
+    }
+
+    #[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+    async fn remove_async<E: Executor>(&self, email_id: &str) -> io::Result<()> {
+        ignore_missing(E::fs_remove_file(&self.path(email_id)).await)
+    }
+}
+
+/// Parses a stored number of attempts, an unreadable one counting as none
+fn parse_attempts(contents: io::Result<Vec<u8>>) -> u32 {
+    contents
+        .ok()
+        .and_then(|contents| String::from_utf8(contents).ok())
+        .and_then(|attempts| attempts.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Sends the messages of a [`QueueTransport`](super::QueueTransport) through another
+/// transport
+///
+/// Instances are created with [`QueueTransport::worker`](super::QueueTransport::worker).
+#[allow(missing_debug_implementations)]
+pub struct QueueWorker<T: Transport> {
+    queue: FileTransport,
+    dead_letter: PathBuf,
+    attempts: Attempts,
+    transport: T,
+    permanent: Classifier<T::Error>,
+    schedule: Schedule,
+}
+
+impl<T: Transport> QueueWorker<T> {
+    pub(super) fn new(
+        queue: FileTransport,
+        dead_letter: PathBuf,
+        attempts: &Path,
+        transport: T,
+    ) -> Self
+    where
+        T::Error: FailureClass,
+    {
+        Self {
+            queue,
+            dead_letter,
+            attempts: Attempts {
+                dir: attempts.to_path_buf(),
+            },
+            transport,
+            permanent: Box::new(|err: &T::Error| err.failure_class() == "permanent"),
+            schedule: Schedule::new(),
+        }
+    }
+
+    /// Set the function telling if an error of the inner transport is permanent
+    ///
+    /// Messages failing with a permanent error are moved to the dead-letter directory
+    /// without being retried. Defaults to the errors whose [`FailureClass`] is `permanent`,
+    /// like the 5xx SMTP replies.
+    pub fn permanent_failures<F>(mut self, permanent: F) -> Self
+    where
+        F: Fn(&T::Error) -> bool + Send + Sync + 'static,
+    {
+        self.permanent = Box::new(permanent);
+        self
+    }
+
+    /// Set the delay before retrying a failed message, doubled after each failure up to `max`
+    ///
+    /// Defaults to 1 minute, up to 1 hour
+    pub fn retry_delay(mut self, initial: Duration, max: Duration) -> Self {
+        self.schedule.retry_delay = initial;
+        self.schedule.max_retry_delay = max;
+        self
+    }
+
+    /// Set the number of failed attempts after which a message is moved to the dead-letter
+    /// directory
+    ///
+    /// Defaults to 10
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.schedule.max_attempts = max_attempts;
+        self
+    }
+
+    /// Returns the inner transport
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Sends the queued messages which are due, oldest first
+    ///
+    /// Only fails when the queue can't be listed or modified. Messages which can't be read,
+    /// for example because their envelope is missing, are moved to the dead-letter directory,
+    /// and the errors of the inner transport are handled by scheduling a retry or moving the
+    /// message to the dead-letter directory.
+    pub fn run_once(&mut self) -> Result<Drained, Error> {
+        let queued = self.queue.ids()?;
+        self.schedule.retain(&queued);
+
+        let mut drained = Drained::default();
+        let now = Instant::now();
+        for email_id in queued {
+            if !self.schedule.is_due(&email_id, now) {
+                drained.waiting += 1;
+                continue;
+            }
+
+            let (envelope, email) = match self.queue.read(&email_id) {
+                Ok(message) => message,
+                Err(_err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        "queued message {} can't be read, moving it to the dead letters: {}",
+                        email_id,
+                        _err
+                    );
+                    self.dead_letter(&email_id)?;
+                    drained.dead_lettered += 1;
+                    continue;
+                }
+            };
+            match self.transport.send_raw(&envelope, &email) {
+                Ok(_) => {
+                    // Removing after the inner transport accepted the message makes it
+                    // sent at least once
+                    self.queue.remove(&email_id)?;
+                    self.attempts.remove(&email_id)?;
+                    self.schedule.sent(&email_id, &mut drained);
+                }
+                Err(err) => {
+                    let permanent = (self.permanent)(&err);
+                    let attempts = match self.schedule.attempts(&email_id) {
+                        Some(attempts) => attempts,
+                        None => self.attempts.read(&email_id),
+                    }
+                    .saturating_add(1);
+                    if self
+                        .schedule
+                        .failed(&email_id, attempts, permanent, &mut drained)
+                    {
+                        self.dead_letter(&email_id)?;
+                    } else {
+                        self.attempts.write(&email_id, attempts)?;
+                    }
+                }
+            }
+        }
+        Ok(drained)
+    }
+
+    /// Moves a message to the dead-letter directory, forgetting its attempts so that it
+    /// starts over when requeued
+    fn dead_letter(&self, email_id: &str) -> Result<(), Error> {
+        self.queue.move_to(email_id, &self.dead_letter)?;
+        self.attempts.remove(email_id)?;
+        Ok(())
+    }
+
+    /// Sends the queued messages as they become due, checking the queue every `interval`
+    ///
+    /// Only returns when the queue can't be listed or modified.
+    pub fn run(&mut self, interval: Duration) -> Result<(), Error> {
+        loop {
+            self.run_once()?;
+            thread::sleep(interval);
+        }
+    }
+}
+
+/// Asynchronously sends the messages of an
+/// [`AsyncQueueTransport`](super::AsyncQueueTransport) through another transport
+///
+/// Instances are created with
+/// [`AsyncQueueTransport::worker`](super::AsyncQueueTransport::worker).
+#[allow(missing_debug_implementations)]
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+pub struct AsyncQueueWorker<T: AsyncTransport, E: Executor> {
+    queue: AsyncFileTransport<E>,
+    dead_letter: PathBuf,
+    attempts: Attempts,
+    transport: T,
+    permanent: Classifier<T::Error>,
+    schedule: Schedule,
+    marker_: PhantomData<E>,
+}
+
+#[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
+impl<T, E> AsyncQueueWorker<T, E>
+where
+    T: AsyncTransport,
+    E: Executor,
+{
+    pub(super) fn new(
+        queue: AsyncFileTransport<E>,
+        dead_letter: PathBuf,
+        attempts: &Path,
+        transport: T,
+    ) -> Self
+    where
+        T::Error: FailureClass,
+    {
+        Self {
+            queue,
+            dead_letter,
+            attempts: Attempts {
+                dir: attempts.to_path_buf(),
+            },
+            transport,
+            permanent: Box::new(|err: &T::Error| err.failure_class() == "permanent"),
+            schedule: Schedule::new(),
+            marker_: PhantomData,
+        }
+    }
+
+    /// Set the function telling if an error of the inner transport is permanent
+    ///
+    /// See [`QueueWorker::permanent_failures`].
+    pub fn permanent_failures<F>(mut self, permanent: F) -> Self
+    where
+        F: Fn(&T::Error) -> bool + Send + Sync + 'static,
+    {
+        self.permanent = Box::new(permanent);
+        self
+    }
+
+    /// Set the delay before retrying a failed message, doubled after each failure up to `max`
+    ///
+    /// Defaults to 1 minute, up to 1 hour
+    pub fn retry_delay(mut self, initial: Duration, max: Duration) -> Self {
+        self.schedule.retry_delay = initial;
+        self.schedule.max_retry_delay = max;
+        self
+    }
+
+    /// Set the number of failed attempts after which a message is moved to the dead-letter
+    /// directory
+    ///
+    /// Defaults to 10
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.schedule.max_attempts = max_attempts;
+        self
+    }
+
+    /// Returns the inner transport
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Sends the queued messages which are due, oldest first
+    ///
+    /// See [`QueueWorker::run_once`].
+    pub async fn run_once(&mut self) -> Result<Drained, Error> {
+        let queued = self.queue.ids().await?;
+        self.schedule.retain(&queued);
+
+        let mut drained = Drained::default();
+        let now = Instant::now();
+        for email_id in queued {
+            if !self.schedule.is_due(&email_id, now) {
+                drained.waiting += 1;
+                continue;
+            }
+
+            let (envelope, email) = match self.queue.read(&email_id).await {
+                Ok(message) => message,
+                Err(_err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        "queued message {} can't be read, moving it to the dead letters: {}",
+                        email_id,
+                        _err
+                    );
+                    self.dead_letter(&email_id).await?;
+                    drained.dead_lettered += 1;
+                    continue;
+                }
+            };
+            match self.transport.send_raw(&envelope, &email).await {
+                Ok(_) => {
+                    self.queue.remove(&email_id).await?;
+                    self.attempts.remove_async::<E>(&email_id).await?;
+                    self.schedule.sent(&email_id, &mut drained);
+                }
+                Err(err) => {
+                    let permanent = (self.permanent)(&err);
+                    let attempts = match self.schedule.attempts(&email_id) {
+                        Some(attempts) => attempts,
+                        None => self.attempts.read_async::<E>(&email_id).await,
+                    }
+                    .saturating_add(1);
+                    if self
+                        .schedule
+                        .failed(&email_id, attempts, permanent, &mut drained)
+                    {
+                        self.dead_letter(&email_id).await?;
+                    } else {
+                        self.attempts.write_async::<E>(&email_id, attempts).await?;
+                    }
+                }
+            }
+        }
+        Ok(drained)
+    }
+
+    /// Moves a message to the dead-letter directory
+    ///
+    /// See [`QueueWorker::dead_letter`].
+    async fn dead_letter(&self, email_id: &str) -> Result<(), Error> {
+        self.queue.move_to(email_id, &self.dead_letter).await?;
+        self.attempts.remove_async::<E>(email_id).await?;
+        Ok(())
+    }
+
+    /// Sends the queued messages as they become due, checking the queue every `interval`
+    ///
+    /// Only returns when the queue can't be listed or modified.
+    pub async fn run(&mut self, interval: Duration) -> Result<(), Error> {
+        loop {
+            self.run_once().await?;
+            E::sleep(interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn retry_delays() {
+        let mut schedule = Schedule::new();
+        assert_eq!(schedule.delay(1), Duration::from_secs(60));
+        assert_eq!(schedule.delay(2), Duration::from_secs(120));
+        assert_eq!(schedule.delay(6), Duration::from_secs(1920));
+        assert_eq!(schedule.delay(7), Duration::from_secs(3600));
+        assert_eq!(schedule.delay(40), Duration::from_secs(3600));
+
+        schedule.retry_delay = Duration::from_secs(u64::MAX / 2);
+        schedule.max_retry_delay = Duration::from_secs(u64::MAX);
+        assert_eq!(schedule.delay(3), Duration::from_secs(u64::MAX));
+    }
+
+    #[test]
+    fn stored_attempts() {
+        assert_eq!(parse_attempts(Ok(b"3".to_vec())), 3);
+        assert_eq!(parse_attempts(Ok(b"3\n".to_vec())), 3);
+        assert_eq!(parse_attempts(Ok(b"x".to_vec())), 0);
+        assert_eq!(
+            parse_attempts(Err(io::Error::from(io::ErrorKind::NotFound))),
+            0
+        );
+    }
+
+    #[test]
+    fn schedule_failures() {
+        let mut schedule = Schedule::new();
+        schedule.max_attempts = 3;
+        let mut drained = Drained::default();
+        let now = Instant::now();
+
+        assert!(!schedule.failed("a", 1, false, &mut drained));
+        assert!(!schedule.is_due("a", now));
+        assert!(schedule.is_due("b", now));
+        assert_eq!(schedule.attempts("a"), Some(1));
+        assert!(!schedule.failed("a", 2, false, &mut drained));
+        assert!(schedule.failed("a", 3, false, &mut drained));
+        assert!(schedule.is_due("a", now));
+        assert_eq!(schedule.attempts("a"), None);
+
+        assert!(schedule.failed("b", 1, true, &mut drained));
+        assert_eq!(drained.retried(), 2);
+        assert_eq!(drained.dead_lettered(), 2);
+    }
+}
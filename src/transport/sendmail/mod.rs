@@ -102,7 +102,7 @@ use crate::AsyncStd1Executor;
 use crate::Tokio02Executor;
 #[cfg(feature = "tokio1")]
 use crate::Tokio1Executor;
-use crate::{address::Envelope, Transport};
+use crate::{address::Envelope, transport::instrument::SendScope, Transport};
 #[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
 use crate::{AsyncTransport, Executor};
 #[cfg(any(feature = "async-std1", feature = "tokio02", feature = "tokio1"))]
@@ -262,17 +262,22 @@ impl Transport for SendmailTransport {
     type Error = Error;
 
     fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
-        // Spawn the sendmail command
-        let mut process = self.command(envelope).spawn()?;
-
-        process.stdin.as_mut().unwrap().write_all(email)?;
-        let output = process.wait_with_output()?;
-
-        if output.status.success() {
-            Ok(())
-        } else {
-            Err(error::Error::Client(String::from_utf8(output.stderr)?))
-        }
+        let scope = SendScope::new("sendmail", envelope, email);
+        let result = scope.in_scope(|| -> Result<Self::Ok, Self::Error> {
+            // Spawn the sendmail command
+            let mut process = self.command(envelope).spawn()?;
+
+            process.stdin.as_mut().unwrap().write_all(email)?;
+            let output = process.wait_with_output()?;
+
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(error::Error::Client(String::from_utf8(output.stderr)?))
+            }
+        });
+        scope.finish(&result);
+        result
     }
 }
 
@@ -283,21 +288,28 @@ impl AsyncTransport for AsyncSendmailTransport<AsyncStd1Executor> {
     type Error = Error;
 
     async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
-        use async_std::io::prelude::WriteExt;
-
-        let mut command = self.async_std_command(envelope);
-
-        // Spawn the sendmail command
-        let mut process = command.spawn()?;
-
-        process.stdin.as_mut().unwrap().write_all(&email).await?;
-        let output = process.output().await?;
-
-        if output.status.success() {
-            Ok(())
-        } else {
-            Err(Error::Client(String::from_utf8(output.stderr)?))
-        }
+        let scope = SendScope::new("sendmail", envelope, email);
+        let result: Result<Self::Ok, Self::Error> = scope
+            .run(async {
+                use async_std::io::prelude::WriteExt;
+
+                let mut command = self.async_std_command(envelope);
+
+                // Spawn the sendmail command
+                let mut process = command.spawn()?;
+
+                process.stdin.as_mut().unwrap().write_all(&email).await?;
+                let output = process.output().await?;
+
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    Err(Error::Client(String::from_utf8(output.stderr)?))
+                }
+            })
+            .await;
+        scope.finish(&result);
+        result
     }
 }
 
@@ -308,21 +320,28 @@ impl AsyncTransport for AsyncSendmailTransport<Tokio02Executor> {
     type Error = Error;
 
     async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
-        use tokio02_crate::io::AsyncWriteExt;
-
-        let mut command = self.tokio02_command(envelope);
-
-        // Spawn the sendmail command
-        let mut process = command.spawn()?;
-
-        process.stdin.as_mut().unwrap().write_all(&email).await?;
-        let output = process.wait_with_output().await?;
-
-        if output.status.success() {
-            Ok(())
-        } else {
-            Err(Error::Client(String::from_utf8(output.stderr)?))
-        }
+        let scope = SendScope::new("sendmail", envelope, email);
+        let result: Result<Self::Ok, Self::Error> = scope
+            .run(async {
+                use tokio02_crate::io::AsyncWriteExt;
+
+                let mut command = self.tokio02_command(envelope);
+
+                // Spawn the sendmail command
+                let mut process = command.spawn()?;
+
+                process.stdin.as_mut().unwrap().write_all(&email).await?;
+                let output = process.wait_with_output().await?;
+
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    Err(Error::Client(String::from_utf8(output.stderr)?))
+                }
+            })
+            .await;
+        scope.finish(&result);
+        result
     }
 }
 
@@ -333,20 +352,27 @@ impl AsyncTransport for AsyncSendmailTransport<Tokio1Executor> {
     type Error = Error;
 
     async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
-        use tokio1_crate::io::AsyncWriteExt;
-
-        let mut command = self.tokio1_command(envelope);
-
-        // Spawn the sendmail command
-        let mut process = command.spawn()?;
-
-        process.stdin.as_mut().unwrap().write_all(&email).await?;
-        let output = process.wait_with_output().await?;
-
-        if output.status.success() {
-            Ok(())
-        } else {
-            Err(Error::Client(String::from_utf8(output.stderr)?))
-        }
+        let scope = SendScope::new("sendmail", envelope, email);
+        let result: Result<Self::Ok, Self::Error> = scope
+            .run(async {
+                use tokio1_crate::io::AsyncWriteExt;
+
+                let mut command = self.tokio1_command(envelope);
+
+                // Spawn the sendmail command
+                let mut process = command.spawn()?;
+
+                process.stdin.as_mut().unwrap().write_all(&email).await?;
+                let output = process.wait_with_output().await?;
+
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    Err(Error::Client(String::from_utf8(output.stderr)?))
+                }
+            })
+            .await;
+        scope.finish(&result);
+        result
     }
 }
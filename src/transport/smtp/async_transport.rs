@@ -1,9 +1,17 @@
-use std::marker::PhantomData;
+use std::{
+    marker::PhantomData,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 
 use super::{
-    client::AsyncSmtpConnection, ClientId, Credentials, Error, Mechanism, Response, SmtpInfo,
+    client::{AsyncSmtpConnection, SessionObserver},
+    failover::FailoverTransportBuilder,
+    lifecycle::{Lifecycle, SHUTDOWN_POLL_INTERVAL},
+    record_response_code, ClientId, Credentials, Error, Mechanism, SendResponse, SmtpInfo,
 };
 #[cfg(feature = "async-std1")]
 use crate::AsyncStd1Executor;
@@ -13,72 +21,95 @@ use crate::AsyncTransport;
 use crate::Tokio02Executor;
 #[cfg(feature = "tokio1")]
 use crate::Tokio1Executor;
-use crate::{Envelope, Executor};
+use crate::{transport::instrument::SendScope, Envelope, Executor};
 
 #[allow(missing_debug_implementations)]
 pub struct AsyncSmtpTransport<E> {
     // TODO: pool
     inner: AsyncSmtpClient<E>,
+    lifecycle: Arc<Lifecycle>,
 }
 
 #[cfg(feature = "tokio02")]
 #[async_trait]
 impl AsyncTransport for AsyncSmtpTransport<Tokio02Executor> {
-    type Ok = Response;
+    type Ok = SendResponse;
     type Error = Error;
 
     /// Sends an email
     async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
-        let mut conn = self.inner.connection().await?;
-
-        let result = conn.send(envelope, email).await?;
-
-        conn.quit().await?;
-
-        Ok(result)
+        self.send_attempt(envelope, email).await.0
     }
 }
 
 #[cfg(feature = "tokio1")]
 #[async_trait]
 impl AsyncTransport for AsyncSmtpTransport<Tokio1Executor> {
-    type Ok = Response;
+    type Ok = SendResponse;
     type Error = Error;
 
     /// Sends an email
     async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
-        let mut conn = self.inner.connection().await?;
-
-        let result = conn.send(envelope, email).await?;
-
-        conn.quit().await?;
-
-        Ok(result)
+        self.send_attempt(envelope, email).await.0
     }
 }
 
 #[cfg(feature = "async-std1")]
 #[async_trait]
 impl AsyncTransport for AsyncSmtpTransport<AsyncStd1Executor> {
-    type Ok = Response;
+    type Ok = SendResponse;
     type Error = Error;
 
     /// Sends an email
     async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
+        self.send_attempt(envelope, email).await.0
+    }
+}
+
+impl<E> AsyncSmtpTransport<E>
+where
+    E: Executor,
+{
+    /// Sends an email, also telling if the server answered the `MAIL` command
+    ///
+    /// Once it did, a failure doesn't mean the server didn't receive the message.
+    pub(crate) async fn send_attempt(
+        &self,
+        envelope: &Envelope,
+        email: &[u8],
+    ) -> (Result<SendResponse, Error>, bool) {
+        let scope = SendScope::new("smtp", envelope, email);
+        scope.record_relay(&self.inner.info.server);
+
+        let mut started = false;
+        let result = scope
+            .run(self.send_inner(envelope, email, &mut started))
+            .await;
+        record_response_code(&scope, result.as_ref().map(SendResponse::response));
+        scope.finish(&result);
+        (result, started)
+    }
+
+    async fn send_inner(
+        &self,
+        envelope: &Envelope,
+        email: &[u8],
+        started: &mut bool,
+    ) -> Result<SendResponse, Error> {
+        let _in_flight = self.lifecycle.begin()?;
+
         let mut conn = self.inner.connection().await?;
 
-        let result = conn.send(envelope, email).await?;
+        let result = SendResponse::new(conn.send_tracked(envelope, email, started).await?);
+        // The message was accepted, don't fail because of the TLS details
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        let result = result.with_tls_info(conn.tls_info().ok().flatten());
 
         conn.quit().await?;
 
         Ok(result)
     }
-}
 
-impl<E> AsyncSmtpTransport<E>
-where
-    E: Executor,
-{
     /// Simple and secure transport, using TLS connections to communicate with the SMTP server
     ///
     /// The right option for most SMTP servers.
@@ -94,9 +125,9 @@ where
         feature = "async-std1-rustls-tls"
     ))]
     pub fn relay(relay: &str) -> Result<AsyncSmtpTransportBuilder, Error> {
-        use super::{Tls, TlsParameters, SUBMISSIONS_PORT};
+        use super::{unbracket, Tls, TlsParameters, SUBMISSIONS_PORT};
 
-        let tls_parameters = TlsParameters::new(relay.into())?;
+        let tls_parameters = TlsParameters::new(unbracket(relay).into())?;
 
         Ok(Self::builder_dangerous(relay)
             .port(SUBMISSIONS_PORT)
@@ -123,15 +154,58 @@ where
         feature = "async-std1-rustls-tls"
     ))]
     pub fn starttls_relay(relay: &str) -> Result<AsyncSmtpTransportBuilder, Error> {
-        use super::{Tls, TlsParameters, SUBMISSION_PORT};
+        use super::{unbracket, Tls, TlsParameters, SUBMISSION_PORT};
 
-        let tls_parameters = TlsParameters::new(relay.into())?;
+        let tls_parameters = TlsParameters::new(unbracket(relay).into())?;
 
         Ok(Self::builder_dangerous(relay)
             .port(SUBMISSION_PORT)
             .tls(Tls::Required(tls_parameters)))
     }
 
+    /// Tests the connection to the server
+    ///
+    /// Opens a new connection, including encryption and authentication,
+    /// checks that the server answers a `NOOP` and closes it with `QUIT`.
+    /// No email is sent. Fails once the transport has been shut down.
+    pub async fn test_connection(&self) -> Result<(), Error> {
+        let _in_flight = self.lifecycle.begin()?;
+        let mut conn = self.inner.connection().await?;
+
+        conn.noop().await?;
+        conn.quit().await?;
+        Ok(())
+    }
+
+    /// Gracefully shuts down the transport
+    ///
+    /// New sends are refused as soon as this method is called. Sends which are
+    /// already in progress are given up to `timeout` to complete.
+    ///
+    /// Returns an error if some sends were still in progress when the timeout
+    /// expired.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), Error> {
+        self.lifecycle.close();
+
+        let deadline = Instant::now() + timeout;
+        while self.lifecycle.in_flight() > 0 {
+            if Instant::now() >= deadline {
+                return Err(Error::Client(
+                    "timed out waiting for in-flight sends during shutdown",
+                ));
+            }
+            E::sleep(SHUTDOWN_POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    /// Creates a transport sending through several relays
+    ///
+    /// See [`FailoverTransportBuilder`] for the available settings.
+    pub fn failover() -> FailoverTransportBuilder<AsyncSmtpTransport<E>> {
+        FailoverTransportBuilder::new()
+    }
+
     /// Creates a new local SMTP client to port 25
     ///
     /// Shortcut for local unencrypted relay (typical local email daemon that will handle relaying)
@@ -166,6 +240,7 @@ where
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            lifecycle: Arc::clone(&self.lifecycle),
         }
     }
 }
@@ -180,6 +255,11 @@ pub struct AsyncSmtpTransportBuilder {
 
 /// Builder for the SMTP `AsyncSmtpTransport`
 impl AsyncSmtpTransportBuilder {
+    /// Server the transport connects to
+    pub(crate) fn server(&self) -> &str {
+        &self.info.server
+    }
+
     /// Set the name used during EHLO
     pub fn hello_name(mut self, name: ClientId) -> Self {
         self.info.hello_name = name;
@@ -198,12 +278,46 @@ impl AsyncSmtpTransportBuilder {
         self
     }
 
+    /// Set the host to connect to, which defaults to the relay name
+    ///
+    /// Allows connecting to a specific IP address or load balancer while still verifying
+    /// the TLS certificate against the relay name. IPv6 addresses can be written
+    /// with brackets, like `[::1]`.
+    pub fn connect_to<T: Into<String>>(mut self, host: T) -> Self {
+        self.info.connect_to = Some(host.into());
+        self
+    }
+
     /// Set the port to use
     pub fn port(mut self, port: u16) -> Self {
         self.info.port = port;
         self
     }
 
+    /// Set the timeout duration of each connection attempt
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.info.connection = self.info.connection.timeout(timeout);
+        self
+    }
+
+    /// Set the local address to bind outgoing connections to
+    ///
+    /// Only server addresses of the same family (IPv4 or IPv6) as the
+    /// source address will be tried.
+    pub fn source_address(mut self, source_address: IpAddr) -> Self {
+        self.info.connection = self.info.connection.source_address(source_address);
+        self
+    }
+
+    /// Set an observer receiving the commands and responses of every connection
+    ///
+    /// Authentication data and message contents are redacted by default,
+    /// see [`SessionObserver`](super::client::SessionObserver).
+    pub fn session_observer<O: SessionObserver + 'static>(mut self, observer: O) -> Self {
+        self.info.connection = self.info.connection.session_observer(observer);
+        self
+    }
+
     /// Set the TLS settings to use
     #[cfg(any(
         feature = "tokio02-native-tls",
@@ -227,7 +341,10 @@ impl AsyncSmtpTransportBuilder {
             info: self.info,
             marker_: PhantomData,
         };
-        AsyncSmtpTransport { inner: client }
+        AsyncSmtpTransport {
+            inner: client,
+            lifecycle: Arc::new(Lifecycle::default()),
+        }
     }
 }
 
@@ -245,13 +362,18 @@ where
     ///
     /// Handles encryption and authentication
     pub async fn connection(&self) -> Result<AsyncSmtpConnection, Error> {
-        let mut conn = E::connect(
-            &self.info.server,
-            self.info.port,
-            &self.info.hello_name,
-            &self.info.tls,
-        )
-        .await?;
+        let mut conn = in_span!(
+            E::connect(
+                self.info.host(),
+                self.info.port,
+                &self.info.hello_name,
+                &self.info.tls,
+                &self.info.connection,
+            ) => await,
+            "connect",
+            relay = self.info.server.as_str(),
+            port = self.info.port
+        )?;
 
         if let Some(credentials) = &self.info.credentials {
             conn.auth(&self.info.authentication, &credentials).await?;
@@ -11,8 +11,8 @@ pub const DEFAULT_MECHANISMS: &[Mechanism] = &[Mechanism::Plain, Mechanism::Logi
 #[derive(PartialEq, Eq, Clone, Hash, Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Credentials {
-    authentication_identity: String,
-    secret: String,
+    pub(crate) authentication_identity: String,
+    pub(crate) secret: String,
 }
 
 impl Credentials {
@@ -50,6 +50,14 @@ pub enum Mechanism {
     /// Non-standard XOAUTH2 mechanism, defined in
     /// [xoauth2-protocol](https://developers.google.com/gmail/imap/xoauth2-protocol)
     Xoauth2,
+    /// EXTERNAL authentication mechanism, defined in
+    /// [RFC 4422](https://tools.ietf.org/html/rfc4422#appendix-A)
+    ///
+    /// Authenticates using the identity established outside of SMTP,
+    /// usually a TLS client certificate. The credentials username is sent as
+    /// authorization identity, leave it empty to act as the identity of
+    /// the certificate. The password is not used.
+    External,
 }
 
 impl Display for Mechanism {
@@ -58,6 +66,7 @@ impl Display for Mechanism {
             Mechanism::Plain => "PLAIN",
             Mechanism::Login => "LOGIN",
             Mechanism::Xoauth2 => "XOAUTH2",
+            Mechanism::External => "EXTERNAL",
         })
     }
 }
@@ -66,7 +75,7 @@ impl Mechanism {
     /// Does the mechanism supports initial response
     pub fn supports_initial_response(self) -> bool {
         match self {
-            Mechanism::Plain | Mechanism::Xoauth2 => true,
+            Mechanism::Plain | Mechanism::Xoauth2 | Mechanism::External => true,
             Mechanism::Login => false,
         }
     }
@@ -107,6 +116,12 @@ impl Mechanism {
                     credentials.authentication_identity, credentials.secret
                 )),
             },
+            Mechanism::External => match challenge {
+                Some(challenge) if !challenge.is_empty() => {
+                    Err(Error::Client("This mechanism does not expect a challenge"))
+                }
+                _ => Ok(credentials.authentication_identity.to_string()),
+            },
         }
     }
 }
@@ -161,6 +176,18 @@ mod test {
         assert!(mechanism.response(&credentials, Some("test")).is_err());
     }
 
+    #[test]
+    fn test_external() {
+        let mechanism = Mechanism::External;
+
+        let credentials = Credentials::new("".to_string(), "".to_string());
+        assert_eq!(mechanism.response(&credentials, None).unwrap(), "");
+
+        let credentials = Credentials::new("alice".to_string(), "".to_string());
+        assert_eq!(mechanism.response(&credentials, Some("")).unwrap(), "alice");
+        assert!(mechanism.response(&credentials, Some("test")).is_err());
+    }
+
     #[test]
     fn test_from_user_pass_for_credentials() {
         assert_eq!(
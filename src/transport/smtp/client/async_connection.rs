@@ -1,8 +1,13 @@
-use std::{fmt::Display, io};
+use std::{fmt::Display, io, sync::Arc};
 
 use futures_util::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 
-use super::{AsyncNetworkStream, ClientCodec, TlsParameters};
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+use super::TlsInfo;
+use super::{
+    transcript::{Sent, SessionLog},
+    AsyncNetworkStream, ClientCodec, ConnectionOptions, SessionObserver, TlsParameters,
+};
 use crate::{
     transport::smtp::{
         authentication::{Credentials, Mechanism},
@@ -14,9 +19,6 @@ use crate::{
     Envelope,
 };
 
-#[cfg(feature = "tracing")]
-use super::escape_crlf;
-
 macro_rules! try_smtp (
     ($err: expr, $client: ident) => ({
         match $err {
@@ -38,6 +40,8 @@ pub struct AsyncSmtpConnection {
     panic: bool,
     /// Information about the server
     server_info: ServerInfo,
+    /// Reports the exchanged data
+    log: SessionLog,
 }
 
 impl AsyncSmtpConnection {
@@ -47,57 +51,85 @@ impl AsyncSmtpConnection {
 
     /// Connects to the configured server
     ///
-    /// Sends EHLO and parses server information
+    /// Sends EHLO and parses server information. The timeout, local address and
+    /// session observer are taken from the `options`.
     #[cfg(feature = "tokio02")]
     pub async fn connect_tokio02(
         hostname: &str,
         port: u16,
         hello_name: &ClientId,
         tls_parameters: Option<TlsParameters>,
+        options: &ConnectionOptions,
     ) -> Result<AsyncSmtpConnection, Error> {
-        let stream = AsyncNetworkStream::connect_tokio02(hostname, port, tls_parameters).await?;
-        Self::connect_impl(stream, hello_name).await
+        let stream = AsyncNetworkStream::connect_tokio02(
+            hostname,
+            port,
+            options.timeout,
+            tls_parameters,
+            options.source_address,
+        )
+        .await?;
+        Self::connect_impl(stream, hello_name, options.observer.clone()).await
     }
 
     /// Connects to the configured server
     ///
-    /// Sends EHLO and parses server information
+    /// Sends EHLO and parses server information. The timeout, local address and
+    /// session observer are taken from the `options`.
     #[cfg(feature = "tokio1")]
     pub async fn connect_tokio1(
         hostname: &str,
         port: u16,
         hello_name: &ClientId,
         tls_parameters: Option<TlsParameters>,
+        options: &ConnectionOptions,
     ) -> Result<AsyncSmtpConnection, Error> {
-        let stream = AsyncNetworkStream::connect_tokio1(hostname, port, tls_parameters).await?;
-        Self::connect_impl(stream, hello_name).await
+        let stream = AsyncNetworkStream::connect_tokio1(
+            hostname,
+            port,
+            options.timeout,
+            tls_parameters,
+            options.source_address,
+        )
+        .await?;
+        Self::connect_impl(stream, hello_name, options.observer.clone()).await
     }
 
     /// Connects to the configured server
     ///
-    /// Sends EHLO and parses server information
+    /// Sends EHLO and parses server information. The timeout, local address and
+    /// session observer are taken from the `options`.
     #[cfg(feature = "async-std1")]
     pub async fn connect_asyncstd1(
         hostname: &str,
         port: u16,
         hello_name: &ClientId,
         tls_parameters: Option<TlsParameters>,
+        options: &ConnectionOptions,
     ) -> Result<AsyncSmtpConnection, Error> {
-        let stream = AsyncNetworkStream::connect_asyncstd1(hostname, port, tls_parameters).await?;
-        Self::connect_impl(stream, hello_name).await
+        let stream = AsyncNetworkStream::connect_asyncstd1(
+            hostname,
+            port,
+            options.timeout,
+            tls_parameters,
+            options.source_address,
+        )
+        .await?;
+        Self::connect_impl(stream, hello_name, options.observer.clone()).await
     }
 
     async fn connect_impl(
         stream: AsyncNetworkStream,
         hello_name: &ClientId,
+        observer: Option<Arc<dyn SessionObserver>>,
     ) -> Result<AsyncSmtpConnection, Error> {
         let stream = BufReader::new(stream);
         let mut conn = AsyncSmtpConnection {
             stream,
             panic: false,
             server_info: ServerInfo::default(),
+            log: SessionLog::new(observer),
         };
-        // TODO log
         let _response = conn.read_response().await?;
 
         conn.ehlo(hello_name).await?;
@@ -109,6 +141,32 @@ impl AsyncSmtpConnection {
     }
 
     pub async fn send(&mut self, envelope: &Envelope, email: &[u8]) -> Result<Response, Error> {
+        self.send_tracked(envelope, email, &mut false).await
+    }
+
+    /// Sends an email, setting `started` once the server answered the `MAIL` command
+    ///
+    /// A failure before that means the server didn't receive the message.
+    pub(crate) async fn send_tracked(
+        &mut self,
+        envelope: &Envelope,
+        email: &[u8],
+        started: &mut bool,
+    ) -> Result<Response, Error> {
+        in_span!(
+            self.transaction(envelope, email, started) => await,
+            "transaction",
+            recipients = envelope.to().len()
+        )
+    }
+
+    /// Runs a mail transaction
+    async fn transaction(
+        &mut self,
+        envelope: &Envelope,
+        email: &[u8],
+        started: &mut bool,
+    ) -> Result<Response, Error> {
         // Mail
         let mut mail_options = vec![];
 
@@ -143,6 +201,7 @@ impl AsyncSmtpConnection {
                 .await,
             self
         );
+        *started = true;
 
         // Recipient
         for to_address in envelope.to() {
@@ -219,9 +278,20 @@ impl AsyncSmtpConnection {
         self.stream.get_ref().is_encrypted()
     }
 
+    /// Returns the details of the TLS session, if the connection is encrypted
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    pub fn tls_info(&self) -> Result<Option<TlsInfo>, Error> {
+        self.stream.get_ref().tls_info()
+    }
+
+    /// Sends a NOOP command, which can be used to keep the connection alive
+    pub async fn noop(&mut self) -> Result<Response, Error> {
+        self.command(Noop).await
+    }
+
     /// Checks if the server is connected using the NOOP SMTP command
     pub async fn test_connected(&mut self) -> bool {
-        self.command(Noop).await.is_ok()
+        self.noop().await.is_ok()
     }
 
     /// Sends an AUTH command with the given mechanism, and handles challenge if needed
@@ -237,20 +307,35 @@ impl AsyncSmtpConnection {
                 "No compatible authentication mechanism was found",
             ))?;
 
+        in_span!(
+            self.authenticate(mechanism, credentials) => await,
+            "auth",
+            mechanism = %mechanism
+        )
+    }
+
+    /// Runs the authentication exchange of the mechanism
+    async fn authenticate(
+        &mut self,
+        mechanism: Mechanism,
+        credentials: &Credentials,
+    ) -> Result<Response, Error> {
         // Limit challenges to avoid blocking
         let mut challenges = 10;
         let mut response = self
-            .command(Auth::new(mechanism, credentials.clone(), None)?)
+            .send_command(
+                Auth::new(mechanism, credentials.clone(), None)?,
+                Sent::Credentials,
+            )
             .await?;
 
         while challenges > 0 && response.has_code(334) {
             challenges -= 1;
             response = try_smtp!(
-                self.command(Auth::new_from_response(
-                    mechanism,
-                    credentials.clone(),
-                    &response,
-                )?)
+                self.send_command(
+                    Auth::new_from_response(mechanism, credentials.clone(), &response)?,
+                    Sent::Credentials,
+                )
                 .await,
                 self
             );
@@ -268,24 +353,31 @@ impl AsyncSmtpConnection {
         let mut out_buf: Vec<u8> = vec![];
         let mut codec = ClientCodec::new();
         codec.encode(message, &mut out_buf);
-        self.write(out_buf.as_slice()).await?;
-        self.write(b"\r\n.\r\n").await?;
+        out_buf.extend_from_slice(b"\r\n.\r\n");
+        self.write(out_buf.as_slice(), Sent::Message).await?;
         self.read_response().await
     }
 
     /// Sends an SMTP command
     pub async fn command<C: Display>(&mut self, command: C) -> Result<Response, Error> {
-        self.write(command.to_string().as_bytes()).await?;
+        self.send_command(command, Sent::Command).await
+    }
+
+    async fn send_command<C: Display>(
+        &mut self,
+        command: C,
+        kind: Sent,
+    ) -> Result<Response, Error> {
+        self.write(command.to_string().as_bytes(), kind).await?;
         self.read_response().await
     }
 
     /// Writes a string to the server
-    async fn write(&mut self, string: &[u8]) -> Result<(), Error> {
+    async fn write(&mut self, string: &[u8], kind: Sent) -> Result<(), Error> {
         self.stream.get_mut().write_all(string).await?;
         self.stream.get_mut().flush().await?;
 
-        #[cfg(feature = "tracing")]
-        tracing::debug!("Wrote: {}", escape_crlf(&String::from_utf8_lossy(string)));
+        self.log.sent(kind, string);
         Ok(())
     }
 
@@ -294,10 +386,9 @@ impl AsyncSmtpConnection {
         let mut buffer = String::with_capacity(100);
 
         while self.stream.read_line(&mut buffer).await? > 0 {
-            #[cfg(feature = "tracing")]
-            tracing::debug!("<< {}", escape_crlf(&buffer));
             match parse_response(&buffer) {
                 Ok((_remaining, response)) => {
+                    self.log.received(&buffer);
                     if response.is_positive() {
                         return Ok(response);
                     }
@@ -5,26 +5,32 @@
 ))]
 use std::sync::Arc;
 use std::{
-    net::SocketAddr,
+    future::Future,
+    net::{IpAddr, SocketAddr},
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use futures_io::{
     AsyncRead as FuturesAsyncRead, AsyncWrite as FuturesAsyncWrite, Error as IoError, ErrorKind,
     Result as IoResult,
 };
+use futures_util::{
+    future::{select, Either},
+    stream::{FuturesUnordered, StreamExt},
+};
 #[cfg(feature = "tokio02")]
 use tokio02_crate::io::{AsyncRead as _, AsyncWrite as _};
 #[cfg(feature = "tokio1")]
 use tokio1_crate::io::{AsyncRead as _, AsyncWrite as _, ReadBuf as Tokio1ReadBuf};
 
 #[cfg(feature = "async-std1")]
-use async_std::net::TcpStream as AsyncStd1TcpStream;
+use async_std::net::{TcpStream as AsyncStd1TcpStream, ToSocketAddrs as _};
 #[cfg(feature = "tokio02")]
 use tokio02_crate::net::TcpStream as Tokio02TcpStream;
 #[cfg(feature = "tokio1")]
-use tokio1_crate::net::TcpStream as Tokio1TcpStream;
+use tokio1_crate::net::{TcpSocket as Tokio1TcpSocket, TcpStream as Tokio1TcpStream};
 
 #[cfg(feature = "async-std1-native-tls")]
 use async_native_tls::TlsStream as AsyncStd1TlsStream;
@@ -40,6 +46,8 @@ use tokio02_rustls::client::TlsStream as Tokio02RustlsTlsStream;
 #[cfg(feature = "tokio1-rustls-tls")]
 use tokio1_rustls::client::TlsStream as Tokio1RustlsTlsStream;
 
+#[cfg(any(feature = "tokio02", feature = "async-std1"))]
+use super::net::connect_addr;
 #[cfg(any(
     feature = "tokio02-native-tls",
     feature = "tokio02-rustls-tls",
@@ -49,7 +57,12 @@ use tokio1_rustls::client::TlsStream as Tokio1RustlsTlsStream;
     feature = "async-std1-rustls-tls"
 ))]
 use super::InnerTlsParameters;
-use super::TlsParameters;
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+use super::TlsInfo;
+use super::{
+    net::{connection_order, CONNECTION_ATTEMPT_DELAY},
+    TlsParameters,
+};
 use crate::transport::smtp::Error;
 
 /// A network stream
@@ -142,9 +155,34 @@ impl AsyncNetworkStream {
     pub async fn connect_tokio02(
         hostname: &str,
         port: u16,
+        timeout: Option<Duration>,
         tls_parameters: Option<TlsParameters>,
+        source_address: Option<IpAddr>,
     ) -> Result<AsyncNetworkStream, Error> {
-        let tcp_stream = Tokio02TcpStream::connect((hostname, port)).await?;
+        let addrs = connection_order(
+            tokio02_crate::net::lookup_host((hostname, port)).await?,
+            source_address,
+        )?;
+        let tcp_stream = connect_happy_eyeballs(
+            addrs,
+            timeout,
+            |addr| async move {
+                match source_address {
+                    // tokio 0.2 can't bind a socket before connecting it
+                    Some(source_address) => {
+                        let tcp_stream = tokio02_crate::task::spawn_blocking(move || {
+                            connect_addr(addr, timeout, Some(source_address))
+                        })
+                        .await??;
+                        tcp_stream.set_nonblocking(true)?;
+                        Tokio02TcpStream::from_std(tcp_stream)
+                    }
+                    None => Tokio02TcpStream::connect(addr).await,
+                }
+            },
+            tokio02_crate::time::delay_for,
+        )
+        .await?;
 
         let mut stream = AsyncNetworkStream::new(InnerAsyncNetworkStream::Tokio02Tcp(tcp_stream));
         if let Some(tls_parameters) = tls_parameters {
@@ -157,9 +195,31 @@ impl AsyncNetworkStream {
     pub async fn connect_tokio1(
         hostname: &str,
         port: u16,
+        timeout: Option<Duration>,
         tls_parameters: Option<TlsParameters>,
+        source_address: Option<IpAddr>,
     ) -> Result<AsyncNetworkStream, Error> {
-        let tcp_stream = Tokio1TcpStream::connect((hostname, port)).await?;
+        let addrs = connection_order(
+            tokio1_crate::net::lookup_host((hostname, port)).await?,
+            source_address,
+        )?;
+        let tcp_stream = connect_happy_eyeballs(
+            addrs,
+            timeout,
+            |addr| async move {
+                let socket = if addr.is_ipv4() {
+                    Tokio1TcpSocket::new_v4()?
+                } else {
+                    Tokio1TcpSocket::new_v6()?
+                };
+                if let Some(source_address) = source_address {
+                    socket.bind(SocketAddr::new(source_address, 0))?;
+                }
+                socket.connect(addr).await
+            },
+            tokio1_crate::time::sleep,
+        )
+        .await?;
 
         let mut stream = AsyncNetworkStream::new(InnerAsyncNetworkStream::Tokio1Tcp(tcp_stream));
         if let Some(tls_parameters) = tls_parameters {
@@ -172,9 +232,30 @@ impl AsyncNetworkStream {
     pub async fn connect_asyncstd1(
         hostname: &str,
         port: u16,
+        timeout: Option<Duration>,
         tls_parameters: Option<TlsParameters>,
+        source_address: Option<IpAddr>,
     ) -> Result<AsyncNetworkStream, Error> {
-        let tcp_stream = AsyncStd1TcpStream::connect((hostname, port)).await?;
+        let addrs = connection_order((hostname, port).to_socket_addrs().await?, source_address)?;
+        let tcp_stream = connect_happy_eyeballs(
+            addrs,
+            timeout,
+            |addr| async move {
+                match source_address {
+                    // async-std can't bind a socket before connecting it
+                    Some(source_address) => {
+                        let tcp_stream = async_std::task::spawn_blocking(move || {
+                            connect_addr(addr, timeout, Some(source_address))
+                        })
+                        .await?;
+                        Ok(AsyncStd1TcpStream::from(tcp_stream))
+                    }
+                    None => AsyncStd1TcpStream::connect(addr).await,
+                }
+            },
+            async_std::task::sleep,
+        )
+        .await?;
 
         let mut stream = AsyncNetworkStream::new(InnerAsyncNetworkStream::AsyncStd1Tcp(tcp_stream));
         if let Some(tls_parameters) = tls_parameters {
@@ -203,8 +284,12 @@ impl AsyncNetworkStream {
                     _ => unreachable!(),
                 };
 
-                self.inner = Self::upgrade_tokio02_tls(tcp_stream, tls_parameters).await?;
-                Ok(())
+                self.inner = in_span!(
+                    Self::upgrade_tokio02_tls(tcp_stream, &tls_parameters) => await,
+                    "tls",
+                    domain = tls_parameters.domain()
+                )?;
+                tls_parameters.verify_pins(self.peer_certificate()?.as_deref())
             }
             #[cfg(all(
                 feature = "tokio1",
@@ -224,8 +309,12 @@ impl AsyncNetworkStream {
                     _ => unreachable!(),
                 };
 
-                self.inner = Self::upgrade_tokio1_tls(tcp_stream, tls_parameters).await?;
-                Ok(())
+                self.inner = in_span!(
+                    Self::upgrade_tokio1_tls(tcp_stream, &tls_parameters) => await,
+                    "tls",
+                    domain = tls_parameters.domain()
+                )?;
+                tls_parameters.verify_pins(self.peer_certificate()?.as_deref())
             }
             #[cfg(all(
                 feature = "async-std1",
@@ -245,8 +334,12 @@ impl AsyncNetworkStream {
                     _ => unreachable!(),
                 };
 
-                self.inner = Self::upgrade_asyncstd1_tls(tcp_stream, tls_parameters).await?;
-                Ok(())
+                self.inner = in_span!(
+                    Self::upgrade_asyncstd1_tls(tcp_stream, &tls_parameters) => await,
+                    "tls",
+                    domain = tls_parameters.domain()
+                )?;
+                tls_parameters.verify_pins(self.peer_certificate()?.as_deref())
             }
             _ => Ok(()),
         }
@@ -256,11 +349,11 @@ impl AsyncNetworkStream {
     #[cfg(any(feature = "tokio02-native-tls", feature = "tokio02-rustls-tls"))]
     async fn upgrade_tokio02_tls(
         tcp_stream: Tokio02TcpStream,
-        mut tls_parameters: TlsParameters,
+        tls_parameters: &TlsParameters,
     ) -> Result<InnerAsyncNetworkStream, Error> {
-        let domain = std::mem::take(&mut tls_parameters.domain);
+        let domain = &tls_parameters.domain;
 
-        match tls_parameters.connector {
+        match &tls_parameters.connector {
             #[cfg(feature = "native-tls")]
             InnerTlsParameters::NativeTls(connector) => {
                 #[cfg(not(feature = "tokio02-native-tls"))]
@@ -270,8 +363,8 @@ impl AsyncNetworkStream {
                 return {
                     use tokio02_native_tls_crate::TlsConnector;
 
-                    let connector = TlsConnector::from(connector);
-                    let stream = connector.connect(&domain, tcp_stream).await?;
+                    let connector = TlsConnector::from(connector.clone());
+                    let stream = connector.connect(domain, tcp_stream).await?;
                     Ok(InnerAsyncNetworkStream::Tokio02NativeTls(stream))
                 };
             }
@@ -284,9 +377,9 @@ impl AsyncNetworkStream {
                 return {
                     use tokio02_rustls::{webpki::DNSNameRef, TlsConnector};
 
-                    let domain = DNSNameRef::try_from_ascii_str(&domain)?;
+                    let domain = DNSNameRef::try_from_ascii_str(domain)?;
 
-                    let connector = TlsConnector::from(Arc::new(config));
+                    let connector = TlsConnector::from(Arc::clone(config));
                     let stream = connector.connect(domain, tcp_stream).await?;
                     Ok(InnerAsyncNetworkStream::Tokio02RustlsTls(stream))
                 };
@@ -298,11 +391,11 @@ impl AsyncNetworkStream {
     #[cfg(any(feature = "tokio1-native-tls", feature = "tokio1-rustls-tls"))]
     async fn upgrade_tokio1_tls(
         tcp_stream: Tokio1TcpStream,
-        mut tls_parameters: TlsParameters,
+        tls_parameters: &TlsParameters,
     ) -> Result<InnerAsyncNetworkStream, Error> {
-        let domain = std::mem::take(&mut tls_parameters.domain);
+        let domain = &tls_parameters.domain;
 
-        match tls_parameters.connector {
+        match &tls_parameters.connector {
             #[cfg(feature = "native-tls")]
             InnerTlsParameters::NativeTls(connector) => {
                 #[cfg(not(feature = "tokio1-native-tls"))]
@@ -312,8 +405,8 @@ impl AsyncNetworkStream {
                 return {
                     use tokio1_native_tls_crate::TlsConnector;
 
-                    let connector = TlsConnector::from(connector);
-                    let stream = connector.connect(&domain, tcp_stream).await?;
+                    let connector = TlsConnector::from(connector.clone());
+                    let stream = connector.connect(domain, tcp_stream).await?;
                     Ok(InnerAsyncNetworkStream::Tokio1NativeTls(stream))
                 };
             }
@@ -326,9 +419,9 @@ impl AsyncNetworkStream {
                 return {
                     use tokio1_rustls::{webpki::DNSNameRef, TlsConnector};
 
-                    let domain = DNSNameRef::try_from_ascii_str(&domain)?;
+                    let domain = DNSNameRef::try_from_ascii_str(domain)?;
 
-                    let connector = TlsConnector::from(Arc::new(config));
+                    let connector = TlsConnector::from(Arc::clone(config));
                     let stream = connector.connect(domain, tcp_stream).await?;
                     Ok(InnerAsyncNetworkStream::Tokio1RustlsTls(stream))
                 };
@@ -340,11 +433,11 @@ impl AsyncNetworkStream {
     #[cfg(any(feature = "async-std1-native-tls", feature = "async-std1-rustls-tls"))]
     async fn upgrade_asyncstd1_tls(
         tcp_stream: AsyncStd1TcpStream,
-        mut tls_parameters: TlsParameters,
+        tls_parameters: &TlsParameters,
     ) -> Result<InnerAsyncNetworkStream, Error> {
-        let domain = std::mem::take(&mut tls_parameters.domain);
+        let domain = &tls_parameters.domain;
 
-        match tls_parameters.connector {
+        match &tls_parameters.connector {
             #[cfg(feature = "native-tls")]
             InnerTlsParameters::NativeTls(connector) => {
                 panic!("native-tls isn't supported with async-std yet. See https://github.com/lettre/lettre/pull/531#issuecomment-757893531");
@@ -359,8 +452,8 @@ impl AsyncNetworkStream {
 
                     // TODO: fix
                     let connector: TlsConnector = todo!();
-                    // let connector = TlsConnector::from(connector);
-                    let stream = connector.connect(&domain, tcp_stream).await?;
+                    // let connector = TlsConnector::from(connector.clone());
+                    let stream = connector.connect(domain, tcp_stream).await?;
                     Ok(InnerAsyncNetworkStream::AsyncStd1NativeTls(stream))
                 };
                 */
@@ -374,9 +467,9 @@ impl AsyncNetworkStream {
                 return {
                     use async_rustls::{webpki::DNSNameRef, TlsConnector};
 
-                    let domain = DNSNameRef::try_from_ascii_str(&domain)?;
+                    let domain = DNSNameRef::try_from_ascii_str(domain)?;
 
-                    let connector = TlsConnector::from(Arc::new(config));
+                    let connector = TlsConnector::from(Arc::clone(config));
                     let stream = connector.connect(domain, tcp_stream).await?;
                     Ok(InnerAsyncNetworkStream::AsyncStd1RustlsTls(stream))
                 };
@@ -384,6 +477,69 @@ impl AsyncNetworkStream {
         }
     }
 
+    /// Returns the DER encoded certificate presented by the server, if the stream is encrypted
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    pub fn peer_certificate(&self) -> Result<Option<Vec<u8>>, Error> {
+        match self.inner {
+            #[cfg(feature = "tokio02-native-tls")]
+            InnerAsyncNetworkStream::Tokio02NativeTls(ref stream) => Ok(stream
+                .get_ref()
+                .peer_certificate()?
+                .map(|certificate| certificate.to_der())
+                .transpose()?),
+            #[cfg(feature = "tokio02-rustls-tls")]
+            InnerAsyncNetworkStream::Tokio02RustlsTls(ref stream) => {
+                Ok(rustls_peer_certificate(stream.get_ref().1))
+            }
+            #[cfg(feature = "tokio1-native-tls")]
+            InnerAsyncNetworkStream::Tokio1NativeTls(ref stream) => Ok(stream
+                .get_ref()
+                .peer_certificate()?
+                .map(|certificate| certificate.to_der())
+                .transpose()?),
+            #[cfg(feature = "tokio1-rustls-tls")]
+            InnerAsyncNetworkStream::Tokio1RustlsTls(ref stream) => {
+                Ok(rustls_peer_certificate(stream.get_ref().1))
+            }
+            #[cfg(feature = "async-std1-native-tls")]
+            InnerAsyncNetworkStream::AsyncStd1NativeTls(ref stream) => Ok(stream
+                .peer_certificate()?
+                .map(|certificate| certificate.to_der())
+                .transpose()?),
+            #[cfg(feature = "async-std1-rustls-tls")]
+            InnerAsyncNetworkStream::AsyncStd1RustlsTls(ref stream) => {
+                Ok(rustls_peer_certificate(stream.get_ref().1))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the details of the TLS session, if the stream is encrypted
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    pub fn tls_info(&self) -> Result<Option<TlsInfo>, Error> {
+        match self.inner {
+            #[cfg(feature = "tokio02-rustls-tls")]
+            InnerAsyncNetworkStream::Tokio02RustlsTls(ref stream) => {
+                Ok(Some(TlsInfo::from_rustls(stream.get_ref().1)))
+            }
+            #[cfg(feature = "tokio1-rustls-tls")]
+            InnerAsyncNetworkStream::Tokio1RustlsTls(ref stream) => {
+                Ok(Some(TlsInfo::from_rustls(stream.get_ref().1)))
+            }
+            #[cfg(feature = "async-std1-rustls-tls")]
+            InnerAsyncNetworkStream::AsyncStd1RustlsTls(ref stream) => {
+                Ok(Some(TlsInfo::from_rustls(stream.get_ref().1)))
+            }
+            // native-tls only exposes the server certificate
+            _ if self.is_encrypted() => Ok(Some(TlsInfo::new(
+                None,
+                None,
+                self.peer_certificate()?.into_iter().collect(),
+            ))),
+            _ => Ok(None),
+        }
+    }
+
     pub fn is_encrypted(&self) -> bool {
         match self.inner {
             #[cfg(feature = "tokio02")]
@@ -409,6 +565,82 @@ impl AsyncNetworkStream {
     }
 }
 
+#[cfg(any(
+    feature = "tokio02-rustls-tls",
+    feature = "tokio1-rustls-tls",
+    feature = "async-std1-rustls-tls"
+))]
+fn rustls_peer_certificate(session: &rustls::ClientSession) -> Option<Vec<u8>> {
+    use rustls::Session;
+
+    session
+        .get_peer_certificates()
+        .and_then(|certificates| certificates.into_iter().next())
+        .map(|certificate| certificate.0)
+}
+
+/// Races connection attempts to `addrs` following RFC 8305
+///
+/// This is the async counterpart of the blocking implementation used by
+/// `NetworkStream`, with `delay` provided by the runtime.
+async fn connect_happy_eyeballs<S, C, CF, D, DF>(
+    addrs: Vec<SocketAddr>,
+    timeout: Option<Duration>,
+    connect: C,
+    delay: D,
+) -> Result<S, Error>
+where
+    C: Fn(SocketAddr) -> CF,
+    CF: Future<Output = IoResult<S>>,
+    D: Fn(Duration) -> DF,
+    DF: Future<Output = ()>,
+{
+    let mut addrs = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+
+    loop {
+        match addrs.next() {
+            Some(addr) => {
+                let attempt = connect(addr);
+                let deadline = timeout.map(&delay);
+                attempts.push(async move {
+                    match deadline {
+                        Some(deadline) => {
+                            match select(Box::pin(attempt), Box::pin(deadline)).await {
+                                Either::Left((result, _)) => result,
+                                Either::Right(_) => {
+                                    Err(IoError::new(ErrorKind::TimedOut, "connection timed out"))
+                                }
+                            }
+                        }
+                        None => attempt.await,
+                    }
+                });
+            }
+            None if attempts.is_empty() => {
+                return Err(last_err.map_or(Error::Resolution, Error::Io))
+            }
+            None => {}
+        }
+
+        let result = if addrs.len() == 0 {
+            attempts.next().await
+        } else {
+            match select(attempts.next(), Box::pin(delay(CONNECTION_ATTEMPT_DELAY))).await {
+                Either::Left((result, _)) => result,
+                // Still pending, start the next attempt
+                Either::Right(_) => None,
+            }
+        };
+        match result {
+            Some(Ok(stream)) => return Ok(stream),
+            Some(Err(err)) => last_err = Some(err),
+            None => {}
+        }
+    }
+}
+
 impl FuturesAsyncRead for AsyncNetworkStream {
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -557,3 +789,63 @@ impl FuturesAsyncWrite for AsyncNetworkStream {
         }
     }
 }
+
+#[cfg(all(test, feature = "tokio1"))]
+mod test {
+    use std::{
+        net::TcpListener,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    #[test]
+    fn connect_does_not_wait_for_stalled_attempt() {
+        let listener_v6 = TcpListener::bind("[::1]:0").unwrap();
+        let listener_v4 = TcpListener::bind("127.0.0.1:0").unwrap();
+        let v6 = listener_v6.local_addr().unwrap();
+        let v4 = listener_v4.local_addr().unwrap();
+
+        let runtime = tokio1_crate::runtime::Runtime::new().unwrap();
+        let start = Instant::now();
+        let stream = runtime
+            .block_on(connect_happy_eyeballs(
+                vec![v6, v4],
+                Some(Duration::from_secs(10)),
+                |addr| async move {
+                    if addr == v6 {
+                        // Simulate a broken route
+                        tokio1_crate::time::sleep(Duration::from_secs(5)).await;
+                    }
+                    Tokio1TcpStream::connect(addr).await
+                },
+                tokio1_crate::time::sleep,
+            ))
+            .unwrap();
+
+        assert_eq!(stream.peer_addr().unwrap(), v4);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn connect_attempt_timeout() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let runtime = tokio1_crate::runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(connect_happy_eyeballs(
+            vec![addr],
+            Some(Duration::from_millis(100)),
+            |addr| async move {
+                tokio1_crate::time::sleep(Duration::from_secs(5)).await;
+                Tokio1TcpStream::connect(addr).await
+            },
+            tokio1_crate::time::sleep,
+        ));
+
+        match result {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), ErrorKind::TimedOut),
+            _ => panic!("expected a timeout"),
+        }
+    }
+}
@@ -2,10 +2,16 @@ use std::{
     fmt::Display,
     io::{self, BufRead, BufReader, Write},
     net::ToSocketAddrs,
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use super::{ClientCodec, NetworkStream, TlsParameters};
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+use super::TlsInfo;
+use super::{
+    transcript::{Sent, SessionLog},
+    ClientCodec, ConnectionOptions, NetworkStream, SessionObserver, TlsParameters,
+};
 use crate::{
     address::Envelope,
     transport::smtp::{
@@ -17,9 +23,6 @@ use crate::{
     },
 };
 
-#[cfg(feature = "tracing")]
-use super::escape_crlf;
-
 macro_rules! try_smtp (
     ($err: expr, $client: ident) => ({
         match $err {
@@ -41,6 +44,10 @@ pub struct SmtpConnection {
     panic: bool,
     /// Information about the server
     server_info: ServerInfo,
+    /// Reports the exchanged data
+    log: SessionLog,
+    /// Time of the last response from the server
+    last_activity: Instant,
 }
 
 impl SmtpConnection {
@@ -52,33 +59,76 @@ impl SmtpConnection {
 
     /// Connects to the configured server
     ///
-    /// Sends EHLO and parses server information
+    /// Sends EHLO and parses server information. The timeout, local address and
+    /// session observer are taken from the `options`.
     pub fn connect<A: ToSocketAddrs>(
         server: A,
-        timeout: Option<Duration>,
         hello_name: &ClientId,
         tls_parameters: Option<&TlsParameters>,
+        options: &ConnectionOptions,
     ) -> Result<SmtpConnection, Error> {
-        let stream = NetworkStream::connect(server, timeout, tls_parameters)?;
-        let stream = BufReader::new(stream);
-        let mut conn = SmtpConnection {
-            stream,
+        let stream = NetworkStream::connect(
+            server,
+            options.timeout,
+            tls_parameters,
+            options.source_address,
+        )?;
+        let mut conn = SmtpConnection::new(stream, options.observer.clone());
+        conn.set_timeout(options.timeout)?;
+        conn.handshake(hello_name)?;
+        Ok(conn)
+    }
+
+    /// Creates a connection over an established stream
+    fn new(stream: NetworkStream, observer: Option<Arc<dyn SessionObserver>>) -> SmtpConnection {
+        SmtpConnection {
+            stream: BufReader::new(stream),
             panic: false,
             server_info: ServerInfo::default(),
-        };
-        conn.set_timeout(timeout)?;
-        // TODO log
-        let _response = conn.read_response()?;
+            log: SessionLog::new(observer),
+            last_activity: Instant::now(),
+        }
+    }
 
-        conn.ehlo(hello_name)?;
+    /// Reads the greeting, sends EHLO and parses server information
+    fn handshake(&mut self, hello_name: &ClientId) -> Result<(), Error> {
+        let _response = self.read_response()?;
+
+        self.ehlo(hello_name)?;
 
         // Print server information
         #[cfg(feature = "tracing")]
-        tracing::debug!("server {}", conn.server_info);
-        Ok(conn)
+        tracing::debug!("server {}", self.server_info);
+        Ok(())
     }
 
     pub fn send(&mut self, envelope: &Envelope, email: &[u8]) -> Result<Response, Error> {
+        self.send_tracked(envelope, email, &mut false)
+    }
+
+    /// Sends an email, setting `started` once the server answered the `MAIL` command
+    ///
+    /// A failure before that means the server didn't receive the message.
+    pub(crate) fn send_tracked(
+        &mut self,
+        envelope: &Envelope,
+        email: &[u8],
+        started: &mut bool,
+    ) -> Result<Response, Error> {
+        in_span!(
+            self.transaction(envelope, email, started),
+            "transaction",
+            recipients = envelope.to().len()
+        )
+    }
+
+    /// Runs a mail transaction
+    fn transaction(
+        &mut self,
+        envelope: &Envelope,
+        email: &[u8],
+        started: &mut bool,
+    ) -> Result<Response, Error> {
         // Mail
         let mut mail_options = vec![];
 
@@ -112,6 +162,7 @@ impl SmtpConnection {
             self.command(Mail::new(envelope.from().cloned(), mail_options)),
             self
         );
+        *started = true;
 
         // Recipient
         for to_address in envelope.to() {
@@ -130,6 +181,12 @@ impl SmtpConnection {
         self.panic
     }
 
+    /// Time elapsed since the server last responded
+    #[cfg(feature = "r2d2")]
+    pub(crate) fn idle_duration(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
     pub fn can_starttls(&self) -> bool {
         !self.is_encrypted() && self.server_info.supports_feature(Extension::StartTls)
     }
@@ -189,15 +246,26 @@ impl SmtpConnection {
         self.stream.get_ref().is_encrypted()
     }
 
+    /// Returns the details of the TLS session, if the connection is encrypted
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    pub fn tls_info(&self) -> Result<Option<TlsInfo>, Error> {
+        self.stream.get_ref().tls_info()
+    }
+
     /// Set timeout
     pub fn set_timeout(&mut self, duration: Option<Duration>) -> io::Result<()> {
         self.stream.get_mut().set_read_timeout(duration)?;
         self.stream.get_mut().set_write_timeout(duration)
     }
 
+    /// Sends a NOOP command, which can be used to keep the connection alive
+    pub fn noop(&mut self) -> Result<Response, Error> {
+        self.command(Noop)
+    }
+
     /// Checks if the server is connected using the NOOP SMTP command
     pub fn test_connected(&mut self) -> bool {
-        self.command(Noop).is_ok()
+        self.noop().is_ok()
     }
 
     /// Sends an AUTH command with the given mechanism, and handles challenge if needed
@@ -213,18 +281,33 @@ impl SmtpConnection {
                 "No compatible authentication mechanism was found",
             ))?;
 
+        in_span!(
+            self.authenticate(mechanism, credentials),
+            "auth",
+            mechanism = %mechanism
+        )
+    }
+
+    /// Runs the authentication exchange of the mechanism
+    fn authenticate(
+        &mut self,
+        mechanism: Mechanism,
+        credentials: &Credentials,
+    ) -> Result<Response, Error> {
         // Limit challenges to avoid blocking
         let mut challenges = 10;
-        let mut response = self.command(Auth::new(mechanism, credentials.clone(), None)?)?;
+        let mut response = self.send_command(
+            Auth::new(mechanism, credentials.clone(), None)?,
+            Sent::Credentials,
+        )?;
 
         while challenges > 0 && response.has_code(334) {
             challenges -= 1;
             response = try_smtp!(
-                self.command(Auth::new_from_response(
-                    mechanism,
-                    credentials.clone(),
-                    &response,
-                )?),
+                self.send_command(
+                    Auth::new_from_response(mechanism, credentials.clone(), &response)?,
+                    Sent::Credentials,
+                ),
                 self
             );
         }
@@ -241,24 +324,27 @@ impl SmtpConnection {
         let mut out_buf: Vec<u8> = vec![];
         let mut codec = ClientCodec::new();
         codec.encode(message, &mut out_buf);
-        self.write(out_buf.as_slice())?;
-        self.write(b"\r\n.\r\n")?;
+        out_buf.extend_from_slice(b"\r\n.\r\n");
+        self.write(out_buf.as_slice(), Sent::Message)?;
         self.read_response()
     }
 
     /// Sends an SMTP command
     pub fn command<C: Display>(&mut self, command: C) -> Result<Response, Error> {
-        self.write(command.to_string().as_bytes())?;
+        self.send_command(command, Sent::Command)
+    }
+
+    fn send_command<C: Display>(&mut self, command: C, kind: Sent) -> Result<Response, Error> {
+        self.write(command.to_string().as_bytes(), kind)?;
         self.read_response()
     }
 
     /// Writes a string to the server
-    fn write(&mut self, string: &[u8]) -> Result<(), Error> {
+    fn write(&mut self, string: &[u8], kind: Sent) -> Result<(), Error> {
         self.stream.get_mut().write_all(string)?;
         self.stream.get_mut().flush()?;
 
-        #[cfg(feature = "tracing")]
-        tracing::debug!("Wrote: {}", escape_crlf(&String::from_utf8_lossy(string)));
+        self.log.sent(kind, string);
         Ok(())
     }
 
@@ -267,10 +353,10 @@ impl SmtpConnection {
         let mut buffer = String::with_capacity(100);
 
         while self.stream.read_line(&mut buffer)? > 0 {
-            #[cfg(feature = "tracing")]
-            tracing::debug!("<< {}", escape_crlf(&buffer));
             match parse_response(&buffer) {
                 Ok((_remaining, response)) => {
+                    self.log.received(&buffer);
+                    self.last_activity = Instant::now();
                     if response.is_positive() {
                         return Ok(response);
                     }
@@ -290,3 +376,96 @@ impl SmtpConnection {
         Err(io::Error::new(io::ErrorKind::Other, "incomplete").into())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{super::replay::Replay, *};
+
+    /// Replays a transcript of `testdata/transcripts`, running the session with `client`
+    fn replay<F>(transcript: &str, client: F)
+    where
+        F: FnOnce(&mut SmtpConnection),
+    {
+        let replay = Replay::parse(transcript);
+        let mut conn = SmtpConnection::new(replay.stream(), None);
+        conn.handshake(&ClientId::Domain("client.test".to_string()))
+            .unwrap();
+        client(&mut conn);
+        replay.assert_commands();
+    }
+
+    fn envelope(to: &[&str]) -> Envelope {
+        Envelope::new(
+            Some("nobody@domain.tld".parse().unwrap()),
+            to.iter().map(|to| to.parse().unwrap()).collect(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn replay_multiline_greeting_auth_login() {
+        replay(
+            include_str!("../../../../testdata/transcripts/multiline_greeting_auth_login.txt"),
+            |conn| {
+                assert!(conn.server_info().supports_auth_mechanism(Mechanism::Login));
+                let credentials = Credentials::new("user".to_string(), "password".to_string());
+                conn.auth(&[Mechanism::Plain, Mechanism::Login], &credentials)
+                    .unwrap();
+                conn.send(
+                    &envelope(&["hei@domain.tld"]),
+                    b"Subject: test\r\n\r\nBe happy!",
+                )
+                .unwrap();
+                conn.quit().unwrap();
+            },
+        );
+    }
+
+    #[test]
+    fn replay_auth_external() {
+        replay(
+            include_str!("../../../../testdata/transcripts/auth_external.txt"),
+            |conn| {
+                assert!(conn
+                    .server_info()
+                    .supports_auth_mechanism(Mechanism::External));
+                let credentials = Credentials::new(String::new(), String::new());
+                conn.auth(&[Mechanism::External], &credentials).unwrap();
+                conn.quit().unwrap();
+            },
+        );
+    }
+
+    #[test]
+    fn replay_rejected_recipient() {
+        replay(
+            include_str!("../../../../testdata/transcripts/rejected_recipient.txt"),
+            |conn| {
+                let result = conn.send(
+                    &envelope(&["hei@domain.tld", "unknown@domain.tld"]),
+                    b"Subject: test\r\n\r\nBe happy!",
+                );
+                match result {
+                    Err(Error::Permanent(response)) => assert!(response.has_code(550)),
+                    _ => panic!("expected the recipient to be rejected"),
+                }
+                assert!(conn.has_broken());
+            },
+        );
+    }
+
+    #[test]
+    fn replay_dot_stuffing() {
+        replay(
+            include_str!("../../../../testdata/transcripts/dot_stuffing.txt"),
+            |conn| {
+                conn.send(
+                    &envelope(&["hei@domain.tld"]),
+                    b"Subject: dots\r\n\r\n.\r\n..\r\n.leading dot",
+                )
+                .unwrap();
+                conn.quit().unwrap();
+            },
+        );
+    }
+}
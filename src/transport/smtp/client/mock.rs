@@ -2,16 +2,24 @@
 // Comes from https://github.com/inre/rust-mq/blob/master/netopt
 
 use std::{
+    fmt::Debug,
     io::{self, Cursor, Read, Write},
     sync::{Arc, Mutex},
 };
 
 pub type MockCursor = Cursor<Vec<u8>>;
 
+/// Provides the data to read once the previous data was read
+pub(crate) trait Responder: Debug + Send {
+    /// Returns the next data to read, given all the data written so far
+    fn respond(&mut self, written: &[u8]) -> Vec<u8>;
+}
+
 #[derive(Clone, Debug)]
 pub struct MockStream {
     reader: Arc<Mutex<MockCursor>>,
     writer: Arc<Mutex<MockCursor>>,
+    responder: Option<Arc<Mutex<dyn Responder>>>,
 }
 
 impl Default for MockStream {
@@ -25,6 +33,7 @@ impl MockStream {
         MockStream {
             reader: Arc::new(Mutex::new(MockCursor::new(Vec::new()))),
             writer: Arc::new(Mutex::new(MockCursor::new(Vec::new()))),
+            responder: None,
         }
     }
 
@@ -32,6 +41,16 @@ impl MockStream {
         MockStream {
             reader: Arc::new(Mutex::new(MockCursor::new(vec))),
             writer: Arc::new(Mutex::new(MockCursor::new(Vec::new()))),
+            responder: None,
+        }
+    }
+
+    /// Creates a stream asking `responder` for the data to read each time it was all read
+    #[cfg(test)]
+    pub(crate) fn with_responder<R: Responder + 'static>(responder: Arc<Mutex<R>>) -> MockStream {
+        MockStream {
+            responder: Some(responder),
+            ..MockStream::new()
         }
     }
 
@@ -77,7 +96,15 @@ impl Write for MockStream {
 
 impl Read for MockStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.reader.lock().unwrap().read(buf)
+        let mut reader = self.reader.lock().unwrap();
+        if let Some(responder) = &self.responder {
+            if reader.position() == reader.get_ref().len() as u64 {
+                let written = self.writer.lock().unwrap().get_ref().clone();
+                let response = responder.lock().unwrap().respond(&written);
+                reader.get_mut().extend_from_slice(&response);
+            }
+        }
+        reader.read(buf)
     }
 }
 
@@ -10,7 +10,7 @@
 //! use lettre::transport::smtp::{SMTP_PORT, extension::ClientId, commands::*, client::SmtpConnection};
 //!
 //! let hello = ClientId::Domain("my_hostname".to_string());
-//! let mut client = SmtpConnection::connect(&("localhost", SMTP_PORT), None, &hello, None)?;
+//! let mut client = SmtpConnection::connect(&("localhost", SMTP_PORT), &hello, None, &Default::default())?;
 //! client.command(
 //!         Mail::new(Some("user@example.com".parse()?), vec![])
 //!     )?;
@@ -31,13 +31,20 @@ use std::fmt::Debug;
 pub(crate) use self::async_connection::AsyncSmtpConnection;
 #[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
 pub(crate) use self::async_net::AsyncNetworkStream;
-use self::net::NetworkStream;
+pub(crate) use self::net::NetworkStream;
 #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
 pub(super) use self::tls::InnerTlsParameters;
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+pub use self::tls::{PeerCertificate, TlsInfo};
 pub use self::{
     connection::SmtpConnection,
     mock::MockStream,
-    tls::{Certificate, Tls, TlsParameters, TlsParametersBuilder},
+    options::ConnectionOptions,
+    tls::{
+        Certificate, CertificateStore, Identity, Tls, TlsParameters, TlsParametersBuilder,
+        TlsVersion,
+    },
+    transcript::{Direction, SessionObserver, Transcript, TranscriptEntry},
 };
 
 #[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
@@ -47,7 +54,13 @@ mod async_net;
 mod connection;
 mod mock;
 mod net;
+mod options;
+#[cfg(test)]
+mod replay;
 mod tls;
+mod transcript;
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+mod x509;
 
 /// The codec used for transparency
 #[derive(Default, Clone, Copy, Debug)]
@@ -78,9 +91,10 @@ impl ClientCodec {
                 let mut start = 0;
                 for (idx, byte) in frame.iter().enumerate() {
                     match self.escape_count {
-                        0 => self.escape_count = if *byte == b'\r' { 1 } else { 0 },
+                        0..=2 if *byte == b'\r' => self.escape_count = 1,
                         1 => self.escape_count = if *byte == b'\n' { 2 } else { 0 },
                         2 => self.escape_count = if *byte == b'.' { 3 } else { 0 },
+                        0 => (),
                         _ => unreachable!(),
                     }
                     if self.escape_count == 3 {
@@ -121,9 +135,10 @@ mod test {
         codec.encode(b"test\n", &mut buf);
         codec.encode(b".test\n", &mut buf);
         codec.encode(b"test", &mut buf);
+        codec.encode(b"\r\n\r\n.\r\r\n.", &mut buf);
         assert_eq!(
             String::from_utf8(buf).unwrap(),
-            "test\r\n..\r\n\r\ntestte\r\n..\r\nsttesttest.test\n.test\ntest"
+            "test\r\n..\r\n\r\ntestte\r\n..\r\nsttesttest.test\n.test\ntest\r\n\r\n..\r\r\n.."
         );
     }
 
@@ -1,19 +1,21 @@
-#[cfg(feature = "rustls-tls")]
-use std::sync::Arc;
 use std::{
     io::{self, Read, Write},
-    net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpStream, ToSocketAddrs},
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpStream, ToSocketAddrs},
+    sync::{mpsc, Arc},
+    thread,
     time::Duration,
 };
 
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
 #[cfg(feature = "native-tls")]
 use native_tls::TlsStream;
 
 #[cfg(feature = "rustls-tls")]
-use rustls::{ClientSession, StreamOwned};
+use rustls::{ClientSession, Session, StreamOwned};
 
 #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
-use super::InnerTlsParameters;
+use super::{InnerTlsParameters, TlsInfo};
 use super::{MockStream, TlsParameters};
 use crate::transport::smtp::Error;
 
@@ -79,24 +81,9 @@ impl NetworkStream {
         server: T,
         timeout: Option<Duration>,
         tls_parameters: Option<&TlsParameters>,
+        source_address: Option<IpAddr>,
     ) -> Result<NetworkStream, Error> {
-        fn try_connect_timeout<T: ToSocketAddrs>(
-            server: T,
-            timeout: Duration,
-        ) -> Result<TcpStream, Error> {
-            let addrs = server.to_socket_addrs()?;
-            for addr in addrs {
-                if let Ok(result) = TcpStream::connect_timeout(&addr, timeout) {
-                    return Ok(result);
-                }
-            }
-            Err(Error::Client("Could not connect"))
-        }
-
-        let tcp_stream = match timeout {
-            Some(t) => try_connect_timeout(server, t)?,
-            None => TcpStream::connect(server)?,
-        };
+        let tcp_stream = connect_tcp(server, timeout, source_address)?;
 
         let mut stream = NetworkStream::new(InnerNetworkStream::Tcp(tcp_stream));
         if let Some(tls_parameters) = tls_parameters {
@@ -123,8 +110,12 @@ impl NetworkStream {
                     _ => unreachable!(),
                 };
 
-                self.inner = Self::upgrade_tls_impl(tcp_stream, tls_parameters)?;
-                Ok(())
+                self.inner = in_span!(
+                    Self::upgrade_tls_impl(tcp_stream, tls_parameters),
+                    "tls",
+                    domain = tls_parameters.domain()
+                )?;
+                tls_parameters.verify_pins(self.peer_certificate()?.as_deref())
             }
             _ => Ok(()),
         }
@@ -148,16 +139,57 @@ impl NetworkStream {
                 use webpki::DNSNameRef;
 
                 let domain = DNSNameRef::try_from_ascii_str(tls_parameters.domain())?;
-                let stream = StreamOwned::new(
-                    ClientSession::new(&Arc::new(connector.clone()), domain),
-                    tcp_stream,
-                );
+                let mut session = ClientSession::new(connector, domain);
+                let mut tcp_stream = tcp_stream;
+                // Complete the handshake right away, so that the server certificate
+                // can be checked before anything is sent
+                while session.is_handshaking() {
+                    session.complete_io(&mut tcp_stream)?;
+                }
+                let stream = StreamOwned::new(session, tcp_stream);
 
                 InnerNetworkStream::RustlsTls(stream)
             }
         })
     }
 
+    /// Returns the DER encoded certificate presented by the server, if the stream is encrypted
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    pub fn peer_certificate(&self) -> Result<Option<Vec<u8>>, Error> {
+        match self.inner {
+            InnerNetworkStream::Tcp(_) | InnerNetworkStream::Mock(_) => Ok(None),
+            #[cfg(feature = "native-tls")]
+            InnerNetworkStream::NativeTls(ref stream) => Ok(stream
+                .peer_certificate()?
+                .map(|certificate| certificate.to_der())
+                .transpose()?),
+            #[cfg(feature = "rustls-tls")]
+            InnerNetworkStream::RustlsTls(ref stream) => Ok(stream
+                .sess
+                .get_peer_certificates()
+                .and_then(|certificates| certificates.into_iter().next())
+                .map(|certificate| certificate.0)),
+        }
+    }
+
+    /// Returns the details of the TLS session, if the stream is encrypted
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    pub fn tls_info(&self) -> Result<Option<TlsInfo>, Error> {
+        match self.inner {
+            InnerNetworkStream::Tcp(_) | InnerNetworkStream::Mock(_) => Ok(None),
+            #[cfg(feature = "native-tls")]
+            InnerNetworkStream::NativeTls(_) => Ok(Some(TlsInfo::new(
+                None,
+                None,
+                self.peer_certificate()?.into_iter().collect(),
+            ))),
+            #[cfg(feature = "rustls-tls")]
+            InnerNetworkStream::RustlsTls(ref stream) => {
+                Ok(Some(TlsInfo::from_rustls(&stream.sess)))
+            }
+        }
+    }
+
     pub fn is_encrypted(&self) -> bool {
         match self.inner {
             InnerNetworkStream::Tcp(_) | InnerNetworkStream::Mock(_) => false,
@@ -202,6 +234,146 @@ impl NetworkStream {
     }
 }
 
+/// Delay before starting the next connection attempt while the previous
+/// one is still pending, as recommended by RFC 8305
+pub(super) const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Opens a TCP connection to `server`
+///
+/// All resolved addresses are tried following RFC 8305 (Happy Eyeballs):
+/// attempts are started every [`CONNECTION_ATTEMPT_DELAY`], alternating
+/// between address families, and the first one to succeed is used. The
+/// timeout applies to each attempt.
+///
+/// When a source address is given, the socket is bound to it before
+/// connecting, and only server addresses of the same family are tried.
+pub(super) fn connect_tcp<T: ToSocketAddrs>(
+    server: T,
+    timeout: Option<Duration>,
+    source_address: Option<IpAddr>,
+) -> Result<TcpStream, Error> {
+    let addrs = connection_order(server.to_socket_addrs()?, source_address)?;
+    connect_happy_eyeballs(addrs, move |addr| {
+        connect_addr(addr, timeout, source_address)
+    })
+}
+
+/// Sorts resolved addresses in the order connections should be attempted
+///
+/// Addresses not matching the family of the source address are dropped,
+/// and the remaining ones are interleaved by family, starting with the
+/// family of the first resolved address. Fails if no address matches the
+/// family of the source address.
+pub(super) fn connection_order<I: IntoIterator<Item = SocketAddr>>(
+    addrs: I,
+    source_address: Option<IpAddr>,
+) -> Result<Vec<SocketAddr>, Error> {
+    let resolved: Vec<SocketAddr> = addrs.into_iter().collect();
+    let addrs: Vec<SocketAddr> = resolved
+        .iter()
+        .copied()
+        .filter(
+            |addr| !matches!(source_address, Some(source) if source.is_ipv4() != addr.is_ipv4()),
+        )
+        .collect();
+    if addrs.is_empty() && !resolved.is_empty() {
+        return Err(Error::Client(
+            "the server has no address of the same family as the source address",
+        ));
+    }
+
+    let mut first_family: Vec<SocketAddr> = Vec::new();
+    let mut other_family = Vec::new();
+    for addr in addrs {
+        match first_family.first() {
+            Some(first) if first.is_ipv4() != addr.is_ipv4() => other_family.push(addr),
+            _ => first_family.push(addr),
+        }
+    }
+
+    let mut sorted = Vec::with_capacity(first_family.len() + other_family.len());
+    let mut first_family = first_family.into_iter();
+    let mut other_family = other_family.into_iter();
+    loop {
+        match (first_family.next(), other_family.next()) {
+            (None, None) => return Ok(sorted),
+            (first, other) => sorted.extend(first.into_iter().chain(other)),
+        }
+    }
+}
+
+/// Races connection attempts to `addrs`, starting a new one every
+/// [`CONNECTION_ATTEMPT_DELAY`] or as soon as the previous one failed
+fn connect_happy_eyeballs<F>(addrs: Vec<SocketAddr>, connect: F) -> Result<TcpStream, Error>
+where
+    F: Fn(SocketAddr) -> io::Result<TcpStream> + Send + Sync + 'static,
+{
+    // Nothing to race
+    if addrs.len() == 1 {
+        return connect(addrs[0]).map_err(Error::Io);
+    }
+
+    let connect = Arc::new(connect);
+    let (sender, receiver) = mpsc::channel();
+    let mut addrs = addrs.into_iter();
+    let mut pending = 0;
+    let mut last_err = None;
+
+    loop {
+        match addrs.next() {
+            Some(addr) => {
+                let connect = Arc::clone(&connect);
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    // The receiver is gone when another attempt already won
+                    let _ = sender.send(connect(addr));
+                });
+                pending += 1;
+            }
+            None if pending == 0 => return Err(last_err.map_or(Error::Resolution, Error::Io)),
+            None => {}
+        }
+
+        let result = if addrs.len() == 0 {
+            receiver.recv().ok()
+        } else {
+            receiver.recv_timeout(CONNECTION_ATTEMPT_DELAY).ok()
+        };
+        match result {
+            Some(Ok(stream)) => return Ok(stream),
+            Some(Err(err)) => {
+                pending -= 1;
+                last_err = Some(err);
+            }
+            // Still pending, start the next attempt
+            None => {}
+        }
+    }
+}
+
+pub(super) fn connect_addr(
+    addr: SocketAddr,
+    timeout: Option<Duration>,
+    source_address: Option<IpAddr>,
+) -> io::Result<TcpStream> {
+    let domain = if addr.is_ipv4() {
+        Domain::ipv4()
+    } else {
+        Domain::ipv6()
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    if let Some(source_address) = source_address {
+        socket.bind(&SockAddr::from(SocketAddr::new(source_address, 0)))?;
+    }
+
+    let addr = SockAddr::from(addr);
+    match timeout {
+        Some(timeout) => socket.connect_timeout(&addr, timeout)?,
+        None => socket.connect(&addr)?,
+    }
+    Ok(socket.into_tcp_stream())
+}
+
 impl Read for NetworkStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self.inner {
@@ -238,3 +410,281 @@ impl Write for NetworkStream {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{net::TcpListener, time::Instant};
+
+    use super::*;
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    use crate::transport::smtp::client::TlsParametersBuilder;
+    #[cfg(feature = "rustls-tls")]
+    use crate::transport::smtp::client::TlsVersion;
+
+    fn listen(ip: &str) -> (TcpListener, SocketAddr) {
+        let listener = TcpListener::bind((ip, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        (listener, addr)
+    }
+
+    #[test]
+    fn connection_order_interleaves_families() {
+        let addrs: Vec<SocketAddr> = vec![
+            "[::1]:25".parse().unwrap(),
+            "[::2]:25".parse().unwrap(),
+            "[::3]:25".parse().unwrap(),
+            "127.0.0.1:25".parse().unwrap(),
+            "127.0.0.2:25".parse().unwrap(),
+        ];
+
+        assert_eq!(
+            connection_order(addrs.clone(), None).unwrap(),
+            vec![addrs[0], addrs[3], addrs[1], addrs[4], addrs[2]]
+        );
+        assert_eq!(
+            connection_order(addrs.clone(), Some("127.0.0.1".parse().unwrap())).unwrap(),
+            vec![addrs[3], addrs[4]]
+        );
+    }
+
+    #[test]
+    fn connection_order_family_mismatch() {
+        let addrs: Vec<SocketAddr> = vec!["[::1]:25".parse().unwrap()];
+
+        assert!(matches!(
+            connection_order(addrs, Some("127.0.0.1".parse().unwrap())),
+            Err(Error::Client(_))
+        ));
+        assert!(connection_order(vec![], Some("127.0.0.1".parse().unwrap()))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn connect_falls_back_to_other_family() {
+        let (listener, closed) = listen("::1");
+        drop(listener);
+        let (_listener, open) = listen("127.0.0.1");
+
+        let stream = connect_tcp(&[closed, open][..], Some(Duration::from_secs(5)), None).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), open);
+    }
+
+    #[test]
+    fn connect_does_not_wait_for_stalled_attempt() {
+        let (_listener_v6, v6) = listen("::1");
+        let (_listener_v4, v4) = listen("127.0.0.1");
+
+        let start = Instant::now();
+        let stream = connect_happy_eyeballs(vec![v6, v4], move |addr| {
+            if addr == v6 {
+                // Simulate a broken route
+                thread::sleep(Duration::from_secs(5));
+            }
+            connect_addr(addr, None, None)
+        })
+        .unwrap();
+
+        assert_eq!(stream.peer_addr().unwrap(), v4);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    /// SHA-256 hash of the public key of the test server certificate
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    const SERVER_PIN: [u8; 32] = [
+        0x21, 0xdf, 0x1b, 0x98, 0x48, 0xfd, 0x3e, 0xca, 0xef, 0x3e, 0xbc, 0x0d, 0xc9, 0x01, 0x3c,
+        0x42, 0xe3, 0x2b, 0x40, 0x7c, 0x74, 0xb7, 0x41, 0x34, 0xb2, 0x2a, 0xa2, 0xc1, 0x03, 0x1e,
+        0x96, 0x86,
+    ];
+
+    /// Accepts TLS connections until the listener is dropped
+    #[cfg(feature = "native-tls")]
+    fn native_tls_server() -> SocketAddr {
+        let identity = native_tls::Identity::from_pkcs12(
+            include_bytes!("../../../../testdata/client.p12"),
+            "lettre",
+        )
+        .unwrap();
+        let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+        let (listener, addr) = listen("127.0.0.1");
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let acceptor = acceptor.clone();
+                thread::spawn(move || {
+                    if let Ok(mut stream) = acceptor.accept(stream.unwrap()) {
+                        let _ = stream.read(&mut [0; 1]);
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    /// Accepts TLS connections until the listener is dropped
+    #[cfg(feature = "rustls-tls")]
+    fn rustls_server() -> SocketAddr {
+        use rustls::{internal::pemfile, NoClientAuth, ServerConfig, ServerSession};
+
+        let certs =
+            pemfile::certs(&mut &include_bytes!("../../../../testdata/client.crt")[..]).unwrap();
+        let key = pemfile::pkcs8_private_keys(
+            &mut &include_bytes!("../../../../testdata/client.key")[..],
+        )
+        .unwrap()
+        .remove(0);
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.set_single_cert(certs, key).unwrap();
+        let config = Arc::new(config);
+        let (listener, addr) = listen("127.0.0.1");
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let config = Arc::clone(&config);
+                thread::spawn(move || {
+                    let session = ServerSession::new(&config);
+                    let _ = StreamOwned::new(session, stream.unwrap()).read(&mut [0; 1]);
+                });
+            }
+        });
+        addr
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    fn assert_pinning(addr: SocketAddr, builder: fn(TlsParametersBuilder) -> TlsParameters) {
+        let timeout = Some(Duration::from_secs(5));
+        let tls_parameters = |pin| {
+            builder(TlsParametersBuilder::new("lettre.test".to_string()).pin_spki_sha256(pin))
+        };
+
+        let stream =
+            NetworkStream::connect(addr, timeout, Some(&tls_parameters(SERVER_PIN)), None).unwrap();
+        assert!(stream.is_encrypted());
+
+        let result = NetworkStream::connect(addr, timeout, Some(&tls_parameters([0; 32])), None);
+        match result {
+            Err(Error::PinMismatch(fingerprint)) => assert_eq!(fingerprint, SERVER_PIN),
+            _ => panic!("expected a pin mismatch"),
+        }
+
+        // STARTTLS
+        let mut stream = NetworkStream::connect(addr, timeout, None, None).unwrap();
+        assert!(stream.upgrade_tls(&tls_parameters([0; 32])).is_err());
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    fn tls_info(addr: SocketAddr, tls_parameters: TlsParameters) -> TlsInfo {
+        let timeout = Some(Duration::from_secs(5));
+
+        let stream = NetworkStream::connect(addr, timeout, None, None).unwrap();
+        assert!(stream.tls_info().unwrap().is_none());
+
+        let stream = NetworkStream::connect(addr, timeout, Some(&tls_parameters), None).unwrap();
+        let tls_info = stream.tls_info().unwrap().unwrap();
+        let certificates = tls_info.peer_certificates();
+        assert_eq!(certificates.len(), 1);
+        assert_eq!(
+            certificates[0].subject().as_deref(),
+            Some("CN=lettre test client")
+        );
+        assert!(certificates[0].not_after().unwrap() > certificates[0].not_before().unwrap());
+        tls_info
+    }
+
+    #[test]
+    #[cfg(feature = "native-tls")]
+    fn tls_info_native_tls() {
+        let tls_parameters = TlsParametersBuilder::new("lettre.test".to_string())
+            .dangerous_accept_invalid_certs(true)
+            .build_native()
+            .unwrap();
+        let tls_info = tls_info(native_tls_server(), tls_parameters);
+        assert_eq!(tls_info.protocol_version(), None);
+        assert_eq!(tls_info.cipher_suite(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "rustls-tls")]
+    fn tls_info_rustls() {
+        let tls_parameters = TlsParametersBuilder::new("lettre.test".to_string())
+            .dangerous_accept_invalid_certs(true)
+            .max_protocol_version(TlsVersion::Tlsv12)
+            .build_rustls()
+            .unwrap();
+        let tls_info = tls_info(rustls_server(), tls_parameters);
+        assert_eq!(tls_info.protocol_version(), Some(TlsVersion::Tlsv12));
+        assert!(tls_info
+            .cipher_suite()
+            .unwrap()
+            .starts_with("TLS_ECDHE_ECDSA_"));
+    }
+
+    #[test]
+    #[cfg(feature = "native-tls")]
+    fn pinning_native_tls() {
+        assert_pinning(native_tls_server(), |builder| {
+            builder.build_native().unwrap()
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "rustls-tls")]
+    fn pinning_rustls() {
+        assert_pinning(rustls_server(), |builder| builder.build_rustls().unwrap());
+    }
+
+    /// Number of full handshakes made with rustls, counted by the certificate verifier
+    #[cfg(feature = "rustls-tls")]
+    fn rustls_handshakes(builder: TlsParametersBuilder) -> usize {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use rustls::{
+            Certificate, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError,
+        };
+        use webpki::DNSNameRef;
+
+        struct CountingVerifier(AtomicUsize);
+
+        impl ServerCertVerifier for CountingVerifier {
+            fn verify_server_cert(
+                &self,
+                _roots: &RootCertStore,
+                _presented_certs: &[Certificate],
+                _dns_name: DNSNameRef<'_>,
+                _ocsp_response: &[u8],
+            ) -> Result<ServerCertVerified, TLSError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(ServerCertVerified::assertion())
+            }
+        }
+
+        let addr = rustls_server();
+        let verifier = Arc::new(CountingVerifier(AtomicUsize::new(0)));
+        // TLS 1.3 sessions are only received after the handshake
+        let mut tls_parameters = builder
+            .max_protocol_version(TlsVersion::Tlsv12)
+            .build_rustls()
+            .unwrap();
+        if let InnerTlsParameters::RustlsTls(config) = &mut tls_parameters.connector {
+            let mut counting = (**config).clone();
+            counting
+                .dangerous()
+                .set_certificate_verifier(verifier.clone());
+            *config = Arc::new(counting);
+        }
+
+        for _ in 0..3 {
+            let timeout = Some(Duration::from_secs(5));
+            NetworkStream::connect(addr, timeout, Some(&tls_parameters), None).unwrap();
+        }
+        verifier.0.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    #[cfg(feature = "rustls-tls")]
+    fn session_resumption_rustls() {
+        let builder = TlsParametersBuilder::new("lettre.test".to_string());
+        assert_eq!(rustls_handshakes(builder.clone()), 1);
+        assert_eq!(rustls_handshakes(builder.session_cache_size(0)), 3);
+    }
+}
@@ -0,0 +1,53 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use super::SessionObserver;
+
+/// Settings for opening a connection to a server
+///
+/// Used by [`SmtpConnection::connect`](super::SmtpConnection::connect). By default there
+/// is no timeout, the system chooses the local address and the session is not observed.
+///
+/// ```rust
+/// use lettre::transport::smtp::client::{ConnectionOptions, Transcript};
+/// use std::time::Duration;
+///
+/// let options = ConnectionOptions::new()
+///     .timeout(Some(Duration::from_secs(10)))
+///     .source_address("192.0.2.1".parse().unwrap())
+///     .session_observer(Transcript::new());
+/// ```
+#[allow(missing_debug_implementations)]
+#[derive(Clone, Default)]
+pub struct ConnectionOptions {
+    /// Timeout of the connection attempt and of each command
+    pub(crate) timeout: Option<Duration>,
+    /// Local address the connection is bound to
+    pub(crate) source_address: Option<IpAddr>,
+    /// Receives the commands and responses of the session
+    pub(crate) observer: Option<Arc<dyn SessionObserver>>,
+}
+
+impl ConnectionOptions {
+    /// Creates the default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the timeout of the connection attempt and of each command
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Bind the local end of the connection to the given address
+    pub fn source_address(mut self, source_address: IpAddr) -> Self {
+        self.source_address = Some(source_address);
+        self
+    }
+
+    /// Set an observer receiving the commands and responses of the session
+    pub fn session_observer<O: SessionObserver + 'static>(mut self, observer: O) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+}
@@ -0,0 +1,328 @@
+//! Replay of recorded SMTP sessions through a `MockStream`
+//!
+//! A transcript has one line per line exchanged, prefixed by `C: ` when written by the
+//! client and `S: ` when written by the server, or `C:` alone for an empty line. Blank
+//! lines and lines starting with `#` are ignored, and the timestamps printed by
+//! [`Transcript`](super::Transcript) are skipped, so recorded sessions can be pasted as
+//! they are:
+//!
+//! ```text
+//! # Multi-line greeting
+//! S: 220-mail.example.com ESMTP
+//! S: 220 Welcome
+//! C: EHLO client.test
+//! S: 250 mail.example.com
+//! C: AUTH PLAIN <redacted>
+//! S: 235 2.7.0 Authentication successful
+//! ```
+//!
+//! Client lines can contain placeholders:
+//!
+//! * a line ending with `<redacted>` matches any line starting like it
+//! * a line starting with `<message` matches the message content, up to the final `.`
+//!
+//! The player releases each block of server lines once the client wrote the client lines
+//! preceding it, so a client reading before writing its command, or writing commands
+//! before reading their reply, fails the replay.
+
+use std::sync::{Arc, Mutex};
+
+use super::{mock::Responder, MockStream, NetworkStream};
+
+/// Placeholder for redacted data
+const REDACTED: &str = "<redacted>";
+
+/// Line of a transcript written by the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expected {
+    /// The exact line
+    Line(String),
+    /// Any line starting with the prefix
+    Prefix(String),
+    /// The content of a message, up to the final `.`
+    Message,
+}
+
+impl Expected {
+    fn parse(line: &str) -> Self {
+        if line.starts_with("<message") {
+            Expected::Message
+        } else if let Some(prefix) = line.strip_suffix(REDACTED) {
+            Expected::Prefix(prefix.to_string())
+        } else {
+            Expected::Line(line.to_string())
+        }
+    }
+}
+
+/// Step of a transcript
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// Consecutive server lines, with their CRLF
+    Server(Vec<u8>),
+    /// A line written by the client
+    Client(Expected),
+}
+
+/// Progress of the replay
+#[derive(Debug)]
+struct Player {
+    steps: Vec<Step>,
+    /// Index of the next step
+    next: usize,
+    /// Number of client lines matched
+    matched: usize,
+    /// Number of written bytes matched
+    read: usize,
+}
+
+impl Player {
+    /// Returns the next complete line written by the client
+    fn next_line(&mut self, written: &[u8]) -> Option<String> {
+        let rest = &written[self.read..];
+        let end = rest.windows(2).position(|crlf| crlf == b"\r\n")?;
+        self.read += end + 2;
+        Some(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+
+    /// Matches the written lines with the client lines, up to the next server lines
+    ///
+    /// Panics on the first line which differs or is missing, and on extra lines.
+    fn match_client(&mut self, written: &[u8]) {
+        while let Some(Step::Client(expected)) = self.steps.get(self.next).cloned() {
+            let matched = match &expected {
+                Expected::Message => loop {
+                    match self.next_line(written) {
+                        Some(line) if line == "." => break true,
+                        Some(_) => continue,
+                        None => break false,
+                    }
+                },
+                Expected::Prefix(prefix) => {
+                    matches!(self.next_line(written), Some(line) if line.starts_with(prefix.as_str()))
+                }
+                Expected::Line(expected) => {
+                    self.next_line(written).as_deref() == Some(expected.as_str())
+                }
+            };
+            self.matched += 1;
+            assert!(
+                matched,
+                "client line {} does not match {:?}, the client wrote:\n{}",
+                self.matched,
+                expected,
+                String::from_utf8_lossy(written)
+            );
+            self.next += 1;
+        }
+
+        let extra = String::from_utf8_lossy(&written[self.read..]);
+        assert!(
+            extra.is_empty(),
+            "the client wrote unexpected lines: {:?}",
+            extra
+        );
+    }
+}
+
+impl Responder for Player {
+    fn respond(&mut self, written: &[u8]) -> Vec<u8> {
+        self.match_client(written);
+        match self.steps.get(self.next) {
+            Some(Step::Server(response)) => {
+                self.next += 1;
+                response.clone()
+            }
+            // End of the session
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Player of a recorded SMTP session
+pub(crate) struct Replay {
+    mock: MockStream,
+    player: Arc<Mutex<Player>>,
+}
+
+impl Replay {
+    /// Parses a transcript
+    ///
+    /// Panics if a line is neither a comment nor prefixed by `C: ` or `S: `.
+    pub(crate) fn parse(transcript: &str) -> Self {
+        let mut steps = Vec::new();
+
+        for (number, line) in transcript.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // Skip the timestamp of recorded transcripts
+            let line = match line.find(|c: char| !c.is_ascii_digit() && c != '.') {
+                Some(start) if line[start..].starts_with(' ') => &line[start + 1..],
+                _ => line,
+            };
+
+            if let Some(response) = strip_side(line, "S:") {
+                if let Some(Step::Server(server)) = steps.last_mut() {
+                    server.extend_from_slice(response.as_bytes());
+                    server.extend_from_slice(b"\r\n");
+                } else {
+                    steps.push(Step::Server(format!("{}\r\n", response).into_bytes()));
+                }
+            } else if let Some(command) = strip_side(line, "C:") {
+                steps.push(Step::Client(Expected::parse(command)));
+            } else {
+                panic!("invalid transcript line {}: {:?}", number + 1, line);
+            }
+        }
+
+        let player = Arc::new(Mutex::new(Player {
+            steps,
+            next: 0,
+            matched: 0,
+            read: 0,
+        }));
+        Self {
+            mock: MockStream::with_responder(Arc::clone(&player)),
+            player,
+        }
+    }
+
+    /// Returns a stream replaying the server side of the session
+    pub(crate) fn stream(&self) -> NetworkStream {
+        NetworkStream::new_mock(self.mock.clone())
+    }
+
+    /// Checks the lines written by the client at the end of the session
+    ///
+    /// Panics on the first line which differs, and if server lines weren't read.
+    pub(crate) fn assert_commands(&self) {
+        let written = self.mock.clone().take_vec();
+        let mut player = self.player.lock().unwrap();
+        player.match_client(&written);
+
+        let unread = &player.steps[player.next..];
+        assert!(
+            unread.is_empty(),
+            "the client stopped before reading: {:?}",
+            unread
+        );
+    }
+}
+
+/// Removes the side prefix of a line, and the space following it
+///
+/// The space is optional so that empty lines survive editors trimming trailing spaces.
+fn strip_side<'a>(line: &'a str, side: &str) -> Option<&'a str> {
+    line.strip_prefix(side)
+        .map(|rest| rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn parse_transcript() {
+        let replay = Replay::parse(
+            "# comment\n\
+             \n\
+             1600000000.042 S: 220 ready\n\
+             C: EHLO client.test\r\n\
+             C:\n\
+             S: 250-mail.example.com\n\
+             S: 250 8BITMIME\n\
+             C: AUTH PLAIN <redacted>\n\
+             C: <redacted>\n\
+             C: <message, 26 bytes>\n",
+        );
+        let player = replay.player.lock().unwrap();
+        assert_eq!(
+            player.steps,
+            vec![
+                Step::Server(b"220 ready\r\n".to_vec()),
+                Step::Client(Expected::Line("EHLO client.test".to_string())),
+                Step::Client(Expected::Line("".to_string())),
+                Step::Server(b"250-mail.example.com\r\n250 8BITMIME\r\n".to_vec()),
+                Step::Client(Expected::Prefix("AUTH PLAIN ".to_string())),
+                Step::Client(Expected::Prefix("".to_string())),
+                Step::Client(Expected::Message),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid transcript line 2")]
+    fn parse_invalid_line() {
+        Replay::parse("S: 220 ready\nEHLO client.test\n");
+    }
+
+    #[test]
+    fn replay_commands() {
+        let replay = Replay::parse(
+            "S: 220 ready\n\
+             C: AUTH PLAIN <redacted>\n\
+             C: <message>\n\
+             C: QUIT\n",
+        );
+        let mut stream = replay.stream();
+        let mut greeting = [0; 11];
+        stream.read_exact(&mut greeting).unwrap();
+        assert_eq!(&greeting, b"220 ready\r\n");
+
+        stream
+            .write_all(b"AUTH PLAIN AHVzZXIAcGFzcw==\r\nSubject: a\r\n\r\nb\r\n.\r\nQUIT\r\n")
+            .unwrap();
+        replay.assert_commands();
+    }
+
+    #[test]
+    #[should_panic(expected = "client line 1 does not match")]
+    fn replay_mismatch() {
+        let replay = Replay::parse("C: NOOP\n");
+        replay.stream().write_all(b"QUIT\r\n").unwrap();
+        replay.assert_commands();
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected lines")]
+    fn replay_extra_commands() {
+        let replay = Replay::parse("C: NOOP\n");
+        replay.stream().write_all(b"NOOP\r\nQUIT\r\n").unwrap();
+        replay.assert_commands();
+    }
+
+    #[test]
+    #[should_panic(expected = "client line 1 does not match")]
+    fn replay_read_before_command() {
+        let replay = Replay::parse("S: 220 ready\nC: NOOP\nS: 250 OK\n");
+        let mut stream = replay.stream();
+        let mut response = [0; 11];
+        stream.read_exact(&mut response).unwrap();
+        // Reads the reply of the NOOP before writing it
+        let _ = stream.read(&mut response);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected lines")]
+    fn replay_command_before_reply() {
+        let replay = Replay::parse("S: 220 ready\nC: NOOP\nS: 250 OK\nC: QUIT\nS: 221 Bye\n");
+        let mut stream = replay.stream();
+        let mut response = [0; 11];
+        stream.read_exact(&mut response).unwrap();
+        // Writes the QUIT before reading the reply of the NOOP
+        stream.write_all(b"NOOP\r\nQUIT\r\n").unwrap();
+        let _ = stream.read(&mut response);
+    }
+
+    #[test]
+    #[should_panic(expected = "stopped before reading")]
+    fn replay_unread_reply() {
+        let replay = Replay::parse("C: NOOP\nS: 250 OK\n");
+        replay.stream().write_all(b"NOOP\r\n").unwrap();
+        replay.assert_commands();
+    }
+}
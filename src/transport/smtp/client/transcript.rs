@@ -0,0 +1,351 @@
+//! Observation of the SMTP sessions
+//!
+//! A [`SessionObserver`] receives each command sent to the server and each response
+//! received from it, with the time at which it was written or read. Authentication
+//! secrets and message contents are redacted unless the observer asks otherwise.
+//!
+//! [`Transcript`] is an observer keeping the whole exchange in memory, for example to
+//! attach it to a support ticket.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "tracing")]
+use super::escape_crlf;
+
+/// Placeholder for redacted data
+const REDACTED: &str = "<redacted>";
+
+/// Receives the commands and responses of SMTP sessions
+///
+/// Lines are given without their trailing CRLF. Multi-line responses are given at once,
+/// with their lines separated by CRLF.
+pub trait SessionObserver: Send + Sync {
+    /// Called after a command or the message content was written to the server
+    fn command(&self, timestamp: SystemTime, command: &str);
+
+    /// Called after a response was read from the server
+    fn response(&self, timestamp: SystemTime, response: &str);
+
+    /// Tells if authentication data should be replaced by a placeholder
+    ///
+    /// Only the `AUTH` keyword and the mechanism are kept. Defaults to `true`.
+    fn redact_credentials(&self) -> bool {
+        true
+    }
+
+    /// Tells if the message content should be replaced by its length
+    ///
+    /// Defaults to `true`.
+    fn redact_message(&self) -> bool {
+        true
+    }
+}
+
+/// Side of the session which wrote a line of the transcript
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Written by the client
+    Client,
+    /// Written by the server
+    Server,
+}
+
+/// A command or response of a [`Transcript`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    timestamp: SystemTime,
+    direction: Direction,
+    text: String,
+}
+
+impl TranscriptEntry {
+    /// When the data was written or read
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    /// Who wrote the data
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// The command or response, after redaction
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Display for TranscriptEntry {
+    /// Formats the entry as `<seconds since epoch> C: <command>` or `S: <response>`,
+    /// with one line per line of a multi-line response
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let since_epoch = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let side = match self.direction {
+            Direction::Client => 'C',
+            Direction::Server => 'S',
+        };
+        for (i, line) in self.text.split("\r\n").enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            write!(
+                f,
+                "{}.{:03} {}: {}",
+                since_epoch.as_secs(),
+                since_epoch.subsec_millis(),
+                side,
+                line
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Observer recording the sessions in memory
+///
+/// Clones share the same entries, so a clone can be given to a transport and the
+/// transcript read from the original.
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "builder")]
+/// # fn test() -> Result<(), Box<dyn std::error::Error>> {
+/// use lettre::{transport::smtp::client::Transcript, SmtpTransport};
+///
+/// let transcript = Transcript::new();
+/// let sender = SmtpTransport::builder_dangerous("localhost")
+///     .session_observer(transcript.clone())
+///     .build();
+/// sender.test_connection()?;
+/// println!("{}", transcript);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    entries: Arc<Mutex<Vec<TranscriptEntry>>>,
+    redact_credentials: bool,
+    redact_message: bool,
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(Vec::new())),
+            redact_credentials: true,
+            redact_message: true,
+        }
+    }
+}
+
+impl Transcript {
+    /// Creates an empty transcript, redacting credentials and message contents
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the authentication data in the clear
+    ///
+    /// The transcript then contains secrets which can be decoded trivially.
+    pub fn show_credentials(mut self) -> Self {
+        self.redact_credentials = false;
+        self
+    }
+
+    /// Record the message contents
+    pub fn show_message(mut self) -> Self {
+        self.redact_message = false;
+        self
+    }
+
+    /// Returns the recorded entries
+    pub fn entries(&self) -> Vec<TranscriptEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Removes all the recorded entries
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    fn push(&self, timestamp: SystemTime, direction: Direction, text: &str) {
+        self.entries.lock().unwrap().push(TranscriptEntry {
+            timestamp,
+            direction,
+            text: text.to_string(),
+        });
+    }
+}
+
+impl SessionObserver for Transcript {
+    fn command(&self, timestamp: SystemTime, command: &str) {
+        self.push(timestamp, Direction::Client, command);
+    }
+
+    fn response(&self, timestamp: SystemTime, response: &str) {
+        self.push(timestamp, Direction::Server, response);
+    }
+
+    fn redact_credentials(&self) -> bool {
+        self.redact_credentials
+    }
+
+    fn redact_message(&self) -> bool {
+        self.redact_message
+    }
+}
+
+impl Display for Transcript {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for entry in self.entries.lock().unwrap().iter() {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Kind of data written to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Sent {
+    /// A command without sensitive data
+    Command,
+    /// A command carrying authentication data
+    Credentials,
+    /// The message content, after dot-stuffing
+    Message,
+}
+
+/// Reports the data exchanged by a connection to its observer and to `tracing`
+#[derive(Clone, Default)]
+pub(super) struct SessionLog {
+    observer: Option<Arc<dyn SessionObserver>>,
+}
+
+impl SessionLog {
+    pub(super) fn new(observer: Option<Arc<dyn SessionObserver>>) -> Self {
+        Self { observer }
+    }
+
+    pub(super) fn sent(&self, kind: Sent, data: &[u8]) {
+        #[cfg(feature = "tracing")]
+        match kind {
+            Sent::Credentials => tracing::debug!("Wrote: {}", redact_auth(&lossy(data))),
+            _ => tracing::debug!("Wrote: {}", escape_crlf(&String::from_utf8_lossy(data))),
+        }
+
+        if let Some(observer) = &self.observer {
+            let timestamp = SystemTime::now();
+            let text = match kind {
+                Sent::Credentials if observer.redact_credentials() => redact_auth(&lossy(data)),
+                Sent::Message if observer.redact_message() => {
+                    format!("<message, {} bytes>", data.len())
+                }
+                _ => lossy(data),
+            };
+            observer.command(timestamp, &text);
+        }
+    }
+
+    pub(super) fn received(&self, response: &str) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("<< {}", escape_crlf(response));
+
+        if let Some(observer) = &self.observer {
+            observer.response(SystemTime::now(), response.trim_end_matches("\r\n"));
+        }
+    }
+}
+
+/// Decodes data written to the server, without the final CRLF
+fn lossy(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches("\r\n")
+        .to_string()
+}
+
+/// Keeps the `AUTH` keyword and mechanism of a command, and redacts the rest
+///
+/// Continuation lines of the authentication exchange are redacted entirely.
+fn redact_auth(command: &str) -> String {
+    let mut words = command.split(' ');
+    match (words.next(), words.next(), words.next()) {
+        (Some(auth), Some(mechanism), rest) if auth.eq_ignore_ascii_case("AUTH") => match rest {
+            Some(_) => format!("{} {} {}", auth, mechanism, REDACTED),
+            None => format!("{} {}", auth, mechanism),
+        },
+        _ => REDACTED.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn redact_auth_commands() {
+        assert_eq!(
+            redact_auth("AUTH PLAIN AHVzZXIAcGFzc3dvcmQ="),
+            "AUTH PLAIN <redacted>"
+        );
+        assert_eq!(redact_auth("AUTH LOGIN"), "AUTH LOGIN");
+        assert_eq!(redact_auth("dXNlcg=="), "<redacted>");
+    }
+
+    #[test]
+    fn redaction() {
+        let transcript = Transcript::new();
+        let log = SessionLog::new(Some(Arc::new(transcript.clone())));
+        log.received("220 ready\r\n");
+        log.sent(Sent::Credentials, b"AUTH PLAIN AHVzZXIAcGFzc3dvcmQ=\r\n");
+        log.sent(Sent::Message, b"Subject: test\r\n\r\nbody\r\n.\r\n");
+        log.sent(Sent::Command, b"QUIT\r\n");
+
+        let texts: Vec<_> = transcript
+            .entries()
+            .iter()
+            .map(|entry| (entry.direction(), entry.text().to_string()))
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                (Direction::Server, "220 ready".to_string()),
+                (Direction::Client, "AUTH PLAIN <redacted>".to_string()),
+                (Direction::Client, "<message, 26 bytes>".to_string()),
+                (Direction::Client, "QUIT".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_redaction() {
+        let transcript = Transcript::new().show_credentials().show_message();
+        let log = SessionLog::new(Some(Arc::new(transcript.clone())));
+        log.sent(Sent::Credentials, b"AUTH PLAIN AHVzZXIAcGFzc3dvcmQ=\r\n");
+        log.sent(Sent::Message, b"body\r\n.\r\n");
+
+        let entries = transcript.entries();
+        assert_eq!(entries[0].text(), "AUTH PLAIN AHVzZXIAcGFzc3dvcmQ=");
+        assert_eq!(entries[1].text(), "body\r\n.");
+    }
+
+    #[test]
+    fn display() {
+        let entry = TranscriptEntry {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_600_000_000_042),
+            direction: Direction::Server,
+            text: "250-example.com\r\n250 8BITMIME".to_string(),
+        };
+        assert_eq!(
+            entry.to_string(),
+            "1600000000.042 S: 250-example.com\n1600000000.042 S: 250 8BITMIME"
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Minimal X.509 certificate parsing
+//!
+//! Only extracts the few fields lettre needs from DER encoded certificates,
+//! without depending on the TLS backend.
+
+use std::{
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_OID: u8 = 0x06;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+
+/// The fields of the `TBSCertificate` lettre is interested in
+struct TbsCertificate<'a> {
+    validity: DerElement<'a>,
+    subject: DerElement<'a>,
+    subject_public_key_info: DerElement<'a>,
+}
+
+impl<'a> TbsCertificate<'a> {
+    fn parse(certificate: &'a [u8]) -> Option<Self> {
+        let (certificate, _) = DerElement::read(certificate)?;
+        let (tbs_certificate, _) = DerElement::read(certificate.content)?;
+        let mut fields = tbs_certificate.content;
+        // Skip the optional version
+        if fields.first() == Some(&0xa0) {
+            fields = DerElement::read(fields)?.1;
+        }
+        // Skip the serial number, signature algorithm and issuer
+        for _ in 0..3 {
+            fields = DerElement::read(fields)?.1;
+        }
+        let (validity, fields) = DerElement::read(fields)?;
+        let (subject, fields) = DerElement::read(fields)?;
+        let (subject_public_key_info, _) = DerElement::read(fields)?;
+
+        if [&validity, &subject, &subject_public_key_info]
+            .iter()
+            .any(|element| element.tag != TAG_SEQUENCE)
+        {
+            return None;
+        }
+        Some(Self {
+            validity,
+            subject,
+            subject_public_key_info,
+        })
+    }
+}
+
+/// Computes the SHA-256 hash of the `SubjectPublicKeyInfo` of a DER encoded certificate
+pub(super) fn spki_sha256(certificate: &[u8]) -> Option<[u8; 32]> {
+    use sha2::{Digest, Sha256};
+
+    let spki = TbsCertificate::parse(certificate)?.subject_public_key_info;
+
+    let mut fingerprint = [0; 32];
+    fingerprint.copy_from_slice(&Sha256::digest(spki.bytes));
+    Some(fingerprint)
+}
+
+/// Formats the subject of a DER encoded certificate as a RFC 4514 string,
+/// like `CN=mail.example.com,O=Example`
+pub(super) fn subject(certificate: &[u8]) -> Option<String> {
+    let subject = TbsCertificate::parse(certificate)?.subject;
+
+    // RFC 4514 lists the relative distinguished names in reverse order
+    let mut rdns = Vec::new();
+    let mut input = subject.content;
+    while !input.is_empty() {
+        let (rdn, rest) = DerElement::read(input)?;
+        if rdn.tag != TAG_SET {
+            return None;
+        }
+        rdns.push(rdn);
+        input = rest;
+    }
+
+    let mut formatted = String::new();
+    for (i, rdn) in rdns.iter().rev().enumerate() {
+        if i > 0 {
+            formatted.push(',');
+        }
+        let mut input = rdn.content;
+        let mut first = true;
+        while !input.is_empty() {
+            let (attribute, rest) = DerElement::read(input)?;
+            let (oid, value) = DerElement::read(attribute.content)?;
+            let (value, _) = DerElement::read(value)?;
+            if oid.tag != TAG_OID {
+                return None;
+            }
+            if !first {
+                formatted.push('+');
+            }
+            first = false;
+            format_attribute(&mut formatted, oid.content, &value)?;
+            input = rest;
+        }
+    }
+    Some(formatted)
+}
+
+/// Returns the `notBefore` and `notAfter` dates of a DER encoded certificate
+pub(super) fn validity(certificate: &[u8]) -> Option<(SystemTime, SystemTime)> {
+    let validity = TbsCertificate::parse(certificate)?.validity;
+
+    let (not_before, rest) = DerElement::read(validity.content)?;
+    let (not_after, _) = DerElement::read(rest)?;
+    Some((parse_time(&not_before)?, parse_time(&not_after)?))
+}
+
+fn format_attribute(out: &mut String, oid: &[u8], value: &DerElement<'_>) -> Option<()> {
+    let name = match oid {
+        [0x55, 0x04, 0x03] => "CN",
+        [0x55, 0x04, 0x06] => "C",
+        [0x55, 0x04, 0x07] => "L",
+        [0x55, 0x04, 0x08] => "ST",
+        [0x55, 0x04, 0x09] => "STREET",
+        [0x55, 0x04, 0x0a] => "O",
+        [0x55, 0x04, 0x0b] => "OU",
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x01] => "UID",
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x19] => "DC",
+        _ => {
+            // Unknown attribute types are written as a dotted OID
+            // with the BER encoded value in hexadecimal
+            format_oid(out, oid)?;
+            out.push_str("=#");
+            for byte in value.bytes {
+                let _ = write!(out, "{:02x}", byte);
+            }
+            return Some(());
+        }
+    };
+
+    out.push_str(name);
+    out.push('=');
+    // UTF8String, PrintableString, TeletexString and IA5String
+    if !matches!(value.tag, 0x0c | 0x13 | 0x14 | 0x16) {
+        return None;
+    }
+    let value = String::from_utf8_lossy(value.content);
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        let escape = matches!(c, '"' | '+' | ',' | ';' | '<' | '>' | '\\')
+            || (i == 0 && matches!(c, ' ' | '#'))
+            || (i == last && c == ' ');
+        if escape {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    Some(())
+}
+
+fn format_oid(out: &mut String, oid: &[u8]) -> Option<()> {
+    let (first, rest) = oid.split_first()?;
+    let _ = write!(out, "{}.{}", first / 40, first % 40);
+
+    let mut arc: u64 = 0;
+    for byte in rest {
+        arc = arc.checked_mul(128)? | u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            let _ = write!(out, ".{}", arc);
+            arc = 0;
+        }
+    }
+    Some(())
+}
+
+/// Parses an `UTCTime` or a `GeneralizedTime` in the `YYMMDDHHMMSSZ` and
+/// `YYYYMMDDHHMMSSZ` forms required by RFC 5280
+fn parse_time(time: &DerElement<'_>) -> Option<SystemTime> {
+    let digits = match (time.tag, time.content.split_last()) {
+        (TAG_UTC_TIME, Some((b'Z', digits))) if digits.len() == 12 => digits,
+        (TAG_GENERALIZED_TIME, Some((b'Z', digits))) if digits.len() == 14 => digits,
+        _ => return None,
+    };
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let number = |digits: &[u8]| {
+        digits
+            .iter()
+            .fold(0, |n, digit| n * 10 + i64::from(digit - b'0'))
+    };
+
+    let (year, digits) = if time.tag == TAG_UTC_TIME {
+        // Two digit years are in the 1950-2049 range
+        let year = number(&digits[..2]);
+        (
+            if year < 50 { 2000 + year } else { 1900 + year },
+            &digits[2..],
+        )
+    } else {
+        (number(&digits[..4]), &digits[4..])
+    };
+    let month = number(&digits[0..2]);
+    let day = number(&digits[2..4]);
+    let hour = number(&digits[4..6]);
+    let minute = number(&digits[6..8]);
+    let second = number(&digits[8..10]);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    if seconds >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs((-seconds) as u64))
+    }
+}
+
+/// Number of days between 1970-01-01 and the given date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// A DER encoded element
+struct DerElement<'a> {
+    tag: u8,
+    /// The whole element, including tag and length
+    bytes: &'a [u8],
+    content: &'a [u8],
+}
+
+impl<'a> DerElement<'a> {
+    /// Reads an element from the start of `input`, returning it with the remaining input
+    fn read(input: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        let tag = *input.first()?;
+        let first_length_byte = *input.get(1)? as usize;
+        let (length, header_length) = if first_length_byte < 0x80 {
+            (first_length_byte, 2)
+        } else {
+            let length_bytes = first_length_byte & 0x7f;
+            if length_bytes == 0 || length_bytes > 4 {
+                return None;
+            }
+            let mut length = 0;
+            for i in 0..length_bytes {
+                length = (length << 8) | *input.get(2 + i)? as usize;
+            }
+            (length, 2 + length_bytes)
+        };
+
+        let end = header_length.checked_add(length)?;
+        if input.len() < end {
+            return None;
+        }
+        let element = DerElement {
+            tag,
+            bytes: &input[..end],
+            content: &input[header_length..end],
+        };
+        Some((element, &input[end..]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLIENT_CERT: &[u8] = include_bytes!("../../../../testdata/client.crt");
+
+    fn client_cert_der() -> Vec<u8> {
+        let pem = String::from_utf8(CLIENT_CERT.to_vec()).unwrap();
+        let base64: String = pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        base64::decode(base64).unwrap()
+    }
+
+    #[test]
+    fn spki_fingerprint() {
+        assert_eq!(
+            base64::encode(spki_sha256(&client_cert_der()).unwrap()),
+            "Id8bmEj9PsrvPrwNyQE8QuMrQHx0t0E0siqiwQMeloY="
+        );
+        assert_eq!(spki_sha256(b"not a certificate"), None);
+    }
+
+    #[test]
+    fn certificate_subject() {
+        assert_eq!(
+            subject(&client_cert_der()).unwrap(),
+            "CN=lettre test client"
+        );
+        assert_eq!(subject(b"not a certificate"), None);
+    }
+
+    #[test]
+    fn certificate_validity() {
+        let (not_before, not_after) = validity(&client_cert_der()).unwrap();
+        // UTCTime
+        assert_eq!(not_before, UNIX_EPOCH + Duration::from_secs(1_792_335_409));
+        // GeneralizedTime
+        assert_eq!(not_after, UNIX_EPOCH + Duration::from_secs(4_945_935_409));
+    }
+
+    #[test]
+    fn times() {
+        let time = |tag, content: &'static [u8]| {
+            parse_time(&DerElement {
+                tag,
+                bytes: &[],
+                content,
+            })
+        };
+
+        assert_eq!(time(TAG_UTC_TIME, b"700101000000Z"), Some(UNIX_EPOCH));
+        assert_eq!(
+            time(TAG_UTC_TIME, b"491231235959Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(2_524_607_999))
+        );
+        assert_eq!(
+            time(TAG_GENERALIZED_TIME, b"19691231235959Z"),
+            Some(UNIX_EPOCH - Duration::from_secs(1))
+        );
+        assert_eq!(time(TAG_UTC_TIME, b"700101000000+0100"), None);
+        assert_eq!(time(TAG_GENERALIZED_TIME, b"700101000000Z"), None);
+    }
+
+    #[test]
+    fn subject_attributes() {
+        let mut formatted = String::new();
+        let value = DerElement {
+            tag: 0x0c,
+            bytes: &[],
+            content: b" Example, Inc.",
+        };
+        format_attribute(&mut formatted, &[0x55, 0x04, 0x0a], &value).unwrap();
+        assert_eq!(formatted, "O=\\ Example\\, Inc.");
+
+        let mut formatted = String::new();
+        let value = DerElement {
+            tag: 0x16,
+            bytes: &[0x16, 0x01, 0x61],
+            content: b"a",
+        };
+        // emailAddress
+        let oid = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01];
+        format_attribute(&mut formatted, &oid, &value).unwrap();
+        assert_eq!(formatted, "1.2.840.113549.1.9.1=#160161");
+    }
+}
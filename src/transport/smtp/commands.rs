@@ -222,7 +222,14 @@ impl Display for Auth {
         let encoded_response = self.response.as_ref().map(base64::encode);
 
         if self.mechanism.supports_initial_response() {
-            write!(f, "AUTH {} {}", self.mechanism, encoded_response.unwrap())?;
+            // An empty initial response is sent as a single "=" (RFC 4954)
+            let encoded_response = encoded_response.unwrap();
+            let encoded_response = if encoded_response.is_empty() {
+                "="
+            } else {
+                encoded_response.as_str()
+            };
+            write!(f, "AUTH {} {}", self.mechanism, encoded_response)?;
         } else {
             match encoded_response {
                 Some(response) => f.write_str(&response)?,
@@ -370,5 +377,17 @@ mod test {
             ),
             "AUTH LOGIN\r\n"
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Auth::new(
+                    Mechanism::External,
+                    Credentials::new("".to_string(), "".to_string()),
+                    None
+                )
+                .unwrap()
+            ),
+            "AUTH EXTERNAL =\r\n"
+        );
     }
 }
@@ -1,7 +1,10 @@
 //! Error and result type for SMTP clients
 
 use self::Error::*;
-use crate::transport::smtp::response::{Response, Severity};
+use crate::transport::{
+    metrics::FailureClass,
+    smtp::response::{Response, Severity},
+};
 use base64::DecodeError;
 use std::{
     error::Error as StdError,
@@ -46,11 +49,31 @@ pub enum Error {
     #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
     #[cfg_attr(docsrs, doc(cfg(any(feature = "native-tls", feature = "rustls-tls"))))]
     InvalidCertificate,
+    /// The server public key does not match any of the pinned keys
+    ///
+    /// Contains the SHA-256 hash of the `SubjectPublicKeyInfo` presented by the server.
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "native-tls", feature = "rustls-tls"))))]
+    PinMismatch([u8; 32]),
     #[cfg(feature = "r2d2")]
     #[cfg_attr(docsrs, doc(cfg(feature = "r2d2")))]
     Pool(r2d2::Error),
 }
 
+impl Error {
+    /// Tells if the server rejected the message temporarily (4xx)
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Transient(_))
+    }
+
+    /// Tells if the server rejected the message permanently (5xx)
+    ///
+    /// Sending the same message again will fail the same way.
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, Permanent(_))
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), fmt::Error> {
         match *self {
@@ -77,6 +100,12 @@ impl Display for Error {
             InvalidDNSName(ref err) => err.fmt(fmt),
             #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
             InvalidCertificate => fmt.write_str("invalid certificate"),
+            #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+            PinMismatch(ref fingerprint) => write!(
+                fmt,
+                "server public key does not match any pin (sha256/{})",
+                base64::encode(fingerprint)
+            ),
             #[cfg(feature = "r2d2")]
             Pool(ref err) => err.fmt(fmt),
         }
@@ -96,6 +125,26 @@ impl StdError for Error {
     }
 }
 
+impl FailureClass for Error {
+    fn failure_class(&self) -> &'static str {
+        match *self {
+            Transient(_) => "transient",
+            Permanent(_) => "permanent",
+            Resolution | Io(_) => "network",
+            #[cfg(feature = "native-tls")]
+            Tls(_) => "tls",
+            #[cfg(feature = "rustls-tls")]
+            InvalidDNSName(_) => "tls",
+            #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+            InvalidCertificate | PinMismatch(_) => "tls",
+            ResponseParsing(_) | ChallengeParsing(_) | Utf8Parsing(_) | Parsing(_) => "protocol",
+            #[cfg(feature = "r2d2")]
+            Pool(_) => "pool",
+            Client(_) => "client",
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         Io(err)
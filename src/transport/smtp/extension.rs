@@ -159,6 +159,9 @@ impl ServerInfo {
                             "XOAUTH2" => {
                                 features.insert(Extension::Authentication(Mechanism::Xoauth2));
                             }
+                            "EXTERNAL" => {
+                                features.insert(Extension::Authentication(Mechanism::External));
+                            }
                             _ => (),
                         }
                     }
@@ -0,0 +1,417 @@
+//! Sending through several relays
+//!
+//! A [`FailoverTransport`] holds one SMTP transport per relay, each one with its own
+//! connection pool and TLS settings. Relays are tried one after the other until one
+//! of them accepts the message.
+//!
+//! A relay which can't be reached, or which answers with a `421` reply, is considered
+//! down for a configurable cooldown period, during which it is only tried as a last resort.
+//! Errors related to the message itself (like a rejected recipient) are returned
+//! directly without trying other relays.
+//!
+//! Before a relay answers the `MAIL` command, connection errors make the next relay be
+//! tried, including those of a pooled connection the relay has closed. After that, only a
+//! `421` reply does. Other failures, like a connection lost after the message content was
+//! sent, are returned directly, as the relay may have accepted the message, and trying
+//! the next relay could deliver it twice.
+//!
+//! When the `r2d2` feature is enabled, a relay is only considered unreachable once
+//! its pool connection timeout (`PoolConfig::connection_timeout`) has expired,
+//! so it should be kept short.
+//!
+//! ```rust,no_run
+//! # #[cfg(all(feature = "builder", any(feature = "native-tls", feature = "rustls-tls")))]
+//! # fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! use lettre::{
+//!     transport::smtp::failover::RelayStrategy, Message, SmtpTransport, Transport,
+//! };
+//! use std::time::Duration;
+//!
+//! let email = Message::builder()
+//!     .from("NoBody <nobody@domain.tld>".parse()?)
+//!     .to("Hei <hei@domain.tld>".parse()?)
+//!     .subject("Happy new year")
+//!     .body(String::from("Be happy!"))?;
+//!
+//! let sender = SmtpTransport::failover()
+//!     .relay(SmtpTransport::relay("smtp1.example.com")?)
+//!     .relay(SmtpTransport::relay("smtp2.example.com")?)
+//!     .strategy(RelayStrategy::RoundRobin)
+//!     .cooldown(Duration::from_secs(30))
+//!     .build();
+//!
+//! let response = sender.send(&email)?;
+//! println!("accepted by {}", response.relay());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+use async_trait::async_trait;
+
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+use super::client::TlsInfo;
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+use super::{AsyncSmtpTransport, AsyncSmtpTransportBuilder};
+use super::{Error, Response, SendResponse, SmtpTransport, SmtpTransportBuilder};
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+use crate::{AsyncTransport, Executor};
+use crate::{Envelope, Transport};
+
+/// Default duration during which a failing relay is considered down
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Order in which relays are tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayStrategy {
+    /// Always start with the first relay, and only use the next ones when it fails
+    Ordered,
+    /// Start with a different relay for each message, spreading the load
+    RoundRobin,
+}
+
+/// Response of the relay which accepted the message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayResponse {
+    relay: String,
+    response: SendResponse,
+}
+
+impl RelayResponse {
+    pub(crate) fn new(relay: String, response: SendResponse) -> Self {
+        Self { relay, response }
+    }
+
+    /// Server name of the relay which accepted the message
+    pub fn relay(&self) -> &str {
+        &self.relay
+    }
+
+    /// Response of the relay to the message content
+    pub fn response(&self) -> &Response {
+        self.response.response()
+    }
+
+    /// Details of the TLS session the message was sent over,
+    /// or `None` if the connection was not encrypted
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "native-tls", feature = "rustls-tls"))))]
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.response.tls_info()
+    }
+}
+
+struct Relay<T> {
+    server: String,
+    transport: T,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl<T> Relay<T> {
+    fn is_down(&self, now: Instant) -> bool {
+        matches!(*self.down_until.lock().unwrap(), Some(until) if until > now)
+    }
+
+    fn mark_down(&self, cooldown: Duration) {
+        *self.down_until.lock().unwrap() = Some(Instant::now() + cooldown);
+    }
+
+    fn mark_up(&self) {
+        *self.down_until.lock().unwrap() = None;
+    }
+}
+
+/// Transport sending through several relays
+///
+/// Instances can be created with [`SmtpTransport::failover`] or `AsyncSmtpTransport::failover`.
+#[allow(missing_debug_implementations)]
+pub struct FailoverTransport<T> {
+    relays: Arc<Vec<Relay<T>>>,
+    strategy: RelayStrategy,
+    cooldown: Duration,
+    next: Arc<AtomicUsize>,
+}
+
+impl<T> Clone for FailoverTransport<T> {
+    fn clone(&self) -> Self {
+        Self {
+            relays: Arc::clone(&self.relays),
+            strategy: self.strategy,
+            cooldown: self.cooldown,
+            next: Arc::clone(&self.next),
+        }
+    }
+}
+
+impl<T> FailoverTransport<T> {
+    /// Indexes of the relays in the order they should be tried
+    ///
+    /// Relays which are down come last, so that they are still tried
+    /// when all relays are down.
+    fn candidates(&self) -> Vec<usize> {
+        let len = self.relays.len();
+        let start = match self.strategy {
+            RelayStrategy::Ordered => 0,
+            RelayStrategy::RoundRobin if len > 0 => self.next.fetch_add(1, Ordering::Relaxed) % len,
+            RelayStrategy::RoundRobin => 0,
+        };
+
+        let now = Instant::now();
+        let (up, down): (Vec<usize>, Vec<usize>) = (0..len)
+            .map(|offset| (start + offset) % len)
+            .partition(|idx| !self.relays[*idx].is_down(now));
+        up.into_iter().chain(down).collect()
+    }
+
+    /// Handles the result of an attempt on the relay at `idx`
+    ///
+    /// Returns `None` when the next relay should be tried.
+    fn attempt_result(
+        &self,
+        idx: usize,
+        (result, started): (Result<SendResponse, Error>, bool),
+        last_error: &mut Option<Error>,
+    ) -> Option<Result<RelayResponse, Error>> {
+        let relay = &self.relays[idx];
+        match result {
+            Ok(response) => {
+                relay.mark_up();
+                Some(Ok(RelayResponse::new(relay.server.clone(), response)))
+            }
+            Err(err) if is_relay_failure(&err, started) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("relay {} failed: {}", relay.server, err);
+                relay.mark_down(self.cooldown);
+                *last_error = Some(err);
+                None
+            }
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// Tells if the error is caused by the relay rather than by the message
+///
+/// `started` tells if the relay answered the `MAIL` command, after which the message
+/// may have reached it. Before that, even an I/O error on a pooled connection means the
+/// message wasn't transmitted.
+fn is_relay_failure(err: &Error, started: bool) -> bool {
+    match err {
+        // The relay is closing the session, without accepting the message
+        Error::Transient(response) => response.has_code(421),
+        _ if started => false,
+        Error::Io(_) | Error::Resolution => true,
+        #[cfg(feature = "native-tls")]
+        Error::Tls(_) => true,
+        #[cfg(feature = "r2d2")]
+        Error::Pool(_) => true,
+        _ => false,
+    }
+}
+
+impl Transport for FailoverTransport<SmtpTransport> {
+    type Ok = RelayResponse;
+    type Error = Error;
+
+    /// Sends an email through the first available relay
+    fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
+        let mut last_error = None;
+        for idx in self.candidates() {
+            let result = self.relays[idx].transport.send_attempt(envelope, email);
+            if let Some(result) = self.attempt_result(idx, result, &mut last_error) {
+                return result;
+            }
+        }
+        Err(last_error.unwrap_or(Error::Client("No relay configured")))
+    }
+}
+
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+#[async_trait]
+impl<E> AsyncTransport for FailoverTransport<AsyncSmtpTransport<E>>
+where
+    E: Executor,
+    AsyncSmtpTransport<E>: AsyncTransport<Ok = SendResponse, Error = Error>,
+{
+    type Ok = RelayResponse;
+    type Error = Error;
+
+    /// Sends an email through the first available relay
+    async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
+        let mut last_error = None;
+        for idx in self.candidates() {
+            let result = self.relays[idx]
+                .transport
+                .send_attempt(envelope, email)
+                .await;
+            if let Some(result) = self.attempt_result(idx, result, &mut last_error) {
+                return result;
+            }
+        }
+        Err(last_error.unwrap_or(Error::Client("No relay configured")))
+    }
+}
+
+/// Builder for [`FailoverTransport`]
+#[allow(missing_debug_implementations)]
+pub struct FailoverTransportBuilder<T> {
+    relays: Vec<Relay<T>>,
+    strategy: RelayStrategy,
+    cooldown: Duration,
+}
+
+impl<T> FailoverTransportBuilder<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            relays: Vec::new(),
+            strategy: RelayStrategy::Ordered,
+            cooldown: DEFAULT_COOLDOWN,
+        }
+    }
+
+    fn push_relay(mut self, server: &str, transport: T) -> Self {
+        self.relays.push(Relay {
+            server: server.to_string(),
+            transport,
+            down_until: Mutex::new(None),
+        });
+        self
+    }
+
+    /// Set the order in which relays are tried
+    ///
+    /// Defaults to [`RelayStrategy::Ordered`]
+    pub fn strategy(mut self, strategy: RelayStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set the duration during which a failing relay is considered down
+    ///
+    /// Defaults to 60 seconds
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Build the transport
+    pub fn build(self) -> FailoverTransport<T> {
+        FailoverTransport {
+            relays: Arc::new(self.relays),
+            strategy: self.strategy,
+            cooldown: self.cooldown,
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl FailoverTransportBuilder<SmtpTransport> {
+    /// Add a relay, tried after the previously added ones
+    ///
+    /// Each relay gets its own connection pool.
+    pub fn relay(self, relay: SmtpTransportBuilder) -> Self {
+        let server = relay.server().to_string();
+        self.push_relay(&server, relay.build())
+    }
+}
+
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+impl<E> FailoverTransportBuilder<AsyncSmtpTransport<E>>
+where
+    E: Executor,
+{
+    /// Add a relay, tried after the previously added ones
+    pub fn relay(self, relay: AsyncSmtpTransportBuilder) -> Self {
+        let server = relay.server().to_string();
+        self.push_relay(&server, relay.build())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::smtp::response::{Category, Code, Detail, Severity};
+
+    fn transport(strategy: RelayStrategy) -> FailoverTransport<SmtpTransport> {
+        SmtpTransport::failover()
+            .relay(SmtpTransport::builder_dangerous("relay1.example.com"))
+            .relay(SmtpTransport::builder_dangerous("relay2.example.com"))
+            .relay(SmtpTransport::builder_dangerous("relay3.example.com"))
+            .strategy(strategy)
+            .build()
+    }
+
+    #[test]
+    fn ordered_candidates() {
+        let transport = transport(RelayStrategy::Ordered);
+        assert_eq!(transport.candidates(), vec![0, 1, 2]);
+        assert_eq!(transport.candidates(), vec![0, 1, 2]);
+
+        transport.relays[0].mark_down(Duration::from_secs(60));
+        assert_eq!(transport.candidates(), vec![1, 2, 0]);
+
+        transport.relays[0].mark_up();
+        assert_eq!(transport.candidates(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn round_robin_candidates() {
+        let transport = transport(RelayStrategy::RoundRobin);
+        assert_eq!(transport.candidates(), vec![0, 1, 2]);
+        assert_eq!(transport.candidates(), vec![1, 2, 0]);
+
+        transport.relays[0].mark_down(Duration::from_secs(60));
+        assert_eq!(transport.candidates(), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn cooldown_expires() {
+        let transport = transport(RelayStrategy::Ordered);
+        transport.relays[0].mark_down(Duration::from_secs(0));
+        assert_eq!(transport.candidates(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn relay_failures() {
+        let response = |severity, detail| {
+            Response::new(Code::new(severity, Category::Connections, detail), vec![])
+        };
+
+        let closing =
+            || Error::Transient(response(Severity::TransientNegativeCompletion, Detail::One));
+        assert!(is_relay_failure(&closing(), false));
+        assert!(is_relay_failure(&closing(), true));
+        assert!(!is_relay_failure(
+            &Error::Transient(response(
+                Severity::TransientNegativeCompletion,
+                Detail::Zero
+            )),
+            false
+        ));
+        assert!(!is_relay_failure(
+            &Error::Permanent(response(Severity::PermanentNegativeCompletion, Detail::One)),
+            false
+        ));
+        assert!(is_relay_failure(&Error::Resolution, false));
+
+        // The message may have reached the relay
+        let lost = || Error::Io(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        assert!(is_relay_failure(&lost(), false));
+        assert!(!is_relay_failure(&lost(), true));
+    }
+
+    #[test]
+    fn no_relay() {
+        let transport = SmtpTransport::failover().build();
+        let envelope = Envelope::new(None, vec!["root@localhost".parse().unwrap()]).unwrap();
+        assert!(transport.send_raw(&envelope, b"test").is_err());
+    }
+}
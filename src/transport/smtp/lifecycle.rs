@@ -0,0 +1,85 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use super::Error;
+
+/// Interval between two checks for in-flight sends during shutdown
+pub(crate) const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Shared shutdown state of a transport
+///
+/// Keeps track of whether the transport still accepts new sends and of
+/// how many sends are currently in progress.
+#[derive(Debug, Default)]
+pub(crate) struct Lifecycle {
+    closed: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+impl Lifecycle {
+    /// Registers a new send, failing if the transport has been shut down
+    ///
+    /// The send is considered in progress until the returned guard is dropped.
+    pub(crate) fn begin(self: &Arc<Self>) -> Result<InFlight, Error> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlight(Arc::clone(self));
+
+        if self.is_closed() {
+            return Err(Error::Client("transport has been shut down"));
+        }
+        Ok(guard)
+    }
+
+    /// Stops accepting new sends
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Number of sends currently in progress
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+/// Guard representing a send in progress
+#[derive(Debug)]
+pub(crate) struct InFlight(Arc<Lifecycle>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn in_flight_accounting() {
+        let lifecycle = Arc::new(Lifecycle::default());
+
+        let first = lifecycle.begin().unwrap();
+        let second = lifecycle.begin().unwrap();
+        assert_eq!(lifecycle.in_flight(), 2);
+
+        drop(first);
+        assert_eq!(lifecycle.in_flight(), 1);
+
+        lifecycle.close();
+        assert!(lifecycle.begin().is_err());
+        assert_eq!(lifecycle.in_flight(), 1);
+
+        drop(second);
+        assert_eq!(lifecycle.in_flight(), 0);
+    }
+}
@@ -11,6 +11,9 @@
 //! * AUTH ([RFC 4954](https://tools.ietf.org/html/rfc4954)) with PLAIN, LOGIN and XOAUTH2 mechanisms
 //! * STARTTLS ([RFC 2487](https://tools.ietf.org/html/rfc2487))
 //!
+//! Messages can be sent through several relays with failover, see the [`failover`] module,
+//! or directly to the recipients' mail exchangers, see the [`mx`] module.
+//!
 //! #### SMTP Transport
 //!
 //! This transport uses the SMTP protocol to send emails over the network (locally or remotely).
@@ -126,15 +129,18 @@ pub use self::pool::PoolConfig;
 pub(crate) use self::transport::SmtpClient;
 pub use self::{
     error::Error,
-    transport::{SmtpTransport, SmtpTransportBuilder},
+    transport::{SendResponse, SmtpTransport, SmtpTransportBuilder},
 };
 #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
 use crate::transport::smtp::client::TlsParameters;
-use crate::transport::smtp::{
-    authentication::{Credentials, Mechanism, DEFAULT_MECHANISMS},
-    client::SmtpConnection,
-    extension::ClientId,
-    response::Response,
+use crate::transport::{
+    instrument::SendScope,
+    smtp::{
+        authentication::{Credentials, Mechanism, DEFAULT_MECHANISMS},
+        client::{ConnectionOptions, SmtpConnection},
+        extension::ClientId,
+        response::Response,
+    },
 };
 use client::Tls;
 use std::time::Duration;
@@ -159,9 +165,17 @@ pub mod client;
 pub mod commands;
 mod error;
 pub mod extension;
+pub mod failover;
+mod lifecycle;
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+pub mod mta_sts;
+pub mod mx;
 #[cfg(feature = "r2d2")]
 mod pool;
 pub mod response;
+#[cfg(feature = "smtp-test-server")]
+#[cfg_attr(docsrs, doc(cfg(feature = "smtp-test-server")))]
+pub mod test_server;
 mod transport;
 pub mod util;
 
@@ -186,8 +200,10 @@ pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
 struct SmtpInfo {
     /// Name sent during EHLO
     hello_name: ClientId,
-    /// Server we are connecting to
+    /// Name of the server, used to verify its certificate
     server: String,
+    /// Host to connect to, if different from the server name
+    connect_to: Option<String>,
     /// Port to connect to
     port: u16,
     /// TLS security configuration
@@ -196,21 +212,59 @@ struct SmtpInfo {
     authentication: Vec<Mechanism>,
     /// Credentials
     credentials: Option<Credentials>,
-    /// Define network timeout
-    /// It can be changed later for specific needs (like a different timeout for each SMTP command)
-    timeout: Option<Duration>,
+    /// Network timeout, local address and session observer of the connections
+    connection: ConnectionOptions,
 }
 
 impl Default for SmtpInfo {
     fn default() -> Self {
         Self {
             server: "localhost".to_string(),
+            connect_to: None,
             port: SMTP_PORT,
             hello_name: ClientId::default(),
             credentials: None,
             authentication: DEFAULT_MECHANISMS.into(),
-            timeout: Some(DEFAULT_TIMEOUT),
             tls: Tls::None,
+            connection: ConnectionOptions::new().timeout(Some(DEFAULT_TIMEOUT)),
         }
     }
 }
+
+impl SmtpInfo {
+    /// Host to connect to, without the brackets around IP address literals like `[::1]`
+    fn host(&self) -> &str {
+        unbracket(self.connect_to.as_ref().unwrap_or(&self.server))
+    }
+}
+
+/// Records the reply code of a send, from the server response or the SMTP error
+fn record_response_code(scope: &SendScope, result: Result<&Response, &Error>) {
+    match result {
+        Ok(response) | Err(Error::Transient(response)) | Err(Error::Permanent(response)) => {
+            scope.record_code(response.code)
+        }
+        Err(_) => {}
+    }
+}
+
+/// Removes the brackets around an IP address literal like `[::1]`
+fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+#[cfg(test)]
+mod test {
+    use super::unbracket;
+
+    #[test]
+    fn unbracket_ip_literals() {
+        assert_eq!(unbracket("[::1]"), "::1");
+        assert_eq!(unbracket("[127.0.0.1]"), "127.0.0.1");
+        assert_eq!(unbracket("::1"), "::1");
+        assert_eq!(unbracket("smtp.example.com"), "smtp.example.com");
+        assert_eq!(unbracket("[::1"), "[::1");
+    }
+}
@@ -0,0 +1,570 @@
+//! MTA-STS ([RFC 8461](https://tools.ietf.org/html/rfc8461)) support for direct delivery
+//!
+//! MTA-STS lets a domain publish a policy requiring mail exchangers to be reached over TLS,
+//! with a valid certificate, and restricting the hosts that can be used as mail exchangers.
+//!
+//! Policies are obtained through the [`PolicyFetcher`] trait and stored in a [`PolicyCache`].
+//! A fetcher using the system DNS configuration and HTTPS is available with the
+//! `trust-dns-resolver` feature, and [`StaticPolicyFetcher`] serves fixed policies,
+//! which is useful for tests.
+//!
+//! MTA-STS is enabled on an [`MxTransport`](super::mx::MxTransport) with
+//! [`MxTransportBuilder::mta_sts`](super::mx::MxTransportBuilder::mta_sts).
+//! With a policy in `enforce` mode, only the mail exchangers matching the policy are used,
+//! and messages are only delivered over TLS with a valid certificate.
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "builder")]
+//! # fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! use lettre::transport::smtp::{
+//!     mta_sts::StaticPolicyFetcher,
+//!     mx::{MxTransport, StaticResolver},
+//! };
+//!
+//! let resolver = StaticResolver::new()
+//!     .mx("example.com", 10, "mx.example.com")
+//!     .ip("mx.example.com", "192.0.2.25".parse()?);
+//! let fetcher = StaticPolicyFetcher::new().policy(
+//!     "example.com",
+//!     "20210101",
+//!     "version: STSv1\nmode: enforce\nmx: *.example.com\nmax_age: 86400\n",
+//! )?;
+//!
+//! let sender = MxTransport::builder(resolver).mta_sts(fetcher).build();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use super::Error;
+
+/// Maximum `max_age` of a policy, about one year
+const MAX_POLICY_AGE: Duration = Duration::from_secs(31_557_600);
+
+/// How the sending server should behave when the policy is not satisfied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Don't deliver to mail exchangers which don't satisfy the policy
+    Enforce,
+    /// Deliver anyway, policy failures are only reported
+    Testing,
+    /// The domain doesn't have an active policy anymore
+    None,
+}
+
+/// A MTA-STS policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    mode: Mode,
+    mx: Vec<String>,
+    max_age: Duration,
+}
+
+impl Policy {
+    /// Parses a policy, in the format served at `https://mta-sts.<domain>/.well-known/mta-sts.txt`
+    pub fn parse(policy: &str) -> Result<Self, Error> {
+        let mut version = None;
+        let mut mode = None;
+        let mut max_age = None;
+        let mut mx = Vec::new();
+
+        for line in policy.lines() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = match line.find(':') {
+                Some(idx) => (line[..idx].trim(), line[idx + 1..].trim()),
+                None => return Err(Error::Client("Invalid MTA-STS policy")),
+            };
+            match key {
+                "version" => version = Some(value),
+                "mode" => {
+                    mode = Some(match value {
+                        "enforce" => Mode::Enforce,
+                        "testing" => Mode::Testing,
+                        "none" => Mode::None,
+                        _ => return Err(Error::Client("Invalid MTA-STS policy mode")),
+                    })
+                }
+                "max_age" => {
+                    let seconds = value
+                        .parse()
+                        .map_err(|_| Error::Client("Invalid MTA-STS policy max_age"))?;
+                    max_age = Some(Duration::from_secs(seconds).min(MAX_POLICY_AGE));
+                }
+                "mx" => mx.push(value.to_lowercase()),
+                // Unknown fields must be ignored
+                _ => {}
+            }
+        }
+
+        if version != Some("STSv1") {
+            return Err(Error::Client("Unsupported MTA-STS policy version"));
+        }
+        let mode = mode.ok_or(Error::Client("Missing MTA-STS policy mode"))?;
+        let max_age = max_age.ok_or(Error::Client("Missing MTA-STS policy max_age"))?;
+        if mode != Mode::None && mx.is_empty() {
+            return Err(Error::Client("Missing MTA-STS policy mx"));
+        }
+        Ok(Self { mode, mx, max_age })
+    }
+
+    /// Mode of the policy
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Patterns of the allowed mail exchangers, like `mail.example.com` or `*.example.net`
+    pub fn mx(&self) -> &[String] {
+        &self.mx
+    }
+
+    /// Duration the policy can be cached for
+    pub fn max_age(&self) -> Duration {
+        self.max_age
+    }
+
+    /// Tells if the mail exchanger host name matches one of the `mx` patterns of the policy
+    ///
+    /// A wildcard only matches a single label, `*.example.com` matches `mx.example.com`
+    /// but not `example.com` nor `a.mx.example.com`.
+    pub fn matches_mx(&self, exchange: &str) -> bool {
+        let exchange = exchange.trim_end_matches('.').to_lowercase();
+        self.mx.iter().any(|pattern| {
+            if let Some(suffix) = pattern.strip_prefix("*.") {
+                match exchange.find('.') {
+                    Some(idx) => idx > 0 && exchange[idx + 1..] == *suffix,
+                    None => false,
+                }
+            } else {
+                exchange == *pattern
+            }
+        })
+    }
+}
+
+/// Discovery and retrieval of MTA-STS policies
+pub trait PolicyFetcher: Send + Sync {
+    /// Returns the `id` of the `_mta-sts` TXT record of the domain
+    ///
+    /// `None` must be returned when the domain doesn't publish a policy.
+    fn policy_id(&self, domain: &str) -> Result<Option<String>, Error>;
+
+    /// Fetches the current policy of the domain
+    ///
+    /// Policies are served over HTTPS, with a valid certificate,
+    /// at `https://mta-sts.<domain>/.well-known/mta-sts.txt`.
+    fn fetch_policy(&self, domain: &str) -> Result<Policy, Error>;
+}
+
+/// A policy stored in a [`PolicyCache`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedPolicy {
+    /// `id` of the policy at the time it was fetched
+    pub id: String,
+    /// The policy
+    pub policy: Policy,
+    /// When the policy was fetched
+    pub fetched_at: SystemTime,
+}
+
+impl CachedPolicy {
+    /// Tells if the `max_age` of the policy has expired
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        match now.duration_since(self.fetched_at) {
+            Ok(age) => age > self.policy.max_age,
+            // The clock went backwards
+            Err(_) => false,
+        }
+    }
+}
+
+/// Storage for the fetched MTA-STS policies
+pub trait PolicyCache: Send + Sync {
+    /// Returns the policy stored for the domain
+    fn get(&self, domain: &str) -> Option<CachedPolicy>;
+
+    /// Stores the policy of the domain, replacing the previous one
+    fn insert(&self, domain: &str, policy: CachedPolicy);
+}
+
+/// Cache keeping policies in memory
+#[derive(Debug, Default)]
+pub struct MemoryPolicyCache {
+    policies: Mutex<HashMap<String, CachedPolicy>>,
+}
+
+impl MemoryPolicyCache {
+    /// Creates an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PolicyCache for MemoryPolicyCache {
+    fn get(&self, domain: &str) -> Option<CachedPolicy> {
+        self.policies.lock().unwrap().get(domain).cloned()
+    }
+
+    fn insert(&self, domain: &str, policy: CachedPolicy) {
+        self.policies
+            .lock()
+            .unwrap()
+            .insert(domain.to_string(), policy);
+    }
+}
+
+/// Fetcher serving fixed policies
+#[derive(Debug, Clone, Default)]
+pub struct StaticPolicyFetcher {
+    policies: HashMap<String, (String, Policy)>,
+}
+
+impl StaticPolicyFetcher {
+    /// Creates a fetcher without any policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the policy of a domain, with its `id`
+    pub fn policy<D: Into<String>, I: Into<String>>(
+        mut self,
+        domain: D,
+        id: I,
+        policy: &str,
+    ) -> Result<Self, Error> {
+        self.policies.insert(
+            domain.into().to_lowercase(),
+            (id.into(), Policy::parse(policy)?),
+        );
+        Ok(self)
+    }
+}
+
+impl PolicyFetcher for StaticPolicyFetcher {
+    fn policy_id(&self, domain: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .policies
+            .get(&domain.to_lowercase())
+            .map(|(id, _)| id.clone()))
+    }
+
+    fn fetch_policy(&self, domain: &str) -> Result<Policy, Error> {
+        self.policies
+            .get(&domain.to_lowercase())
+            .map(|(_, policy)| policy.clone())
+            .ok_or(Error::Client("No MTA-STS policy"))
+    }
+}
+
+/// Fetcher using the system DNS configuration and HTTPS
+#[cfg(feature = "trust-dns-resolver")]
+#[cfg_attr(docsrs, doc(cfg(feature = "trust-dns-resolver")))]
+#[allow(missing_debug_implementations)]
+pub struct SystemPolicyFetcher {
+    resolver: trust_dns_resolver::Resolver,
+    timeout: Option<Duration>,
+}
+
+#[cfg(feature = "trust-dns-resolver")]
+impl SystemPolicyFetcher {
+    /// Creates a fetcher from the system DNS configuration (`/etc/resolv.conf` on Unix)
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            resolver: trust_dns_resolver::Resolver::from_system_conf()?,
+            timeout: Some(super::DEFAULT_TIMEOUT),
+        })
+    }
+}
+
+#[cfg(feature = "trust-dns-resolver")]
+impl PolicyFetcher for SystemPolicyFetcher {
+    fn policy_id(&self, domain: &str) -> Result<Option<String>, Error> {
+        use trust_dns_resolver::error::ResolveErrorKind;
+
+        let lookup = match self
+            .resolver
+            .txt_lookup(format!("_mta-sts.{}.", domain.trim_end_matches('.')))
+        {
+            Ok(lookup) => lookup,
+            Err(err) => match err.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => return Ok(None),
+                _ => return Err(Error::Resolution),
+            },
+        };
+
+        let records: Vec<String> = lookup
+            .iter()
+            .map(|txt| {
+                txt.txt_data()
+                    .iter()
+                    .map(|data| String::from_utf8_lossy(data))
+                    .collect()
+            })
+            .filter(|record: &String| record.starts_with("v=STSv1"))
+            .collect();
+        // Several records mean no policy
+        match records.as_slice() {
+            [record] => Ok(parse_policy_id(record)),
+            _ => Ok(None),
+        }
+    }
+
+    fn fetch_policy(&self, domain: &str) -> Result<Policy, Error> {
+        use std::io::{Read, Write};
+
+        use super::client::{NetworkStream, TlsParameters};
+
+        /// Policies can't be larger than 64 KiB
+        const MAX_POLICY_SIZE: u64 = 64 * 1024;
+
+        let host = format!("mta-sts.{}", domain.trim_end_matches('.'));
+        let tls_parameters = TlsParameters::new(host.clone())?;
+        let mut stream = NetworkStream::connect(
+            (host.as_str(), 443),
+            self.timeout,
+            Some(&tls_parameters),
+            None,
+        )?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        // HTTP/1.0 avoids chunked responses
+        write!(
+            stream,
+            "GET /.well-known/mta-sts.txt HTTP/1.0\r\nHost: {}\r\n\r\n",
+            host
+        )?;
+        stream.flush()?;
+        let mut response = Vec::new();
+        // Leave room for the headers
+        stream
+            .take(MAX_POLICY_SIZE * 2)
+            .read_to_end(&mut response)?;
+
+        let response = String::from_utf8_lossy(&response);
+        let (head, body) = match response.find("\r\n\r\n") {
+            Some(idx) => (&response[..idx], &response[idx + 4..]),
+            None => return Err(Error::Client("Invalid MTA-STS policy response")),
+        };
+        let mut lines = head.split("\r\n");
+        // Redirects must not be followed
+        match lines.next().and_then(|status| status.split(' ').nth(1)) {
+            Some("200") => {}
+            _ => return Err(Error::Client("MTA-STS policy not found")),
+        }
+        let is_text = lines.any(|header| {
+            let header = header.to_ascii_lowercase();
+            header.starts_with("content-type:") && header[13..].trim().starts_with("text/plain")
+        });
+        if !is_text || body.len() as u64 > MAX_POLICY_SIZE {
+            return Err(Error::Client("Invalid MTA-STS policy response"));
+        }
+        Policy::parse(body)
+    }
+}
+
+/// Extracts the `id` field of a `v=STSv1; id=...` TXT record
+#[cfg(any(feature = "trust-dns-resolver", test))]
+fn parse_policy_id(record: &str) -> Option<String> {
+    record
+        .split(';')
+        .filter_map(|field| {
+            let field = field.trim();
+            let idx = field.find('=')?;
+            Some((&field[..idx], &field[idx + 1..]))
+        })
+        .find(|(key, _)| *key == "id")
+        .map(|(_, id)| id.to_string())
+        .filter(|id| !id.is_empty() && id.len() <= 32 && id.chars().all(char::is_alphanumeric))
+}
+
+/// MTA-STS settings of a transport
+#[derive(Clone)]
+pub(super) struct MtaSts {
+    /// MTA-STS is disabled without a fetcher
+    fetcher: Option<Arc<dyn PolicyFetcher>>,
+    cache: Arc<dyn PolicyCache>,
+}
+
+impl Default for MtaSts {
+    fn default() -> Self {
+        Self {
+            fetcher: None,
+            cache: Arc::new(MemoryPolicyCache::new()),
+        }
+    }
+}
+
+impl MtaSts {
+    pub(super) fn set_fetcher(&mut self, fetcher: Arc<dyn PolicyFetcher>) {
+        self.fetcher = Some(fetcher);
+    }
+
+    pub(super) fn set_cache(&mut self, cache: Arc<dyn PolicyCache>) {
+        self.cache = cache;
+    }
+
+    /// Returns the policy of the domain, from the cache when it is still current
+    ///
+    /// Errors are not fatal: the cached policy is used if it has not expired,
+    /// as described in [RFC 8461, section 5.1](https://tools.ietf.org/html/rfc8461#section-5.1).
+    pub(super) fn policy(&self, domain: &str) -> Option<Policy> {
+        let fetcher = self.fetcher.as_ref()?;
+        let now = SystemTime::now();
+        let cached = self
+            .cache
+            .get(domain)
+            .filter(|cached| !cached.is_expired(now));
+
+        let id = match fetcher.policy_id(domain) {
+            Ok(Some(id)) => id,
+            _ => return cached.map(|cached| cached.policy),
+        };
+        if let Some(cached) = cached.as_ref().filter(|cached| cached.id == id) {
+            return Some(cached.policy.clone());
+        }
+
+        match fetcher.fetch_policy(domain) {
+            Ok(policy) => {
+                self.cache.insert(
+                    domain,
+                    CachedPolicy {
+                        id,
+                        policy: policy.clone(),
+                        fetched_at: now,
+                    },
+                );
+                Some(policy)
+            }
+            Err(_err) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("could not fetch the MTA-STS policy of {}: {}", domain, _err);
+                cached.map(|cached| cached.policy)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const POLICY: &str = "version: STSv1\r\nmode: enforce\r\nmx: mail.example.com\r\nmx: *.example.net\r\nmax_age: 604800\r\n";
+
+    #[test]
+    fn parse_policy() {
+        let policy = Policy::parse(POLICY).unwrap();
+        assert_eq!(policy.mode(), Mode::Enforce);
+        assert_eq!(policy.mx(), ["mail.example.com", "*.example.net"]);
+        assert_eq!(policy.max_age(), Duration::from_secs(604_800));
+
+        let policy = Policy::parse("version: STSv1\nmode: none\nmax_age: 99999999999\n").unwrap();
+        assert_eq!(policy.mode(), Mode::None);
+        assert_eq!(policy.max_age(), MAX_POLICY_AGE);
+
+        for invalid in &[
+            "version: STSv2\nmode: enforce\nmx: mail.example.com\nmax_age: 86400\n",
+            "version: STSv1\nmode: enforce\nmax_age: 86400\n",
+            "version: STSv1\nmode: strict\nmx: mail.example.com\nmax_age: 86400\n",
+            "version: STSv1\nmode: enforce\nmx: mail.example.com\n",
+            "<html></html>",
+        ] {
+            assert!(Policy::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn match_mx() {
+        let policy = Policy::parse(POLICY).unwrap();
+        assert!(policy.matches_mx("mail.example.com"));
+        assert!(policy.matches_mx("Mail.Example.com."));
+        assert!(policy.matches_mx("mx1.example.net"));
+        assert!(!policy.matches_mx("example.net"));
+        assert!(!policy.matches_mx("a.mx1.example.net"));
+        assert!(!policy.matches_mx("mx.example.com"));
+    }
+
+    #[test]
+    fn policy_id() {
+        assert_eq!(
+            parse_policy_id("v=STSv1; id=20160831085700Z;"),
+            Some("20160831085700Z".to_string())
+        );
+        assert_eq!(parse_policy_id("v=STSv1;"), None);
+        assert_eq!(parse_policy_id("v=STSv1; id=not valid"), None);
+    }
+
+    /// Fetcher counting policy fetches, which can be made to fail
+    struct CountingFetcher {
+        inner: StaticPolicyFetcher,
+        fetches: Mutex<usize>,
+        fail: bool,
+    }
+
+    impl PolicyFetcher for CountingFetcher {
+        fn policy_id(&self, domain: &str) -> Result<Option<String>, Error> {
+            self.inner.policy_id(domain)
+        }
+
+        fn fetch_policy(&self, domain: &str) -> Result<Policy, Error> {
+            *self.fetches.lock().unwrap() += 1;
+            if self.fail {
+                return Err(Error::Client("unreachable"));
+            }
+            self.inner.fetch_policy(domain)
+        }
+    }
+
+    fn counting_mta_sts(
+        id: &str,
+        fail: bool,
+        cache: &Arc<MemoryPolicyCache>,
+    ) -> (MtaSts, Arc<CountingFetcher>) {
+        let fetcher = Arc::new(CountingFetcher {
+            inner: StaticPolicyFetcher::new()
+                .policy("example.com", id, POLICY)
+                .unwrap(),
+            fetches: Mutex::new(0),
+            fail,
+        });
+        let mut mta_sts = MtaSts::default();
+        mta_sts.set_fetcher(fetcher.clone());
+        mta_sts.set_cache(cache.clone());
+        (mta_sts, fetcher)
+    }
+
+    #[test]
+    fn policy_cache() {
+        let cache = Arc::new(MemoryPolicyCache::new());
+
+        let (mta_sts, fetcher) = counting_mta_sts("1", false, &cache);
+        assert_eq!(mta_sts.policy("example.org"), None);
+        assert!(mta_sts.policy("example.com").is_some());
+        assert!(mta_sts.policy("example.com").is_some());
+        assert_eq!(*fetcher.fetches.lock().unwrap(), 1);
+
+        // A new id means the policy changed
+        let (mta_sts, fetcher) = counting_mta_sts("2", false, &cache);
+        assert!(mta_sts.policy("example.com").is_some());
+        assert_eq!(*fetcher.fetches.lock().unwrap(), 1);
+        assert_eq!(cache.get("example.com").unwrap().id, "2");
+
+        // The cached policy is used when the new one can't be fetched
+        let (mta_sts, _) = counting_mta_sts("3", true, &cache);
+        assert!(mta_sts.policy("example.com").is_some());
+        assert_eq!(cache.get("example.com").unwrap().id, "2");
+
+        // Expired policies are not used
+        let mut expired = cache.get("example.com").unwrap();
+        expired.fetched_at = SystemTime::now() - Duration::from_secs(604_801);
+        cache.insert("example.com", expired);
+        assert_eq!(mta_sts.policy("example.com"), None);
+    }
+}
@@ -0,0 +1,705 @@
+//! Direct delivery to the recipients' mail exchangers
+//!
+//! The [`MxTransport`] doesn't need a relay: it groups the envelope recipients by
+//! domain, looks up the MX records of each domain and delivers the message to the
+//! mail exchangers in preference order, on port 25. When a domain has no MX record,
+//! the domain itself is used as mail exchanger (the implicit MX described in
+//! [RFC 5321, section 5.1](https://tools.ietf.org/html/rfc5321#section-5.1)).
+//!
+//! When a TLS backend is enabled, STARTTLS is used whenever the mail exchanger supports it,
+//! and [MTA-STS](super::mta_sts) policies can be enforced. Without a policy, certificates
+//! are not verified and the message is sent in plaintext if STARTTLS fails.
+//!
+//! DNS lookups go through the [`Resolver`] trait. A resolver based on the system
+//! configuration is available with the `trust-dns-resolver` feature, and
+//! [`StaticResolver`] serves a fixed in-memory zone, which is useful for tests.
+//!
+//! This transport is meant for applications without a smarthost. When possible,
+//! sending through a relay with [`SmtpTransport`](super::SmtpTransport) should be preferred.
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "builder")]
+//! # fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! use lettre::{
+//!     transport::smtp::mx::{MxTransport, StaticResolver},
+//!     Message, Transport,
+//! };
+//!
+//! let email = Message::builder()
+//!     .from("NoBody <nobody@domain.tld>".parse()?)
+//!     .to("Hei <hei@example.com>".parse()?)
+//!     .to("Yuin <yuin@example.org>".parse()?)
+//!     .subject("Happy new year")
+//!     .body(String::from("Be happy!"))?;
+//!
+//! let resolver = StaticResolver::new()
+//!     .mx("example.com", 10, "mx.example.com")
+//!     .ip("mx.example.com", "192.0.2.25".parse()?)
+//!     .ip("example.org", "192.0.2.26".parse()?);
+//!
+//! let sender = MxTransport::builder(resolver).build();
+//! let result = sender.send(&email)?;
+//! for delivery in result.deliveries() {
+//!     println!("{}: {:?}", delivery.domain(), delivery.result());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+use super::{
+    client::TlsParameters,
+    mta_sts::{Mode, MtaSts, PolicyCache, PolicyFetcher},
+};
+use super::{
+    client::{ConnectionOptions, SessionObserver, SmtpConnection, Tls},
+    extension::ClientId,
+    failover::RelayResponse,
+    Error, SendResponse, DEFAULT_TIMEOUT, SMTP_PORT,
+};
+use crate::{address::Address, transport::instrument::SendScope, Envelope, Transport};
+
+/// A mail exchanger of a domain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MxRecord {
+    /// Preference of the mail exchanger, lower values are tried first
+    pub preference: u16,
+    /// Host name of the mail exchanger
+    ///
+    /// An empty name is a null MX ([RFC 7505](https://tools.ietf.org/html/rfc7505)),
+    /// meaning the domain doesn't accept email.
+    pub exchange: String,
+}
+
+/// DNS lookups used by the [`MxTransport`]
+pub trait Resolver: Send + Sync {
+    /// Returns the MX records of the domain
+    ///
+    /// An empty list must be returned when the domain exists but has no MX record.
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<MxRecord>, Error>;
+
+    /// Returns the IPv4 and IPv6 addresses of the host
+    fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>, Error>;
+}
+
+/// Resolver serving a fixed in-memory zone
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    mx: HashMap<String, Vec<MxRecord>>,
+    ip: HashMap<String, Vec<IpAddr>>,
+}
+
+impl StaticResolver {
+    /// Creates an empty zone
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a MX record to the zone
+    pub fn mx<D: Into<String>, H: Into<String>>(
+        mut self,
+        domain: D,
+        preference: u16,
+        exchange: H,
+    ) -> Self {
+        self.mx
+            .entry(domain.into().to_lowercase())
+            .or_default()
+            .push(MxRecord {
+                preference,
+                exchange: exchange.into(),
+            });
+        self
+    }
+
+    /// Add an A or AAAA record to the zone
+    pub fn ip<H: Into<String>>(mut self, host: H, addr: IpAddr) -> Self {
+        self.ip
+            .entry(host.into().to_lowercase())
+            .or_default()
+            .push(addr);
+        self
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<MxRecord>, Error> {
+        Ok(self
+            .mx
+            .get(&domain.to_lowercase())
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>, Error> {
+        self.ip
+            .get(&host.to_lowercase())
+            .cloned()
+            .ok_or(Error::Resolution)
+    }
+}
+
+/// Resolver using the system DNS configuration
+#[cfg(feature = "trust-dns-resolver")]
+#[cfg_attr(docsrs, doc(cfg(feature = "trust-dns-resolver")))]
+#[allow(missing_debug_implementations)]
+pub struct SystemResolver {
+    inner: trust_dns_resolver::Resolver,
+}
+
+#[cfg(feature = "trust-dns-resolver")]
+impl SystemResolver {
+    /// Creates a resolver from the system configuration (`/etc/resolv.conf` on Unix)
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            inner: trust_dns_resolver::Resolver::from_system_conf()?,
+        })
+    }
+}
+
+#[cfg(feature = "trust-dns-resolver")]
+impl Resolver for SystemResolver {
+    fn lookup_mx(&self, domain: &str) -> Result<Vec<MxRecord>, Error> {
+        use trust_dns_resolver::error::ResolveErrorKind;
+
+        // Query the name as fully qualified to avoid search domains
+        match self
+            .inner
+            .mx_lookup(format!("{}.", domain.trim_end_matches('.')))
+        {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .map(|mx| MxRecord {
+                    preference: mx.preference(),
+                    exchange: mx.exchange().to_ascii().trim_end_matches('.').to_string(),
+                })
+                .collect()),
+            Err(err) => match err.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => Ok(Vec::new()),
+                _ => Err(Error::Resolution),
+            },
+        }
+    }
+
+    fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>, Error> {
+        let lookup = self
+            .inner
+            .lookup_ip(format!("{}.", host.trim_end_matches('.')))
+            .map_err(|_| Error::Resolution)?;
+        Ok(lookup.iter().collect())
+    }
+}
+
+/// Delivery result for one recipient domain
+#[derive(Debug)]
+pub struct DomainDelivery {
+    domain: String,
+    recipients: Vec<Address>,
+    result: Result<RelayResponse, Error>,
+}
+
+impl DomainDelivery {
+    /// Recipient domain
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Recipients of the envelope in this domain
+    pub fn recipients(&self) -> &[Address] {
+        &self.recipients
+    }
+
+    /// Mail exchanger which accepted the message and its response, or the last error
+    pub fn result(&self) -> &Result<RelayResponse, Error> {
+        &self.result
+    }
+}
+
+/// Delivery results for all the recipient domains of a message
+#[derive(Debug)]
+pub struct MxResponse {
+    deliveries: Vec<DomainDelivery>,
+}
+
+impl MxResponse {
+    /// Results for each recipient domain
+    pub fn deliveries(&self) -> &[DomainDelivery] {
+        &self.deliveries
+    }
+
+    /// Tells if the message was accepted for all recipient domains
+    pub fn is_complete(&self) -> bool {
+        self.deliveries.iter().all(|d| d.result.is_ok())
+    }
+}
+
+/// Transport delivering messages directly to the recipients' mail exchangers
+#[derive(Clone)]
+#[allow(missing_debug_implementations)]
+pub struct MxTransport {
+    resolver: Arc<dyn Resolver>,
+    hello_name: ClientId,
+    port: u16,
+    connection: ConnectionOptions,
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    opportunistic_tls: bool,
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    mta_sts: MtaSts,
+}
+
+impl MxTransport {
+    /// Creates a new direct delivery transport using the given DNS resolver
+    ///
+    /// Defaults are:
+    ///
+    /// * Port 25
+    /// * A 60 seconds timeout for smtp commands
+    /// * STARTTLS when available, if a TLS backend is enabled
+    pub fn builder<R: Resolver + 'static>(resolver: R) -> MxTransportBuilder {
+        MxTransportBuilder {
+            transport: MxTransport {
+                resolver: Arc::new(resolver),
+                hello_name: ClientId::default(),
+                port: SMTP_PORT,
+                connection: ConnectionOptions::new().timeout(Some(DEFAULT_TIMEOUT)),
+                #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+                opportunistic_tls: true,
+                #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+                mta_sts: MtaSts::default(),
+            },
+        }
+    }
+
+    /// Creates a new direct delivery transport using the system DNS configuration
+    #[cfg(feature = "trust-dns-resolver")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "trust-dns-resolver")))]
+    pub fn from_system_conf() -> Result<MxTransportBuilder, Error> {
+        Ok(Self::builder(SystemResolver::new()?))
+    }
+
+    /// Mail exchangers of the domain, in the order they should be tried
+    fn exchangers(&self, domain: &str) -> Result<Vec<String>, Error> {
+        let mut records = self.resolver.lookup_mx(domain)?;
+        if records.is_empty() {
+            // Implicit MX
+            return Ok(vec![domain.to_string()]);
+        }
+        if records.iter().any(|mx| mx.exchange.is_empty()) {
+            return Err(Error::Client("Domain does not accept email (null MX)"));
+        }
+
+        // Stable sort, so that records with the same preference keep the resolver order
+        records.sort_by_key(|mx| mx.preference);
+        Ok(records.into_iter().map(|mx| mx.exchange).collect())
+    }
+
+    /// Mail exchangers of the domain, with the TLS settings to use for each of them
+    fn destinations(&self, domain: &str) -> Result<Vec<(String, Tls)>, Error> {
+        let exchangers = self.exchangers(domain)?;
+
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        match self.mta_sts.policy(domain) {
+            Some(policy) if policy.mode() == Mode::Enforce => {
+                let destinations = exchangers
+                    .into_iter()
+                    .filter(|exchange| policy.matches_mx(exchange))
+                    .map(|exchange| {
+                        let tls_parameters = TlsParameters::new(exchange.clone())?;
+                        Ok((exchange, Tls::Required(tls_parameters)))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                if destinations.is_empty() {
+                    return Err(Error::Client(
+                        "No mail exchanger matches the MTA-STS policy",
+                    ));
+                }
+                return Ok(destinations);
+            }
+            #[cfg(feature = "tracing")]
+            Some(policy) if policy.mode() == Mode::Testing => {
+                for exchange in exchangers.iter().filter(|e| !policy.matches_mx(e)) {
+                    tracing::warn!(
+                        "{} does not match the MTA-STS policy of {}",
+                        exchange,
+                        domain
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        Ok(exchangers
+            .into_iter()
+            .map(|exchange| {
+                let tls = self.opportunistic_tls(&exchange);
+                (exchange, tls)
+            })
+            .collect())
+    }
+
+    /// TLS settings for mail exchangers without MTA-STS policy
+    ///
+    /// Without a policy the identity of the mail exchanger can't be authenticated, and many
+    /// of them use self-signed certificates: any certificate is accepted, as encrypting
+    /// with it is still better than sending in plaintext.
+    #[allow(clippy::unused_self)]
+    fn opportunistic_tls(&self, _exchange: &str) -> Tls {
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        if self.opportunistic_tls {
+            let tls_parameters = TlsParameters::builder(_exchange.to_string())
+                .dangerous_accept_invalid_certs(true)
+                .build();
+            if let Ok(tls_parameters) = tls_parameters {
+                return Tls::Opportunistic(tls_parameters);
+            }
+        }
+        Tls::None
+    }
+
+    /// Delivers the message for one domain, trying each mail exchanger in turn
+    fn deliver_domain(
+        &self,
+        domain: &str,
+        envelope: &Envelope,
+        email: &[u8],
+    ) -> Result<RelayResponse, Error> {
+        let mut last_error = Error::Resolution;
+        for (exchange, tls) in self.destinations(domain)? {
+            match self.deliver_to(&exchange, &tls, envelope, email) {
+                Ok(response) => return Ok(RelayResponse::new(exchange, response)),
+                // The message was refused, other exchangers won't accept it either
+                Err(err @ Error::Permanent(_)) => return Err(err),
+                Err(err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("delivery to {} failed: {}", exchange, err);
+                    last_error = err;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    fn deliver_to(
+        &self,
+        exchange: &str,
+        tls: &Tls,
+        envelope: &Envelope,
+        email: &[u8],
+    ) -> Result<SendResponse, Error> {
+        let mut conn = in_span!(
+            self.connect(exchange, tls),
+            "connect",
+            relay = exchange,
+            port = self.port
+        )?;
+
+        let response = SendResponse::new(conn.send(envelope, email)?);
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        let response = response.with_tls_info(conn.tls_info().ok().flatten());
+        // The message has been accepted at this point
+        let _ = conn.quit();
+        Ok(response)
+    }
+
+    /// Connects to a mail exchanger and negotiates TLS
+    ///
+    /// When opportunistic STARTTLS fails, the message is delivered in plaintext over a new
+    /// connection.
+    fn connect(&self, exchange: &str, tls: &Tls) -> Result<SmtpConnection, Error> {
+        let addrs: Vec<SocketAddr> = self
+            .resolver
+            .lookup_ip(exchange)?
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, self.port))
+            .collect();
+        if addrs.is_empty() {
+            return Err(Error::Resolution);
+        }
+
+        #[allow(unused_mut)]
+        let mut conn = self.connect_plain(&addrs)?;
+
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        match tls {
+            Tls::Opportunistic(tls_parameters) if conn.can_starttls() => {
+                if let Err(_err) = conn.starttls(tls_parameters, &self.hello_name) {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        "STARTTLS with {} failed, sending in plaintext: {}",
+                        exchange,
+                        _err
+                    );
+                    return self.connect_plain(&addrs);
+                }
+            }
+            Tls::Required(tls_parameters) => {
+                conn.starttls(tls_parameters, &self.hello_name)?;
+            }
+            _ => {}
+        }
+        #[cfg(not(any(feature = "native-tls", feature = "rustls-tls")))]
+        let _ = tls;
+
+        Ok(conn)
+    }
+
+    /// Opens an unencrypted connection to the first reachable address
+    fn connect_plain(&self, addrs: &[SocketAddr]) -> Result<SmtpConnection, Error> {
+        SmtpConnection::connect(addrs, &self.hello_name, None, &self.connection)
+    }
+}
+
+impl Transport for MxTransport {
+    type Ok = MxResponse;
+    type Error = Error;
+
+    /// Delivers the message to the mail exchangers of each recipient domain
+    ///
+    /// Returns an error only if no domain accepted the message, the result
+    /// for each domain is available in the [`MxResponse`] otherwise.
+    fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
+        let scope = SendScope::new("mx", envelope, email);
+        let result = scope.in_scope(|| self.send_inner(envelope, email));
+        scope.finish(&result);
+        result
+    }
+}
+
+impl MxTransport {
+    /// Delivers the message for each recipient domain
+    fn send_inner(&self, envelope: &Envelope, email: &[u8]) -> Result<MxResponse, Error> {
+        let mut domains: BTreeMap<String, Vec<Address>> = BTreeMap::new();
+        for recipient in envelope.to() {
+            domains
+                .entry(recipient.domain().to_lowercase())
+                .or_default()
+                .push(recipient.clone());
+        }
+
+        let mut deliveries = Vec::with_capacity(domains.len());
+        for (domain, recipients) in domains {
+            let result = Envelope::new(envelope.from().cloned(), recipients.clone())
+                .map_err(|_| Error::Client("Invalid envelope"))
+                .and_then(|envelope| self.deliver_domain(&domain, &envelope, email));
+            deliveries.push(DomainDelivery {
+                domain,
+                recipients,
+                result,
+            });
+        }
+
+        if deliveries.iter().any(|d| d.result.is_ok()) {
+            return Ok(MxResponse { deliveries });
+        }
+        match deliveries.pop() {
+            Some(DomainDelivery {
+                result: Err(err), ..
+            }) => Err(err),
+            _ => Err(Error::Client("Envelope has no recipient")),
+        }
+    }
+}
+
+/// Builder for [`MxTransport`]
+#[derive(Clone)]
+#[allow(missing_debug_implementations)]
+pub struct MxTransportBuilder {
+    transport: MxTransport,
+}
+
+impl MxTransportBuilder {
+    /// Set the name used during EHLO
+    pub fn hello_name(mut self, name: ClientId) -> Self {
+        self.transport.hello_name = name;
+        self
+    }
+
+    /// Set the port to use
+    ///
+    /// Defaults to 25, other values are only useful for testing
+    pub fn port(mut self, port: u16) -> Self {
+        self.transport.port = port;
+        self
+    }
+
+    /// Set the timeout duration
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.transport.connection = self.transport.connection.timeout(timeout);
+        self
+    }
+
+    /// Set the local address to bind outgoing connections to
+    pub fn source_address(mut self, source_address: IpAddr) -> Self {
+        self.transport.connection = self.transport.connection.source_address(source_address);
+        self
+    }
+
+    /// Set an observer receiving the commands and responses of every connection
+    ///
+    /// Authentication data and message contents are redacted by default,
+    /// see [`SessionObserver`](super::client::SessionObserver).
+    pub fn session_observer<O: SessionObserver + 'static>(mut self, observer: O) -> Self {
+        self.transport.connection = self.transport.connection.session_observer(observer);
+        self
+    }
+
+    /// Controls whether STARTTLS is used when the mail exchanger supports it
+    ///
+    /// Defaults to `true`
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "native-tls", feature = "rustls-tls"))))]
+    pub fn opportunistic_tls(mut self, opportunistic_tls: bool) -> Self {
+        self.transport.opportunistic_tls = opportunistic_tls;
+        self
+    }
+
+    /// Enforce the [MTA-STS](super::mta_sts) policies of the recipient domains,
+    /// obtained with the given fetcher
+    ///
+    /// For domains with a policy in `enforce` mode, only the mail exchangers matching the
+    /// policy are used, and STARTTLS with a valid certificate is required.
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "native-tls", feature = "rustls-tls"))))]
+    pub fn mta_sts<F: PolicyFetcher + 'static>(mut self, fetcher: F) -> Self {
+        self.transport.mta_sts.set_fetcher(Arc::new(fetcher));
+        self
+    }
+
+    /// Set the cache for the MTA-STS policies
+    ///
+    /// Defaults to a [`MemoryPolicyCache`](super::mta_sts::MemoryPolicyCache)
+    /// owned by the transport.
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "native-tls", feature = "rustls-tls"))))]
+    pub fn mta_sts_cache<C: PolicyCache + 'static>(mut self, cache: C) -> Self {
+        self.transport.mta_sts.set_cache(Arc::new(cache));
+        self
+    }
+
+    /// Build the transport
+    pub fn build(self) -> MxTransport {
+        self.transport
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn transport(resolver: StaticResolver) -> MxTransport {
+        MxTransport::builder(resolver).build()
+    }
+
+    #[test]
+    fn exchangers_by_preference() {
+        let transport = transport(
+            StaticResolver::new()
+                .mx("example.com", 20, "mx2.example.com")
+                .mx("example.com", 10, "mx1.example.com")
+                .mx("example.com", 20, "mx3.example.com"),
+        );
+        assert_eq!(
+            transport.exchangers("Example.com").unwrap(),
+            vec!["mx1.example.com", "mx2.example.com", "mx3.example.com"]
+        );
+    }
+
+    #[test]
+    fn implicit_mx() {
+        let transport = transport(StaticResolver::new());
+        assert_eq!(
+            transport.exchangers("example.com").unwrap(),
+            vec!["example.com"]
+        );
+    }
+
+    #[test]
+    fn null_mx() {
+        let transport = transport(StaticResolver::new().mx("example.com", 0, ""));
+        assert!(transport.exchangers("example.com").is_err());
+    }
+
+    #[test]
+    fn unresolvable_exchanger() {
+        let transport = transport(StaticResolver::new().mx("example.com", 10, "mx.example.com"));
+        let envelope = Envelope::new(None, vec!["user@example.com".parse().unwrap()]).unwrap();
+        assert!(matches!(
+            transport.send_raw(&envelope, b"test"),
+            Err(Error::Resolution)
+        ));
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    fn mta_sts_transport(mode: &str) -> MxTransport {
+        use super::super::mta_sts::StaticPolicyFetcher;
+
+        let resolver = StaticResolver::new()
+            .mx("example.com", 10, "mx1.example.com")
+            .mx("example.com", 20, "backup.example.net");
+        let fetcher = StaticPolicyFetcher::new()
+            .policy(
+                "example.com",
+                "1",
+                &format!(
+                    "version: STSv1\nmode: {}\nmx: *.example.com\nmax_age: 86400\n",
+                    mode
+                ),
+            )
+            .unwrap();
+        MxTransport::builder(resolver).mta_sts(fetcher).build()
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[test]
+    fn mta_sts_enforce() {
+        let transport = mta_sts_transport("enforce");
+        let destinations = transport.destinations("example.com").unwrap();
+        assert_eq!(destinations.len(), 1);
+        assert_eq!(destinations[0].0, "mx1.example.com");
+        assert!(matches!(destinations[0].1, Tls::Required(_)));
+
+        // Domains without policy are not affected
+        let destinations = transport.destinations("example.org").unwrap();
+        assert_eq!(destinations.len(), 1);
+        assert!(matches!(destinations[0].1, Tls::Opportunistic(_)));
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[test]
+    fn mta_sts_enforce_no_match() {
+        let transport =
+            MxTransport::builder(StaticResolver::new().mx("example.com", 10, "mx.example.net"))
+                .mta_sts(
+                    super::super::mta_sts::StaticPolicyFetcher::new()
+                        .policy(
+                            "example.com",
+                            "1",
+                            "version: STSv1\nmode: enforce\nmx: mx.example.com\nmax_age: 86400\n",
+                        )
+                        .unwrap(),
+                )
+                .build();
+        assert!(matches!(
+            transport.destinations("example.com"),
+            Err(Error::Client(_))
+        ));
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[test]
+    fn mta_sts_testing() {
+        let transport = mta_sts_transport("testing");
+        let destinations = transport.destinations("example.com").unwrap();
+        assert_eq!(destinations.len(), 2);
+        assert!(destinations
+            .iter()
+            .all(|(_, tls)| matches!(tls, Tls::Opportunistic(_))));
+    }
+}
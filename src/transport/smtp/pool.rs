@@ -1,6 +1,15 @@
-use std::time::Duration;
+use std::{
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
 
-use crate::transport::smtp::{client::SmtpConnection, error::Error, SmtpClient};
+use crate::transport::smtp::{
+    client::SmtpConnection, error::Error, lifecycle::Lifecycle, SmtpClient,
+};
 
 use r2d2::{ManageConnection, Pool};
 
@@ -13,6 +22,8 @@ pub struct PoolConfig {
     max_size: u32,
     connection_timeout: Duration,
     idle_timeout: Duration,
+    test_on_checkout: bool,
+    keepalive: Option<Duration>,
 }
 
 impl PoolConfig {
@@ -53,14 +64,58 @@ impl PoolConfig {
         self
     }
 
+    /// Check that connections are still usable with a `NOOP` before reusing them
+    ///
+    /// Defaults to `true`
+    pub fn test_on_checkout(mut self, test_on_checkout: bool) -> Self {
+        self.test_on_checkout = test_on_checkout;
+        self
+    }
+
+    /// Interval at which a `NOOP` is sent on idle connections to keep them alive
+    ///
+    /// Connections which don't respond are removed from the pool. The idle connections
+    /// are briefly taken out of the pool while they are checked.
+    ///
+    /// Defaults to `None` (no keepalive)
+    pub fn keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
     pub(crate) fn build<C: ManageConnection>(&self, client: C) -> Pool<C> {
         Pool::builder()
             .min_idle(Some(self.min_idle))
             .max_size(self.max_size)
             .connection_timeout(self.connection_timeout)
             .idle_timeout(Some(self.idle_timeout))
+            .test_on_check_out(self.test_on_checkout)
             .build_unchecked(client)
     }
+
+    /// Starts the keepalive thread for the given pool, if enabled
+    pub(crate) fn spawn_keepalive(
+        &self,
+        pool: &Pool<SmtpClient>,
+        lifecycle: &Arc<Lifecycle>,
+    ) -> Option<Keepalive> {
+        let interval = self.keepalive?;
+        let pool = pool.clone();
+        let lifecycle = Arc::clone(lifecycle);
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        thread::spawn(move || {
+            // The channel is disconnected when the transport is dropped
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if lifecycle.is_closed() {
+                    break;
+                }
+                keepalive_idle(&pool, &lifecycle, interval);
+            }
+        });
+
+        Some(Keepalive { _stop: stop })
+    }
 }
 
 impl Default for PoolConfig {
@@ -70,6 +125,39 @@ impl Default for PoolConfig {
             max_size: 10,
             connection_timeout: Duration::from_secs(30),
             idle_timeout: Duration::from_secs(60),
+            test_on_checkout: true,
+            keepalive: None,
+        }
+    }
+}
+
+/// Handle to the keepalive thread of a pool, which stops when it is dropped
+#[derive(Debug)]
+pub(crate) struct Keepalive {
+    _stop: Sender<()>,
+}
+
+/// Sends a NOOP on the idle connections of the pool which were inactive during `interval`
+///
+/// All the idle connections are taken before returning any: the pool hands out the most
+/// recently returned connection first, so it would give the same one again otherwise.
+fn keepalive_idle(pool: &Pool<SmtpClient>, lifecycle: &Lifecycle, interval: Duration) {
+    let mut idle = Vec::new();
+    for _ in 0..pool.state().idle_connections {
+        match pool.try_get() {
+            Some(conn) => idle.push(conn),
+            None => break,
+        }
+    }
+
+    for conn in &mut idle {
+        if lifecycle.is_closed() {
+            conn.abort();
+        } else if conn.idle_duration() < interval {
+            // When testing on checkout the pool has just sent a NOOP
+        } else if conn.noop().is_err() {
+            // Marks the connection as broken so that the pool drops it
+            conn.abort();
         }
     }
 }
@@ -83,13 +171,46 @@ impl ManageConnection for SmtpClient {
     }
 
     fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Error> {
-        if conn.test_connected() {
-            return Ok(());
-        }
-        Err(Error::Client("is not connected anymore"))
+        conn.noop().map(|_| ())
     }
 
     fn has_broken(&self, conn: &mut Self::Connection) -> bool {
         conn.has_broken()
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "smtp-test-server")]
+mod test {
+    use super::{keepalive_idle, PoolConfig};
+    use crate::transport::smtp::{
+        client::Transcript, lifecycle::Lifecycle, test_server::TestServer, SmtpTransport,
+    };
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn keepalive_all_idle_connections() {
+        let server = TestServer::start().unwrap();
+        let transcript = Transcript::new();
+        let client = SmtpTransport::builder_dangerous("127.0.0.1")
+            .port(server.port())
+            .session_observer(transcript.clone())
+            .client();
+        let pool = PoolConfig::new().test_on_checkout(false).build(client);
+
+        let first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        drop((first, second));
+        assert_eq!(pool.state().idle_connections, 2);
+        thread::sleep(Duration::from_millis(20));
+
+        keepalive_idle(&pool, &Lifecycle::default(), Duration::from_millis(10));
+        let noops = transcript
+            .entries()
+            .iter()
+            .filter(|entry| entry.text() == "NOOP")
+            .count();
+        assert_eq!(noops, 2);
+        assert_eq!(pool.state().idle_connections, 2);
+    }
+}
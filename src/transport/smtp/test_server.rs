@@ -0,0 +1,996 @@
+//! In-process SMTP server for tests
+//!
+//! [`TestServer`] listens on a local port and accepts the mail transactions of
+//! [`SmtpTransport`](super::SmtpTransport) and [`AsyncSmtpTransport`](super::AsyncSmtpTransport),
+//! recording the received envelopes and messages for assertions. Its replies can be
+//! scripted to exercise the error paths of the client: rejected recipients, temporary
+//! failures or connections dropped by the server.
+//!
+//! It is meant for tests only: it reads each session in a thread, and its STARTTLS support
+//! uses a self-signed certificate bundled with lettre.
+//!
+//! ```rust
+//! # #[cfg(feature = "builder")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use lettre::{
+//!     transport::smtp::{
+//!         response::{Category, Code, Detail, Response, Severity},
+//!         test_server::TestServer,
+//!     },
+//!     Message, SmtpTransport, Transport,
+//! };
+//!
+//! let server = TestServer::builder()
+//!     .reject_recipient(
+//!         "unknown@example.com".parse()?,
+//!         Response::new(
+//!             Code::new(
+//!                 Severity::PermanentNegativeCompletion,
+//!                 Category::MailSystem,
+//!                 Detail::Zero,
+//!             ),
+//!             vec!["No such user".to_string()],
+//!         ),
+//!     )
+//!     .start()?;
+//!
+//! let sender = SmtpTransport::builder_dangerous("127.0.0.1")
+//!     .port(server.port())
+//!     .build();
+//!
+//! let email = Message::builder()
+//!     .from("NoBody <nobody@example.com>".parse()?)
+//!     .to("Hei <hei@example.com>".parse()?)
+//!     .subject("Happy new year")
+//!     .body(String::from("Be happy!"))?;
+//! sender.send(&email)?;
+//!
+//! let messages = server.messages();
+//! assert_eq!(messages.len(), 1);
+//! assert_eq!(messages[0].envelope().to(), &["hei@example.com".parse()?]);
+//!
+//! let email = Message::builder()
+//!     .from("NoBody <nobody@example.com>".parse()?)
+//!     .to("Unknown <unknown@example.com>".parse()?)
+//!     .subject("Happy new year")
+//!     .body(String::from("Be happy!"))?;
+//! assert!(sender.send(&email).is_err());
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "builder"))]
+//! # fn main() {}
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Debug, Formatter},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+#[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+use rustls::{internal::pemfile, NoClientAuth, ServerConfig, ServerSession, StreamOwned};
+
+use super::{
+    authentication::{Credentials, Mechanism},
+    extension::Extension,
+    response::{Category, Code, Detail, Response, Severity},
+};
+use crate::{Address, Envelope};
+
+/// Host name announced by default in the greeting and the `EHLO` response
+const DEFAULT_HOSTNAME: &str = "lettre.test";
+
+/// Step of an SMTP session at which a reply can be scripted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Greeting sent when the client connects
+    Greeting,
+    /// `EHLO` or `HELO` command
+    Ehlo,
+    /// `STARTTLS` command
+    StartTls,
+    /// `AUTH` command, replacing the whole authentication exchange
+    Auth,
+    /// `MAIL FROM` command
+    Mail,
+    /// `RCPT TO` command
+    Rcpt,
+    /// `DATA` command
+    Data,
+    /// End of the message content
+    Message,
+    /// `RSET` command
+    Rset,
+    /// `NOOP` command
+    Noop,
+    /// `QUIT` command
+    Quit,
+}
+
+/// Scripted reply of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// Sends the response
+    ///
+    /// A positive response lets the command take effect, like recording the sender of a
+    /// `MAIL` command or the message, and a negative response discards it.
+    Respond(Response),
+    /// Sends a `421` response and closes the connection
+    Close,
+}
+
+/// Message received by a [`TestServer`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedMessage {
+    envelope: Envelope,
+    data: Vec<u8>,
+    encrypted: bool,
+    user: Option<String>,
+}
+
+impl ReceivedMessage {
+    /// Sender and accepted recipients of the message
+    pub fn envelope(&self) -> &Envelope {
+        &self.envelope
+    }
+
+    /// Content of the message, without dot-stuffing
+    ///
+    /// It includes the line break preceding the terminating dot.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Tells if the message was received over a connection upgraded with `STARTTLS`
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// User the client authenticated as, if any
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+}
+
+/// Builder for a [`TestServer`]
+#[derive(Debug, Clone)]
+pub struct TestServerBuilder {
+    address: SocketAddr,
+    hostname: String,
+    extensions: Vec<Extension>,
+    credentials: Option<Credentials>,
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    starttls: bool,
+    rejected_recipients: Vec<(Address, Response)>,
+    replies: HashMap<Stage, VecDeque<Reply>>,
+}
+
+impl Default for TestServerBuilder {
+    fn default() -> Self {
+        Self {
+            address: ([127, 0, 0, 1], 0).into(),
+            hostname: DEFAULT_HOSTNAME.to_string(),
+            extensions: vec![Extension::EightBitMime, Extension::SmtpUtfEight],
+            credentials: None,
+            #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+            starttls: false,
+            rejected_recipients: Vec::new(),
+            replies: HashMap::new(),
+        }
+    }
+}
+
+impl TestServerBuilder {
+    /// Creates a builder for a server listening on a random port of `127.0.0.1`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Address to listen on
+    ///
+    /// Defaults to a port chosen by the system on `127.0.0.1`.
+    pub fn address(mut self, address: SocketAddr) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Host name announced in the greeting and the `EHLO` response
+    ///
+    /// Defaults to `lettre.test`.
+    pub fn hostname(mut self, hostname: String) -> Self {
+        self.hostname = hostname;
+        self
+    }
+
+    /// Extensions announced in the `EHLO` response
+    ///
+    /// `STARTTLS` and `AUTH` are announced depending on [`starttls`](Self::starttls) and
+    /// [`credentials`](Self::credentials). Defaults to `8BITMIME` and `SMTPUTF8`.
+    pub fn extensions(mut self, extensions: Vec<Extension>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Requires clients to authenticate with the credentials before sending messages
+    ///
+    /// The `PLAIN` and `LOGIN` mechanisms are announced.
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Announces and accepts `STARTTLS`
+    ///
+    /// The server presents a self-signed certificate, so clients must be configured with
+    /// [`dangerous_accept_invalid_certs`](super::client::TlsParametersBuilder::dangerous_accept_invalid_certs).
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "native-tls", feature = "rustls-tls"))))]
+    pub fn starttls(mut self) -> Self {
+        self.starttls = true;
+        self
+    }
+
+    /// Rejects `RCPT TO` commands for the address with the response
+    pub fn reject_recipient(mut self, address: Address, response: Response) -> Self {
+        self.rejected_recipients.push((address, response));
+        self
+    }
+
+    /// Replaces the next default reply of the server at the stage
+    ///
+    /// Replies scripted for the same stage are used in order, one per occurrence of the
+    /// stage in any session, and the server replies as usual afterwards.
+    pub fn reply(mut self, stage: Stage, reply: Reply) -> Self {
+        self.replies.entry(stage).or_default().push_back(reply);
+        self
+    }
+
+    /// Starts listening and serving connections in a background thread
+    pub fn start(self) -> io::Result<TestServer> {
+        let listener = TcpListener::bind(self.address)?;
+        let addr = listener.local_addr()?;
+
+        let config = Arc::new(Config {
+            #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+            acceptor: if self.starttls {
+                Some(TlsAcceptor::new()?)
+            } else {
+                None
+            },
+            hostname: self.hostname,
+            extensions: self.extensions,
+            credentials: self.credentials,
+            rejected_recipients: self.rejected_recipients,
+            replies: Mutex::new(self.replies),
+            messages: Mutex::new(Vec::new()),
+        });
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        {
+            let config = Arc::clone(&config);
+            let stopped = Arc::clone(&stopped);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let config = Arc::clone(&config);
+                        thread::spawn(move || {
+                            let result = Session::new(config, stream).serve();
+                            #[cfg(feature = "tracing")]
+                            if let Err(err) = result {
+                                tracing::debug!("test server session failed: {}", err);
+                            }
+                            #[cfg(not(feature = "tracing"))]
+                            let _ = result;
+                        });
+                    }
+                }
+            });
+        }
+
+        Ok(TestServer {
+            addr,
+            config,
+            stopped,
+        })
+    }
+}
+
+/// SMTP server running in the background of a test
+///
+/// The server stops accepting connections when dropped.
+pub struct TestServer {
+    addr: SocketAddr,
+    config: Arc<Config>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl TestServer {
+    /// Creates a builder for a server
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder::new()
+    }
+
+    /// Starts a server accepting all messages on a random port of `127.0.0.1`
+    pub fn start() -> io::Result<Self> {
+        Self::builder().start()
+    }
+
+    /// Address the server listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Port the server listens on
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Messages received so far, in order
+    pub fn messages(&self) -> Vec<ReceivedMessage> {
+        self.config.messages.lock().unwrap().clone()
+    }
+
+    /// Forgets the received messages
+    pub fn clear(&self) {
+        self.config.messages.lock().unwrap().clear();
+    }
+}
+
+impl Debug for TestServer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestServer")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake up the listening thread
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+/// Settings and state shared by the sessions of a server
+struct Config {
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    acceptor: Option<TlsAcceptor>,
+    hostname: String,
+    extensions: Vec<Extension>,
+    credentials: Option<Credentials>,
+    rejected_recipients: Vec<(Address, Response)>,
+    replies: Mutex<HashMap<Stage, VecDeque<Reply>>>,
+    messages: Mutex<Vec<ReceivedMessage>>,
+}
+
+impl Config {
+    fn scripted(&self, stage: Stage) -> Option<Reply> {
+        self.replies
+            .lock()
+            .unwrap()
+            .get_mut(&stage)
+            .and_then(VecDeque::pop_front)
+    }
+}
+
+/// Server side of the test certificate
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+enum TlsAcceptor {
+    #[cfg(feature = "native-tls")]
+    NativeTls(native_tls::TlsAcceptor),
+    #[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+    Rustls(Arc<ServerConfig>),
+}
+
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+impl TlsAcceptor {
+    #[cfg(feature = "native-tls")]
+    fn new() -> io::Result<Self> {
+        let identity = native_tls::Identity::from_pkcs12(
+            include_bytes!("../../../testdata/client.p12"),
+            "lettre",
+        )
+        .and_then(native_tls::TlsAcceptor::new)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(TlsAcceptor::NativeTls(identity))
+    }
+
+    #[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+    fn new() -> io::Result<Self> {
+        let invalid = |_| io::Error::new(io::ErrorKind::InvalidData, "invalid test certificate");
+        let certs = pemfile::certs(&mut &include_bytes!("../../../testdata/client.crt")[..])
+            .map_err(invalid)?;
+        let key =
+            pemfile::pkcs8_private_keys(&mut &include_bytes!("../../../testdata/client.key")[..])
+                .map_err(invalid)?
+                .pop()
+                .ok_or_else(|| invalid(()))?;
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(certs, key)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(TlsAcceptor::Rustls(Arc::new(config)))
+    }
+
+    fn accept(&self, stream: TcpStream) -> io::Result<ServerStream> {
+        match self {
+            #[cfg(feature = "native-tls")]
+            TlsAcceptor::NativeTls(acceptor) => acceptor
+                .accept(stream)
+                .map(|stream| ServerStream::NativeTls(Box::new(stream)))
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+            #[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+            TlsAcceptor::Rustls(config) => {
+                let session = ServerSession::new(config);
+                Ok(ServerStream::Rustls(Box::new(StreamOwned::new(
+                    session, stream,
+                ))))
+            }
+        }
+    }
+}
+
+/// Connection of a client to the server
+enum ServerStream {
+    Tcp(TcpStream),
+    #[cfg(feature = "native-tls")]
+    NativeTls(Box<native_tls::TlsStream<TcpStream>>),
+    #[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+    Rustls(Box<StreamOwned<ServerSession, TcpStream>>),
+}
+
+impl ServerStream {
+    fn shutdown(&self) {
+        let stream = match self {
+            ServerStream::Tcp(stream) => stream,
+            #[cfg(feature = "native-tls")]
+            ServerStream::NativeTls(stream) => stream.get_ref(),
+            #[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+            ServerStream::Rustls(stream) => &stream.sock,
+        };
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl Read for ServerStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ServerStream::Tcp(stream) => stream.read(buf),
+            #[cfg(feature = "native-tls")]
+            ServerStream::NativeTls(stream) => stream.read(buf),
+            #[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+            ServerStream::Rustls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ServerStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ServerStream::Tcp(stream) => stream.write(buf),
+            #[cfg(feature = "native-tls")]
+            ServerStream::NativeTls(stream) => stream.write(buf),
+            #[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+            ServerStream::Rustls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ServerStream::Tcp(stream) => stream.flush(),
+            #[cfg(feature = "native-tls")]
+            ServerStream::NativeTls(stream) => stream.flush(),
+            #[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+            ServerStream::Rustls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// What happened to a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    /// A positive response was sent
+    Accepted,
+    /// A negative response was sent
+    Rejected,
+    /// The connection was closed
+    Closed,
+}
+
+/// State of a client session
+struct Session {
+    config: Arc<Config>,
+    stream: Option<BufReader<ServerStream>>,
+    encrypted: bool,
+    user: Option<String>,
+    from: Option<Option<Address>>,
+    to: Vec<Address>,
+}
+
+impl Session {
+    fn new(config: Arc<Config>, stream: TcpStream) -> Self {
+        Self {
+            config,
+            stream: Some(BufReader::new(ServerStream::Tcp(stream))),
+            encrypted: false,
+            user: None,
+            from: None,
+            to: Vec::new(),
+        }
+    }
+
+    fn stream(&mut self) -> &mut BufReader<ServerStream> {
+        self.stream.as_mut().expect("connection closed")
+    }
+
+    fn serve(mut self) -> io::Result<()> {
+        let greeting = response(
+            Severity::PositiveCompletion,
+            Category::Connections,
+            Detail::Zero,
+            &format!("{} ESMTP lettre test server", self.config.hostname),
+        );
+        if self.reply(Stage::Greeting, greeting)? != Flow::Accepted {
+            return self.close();
+        }
+
+        while let Some(line) = self.read_line()? {
+            let verb = line
+                .split(' ')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_uppercase();
+            let flow = match verb.as_str() {
+                "EHLO" => self.ehlo(true)?,
+                "HELO" => self.ehlo(false)?,
+                #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+                "STARTTLS" if self.config.acceptor.is_some() && !self.encrypted => {
+                    self.starttls()?
+                }
+                "AUTH" if self.config.credentials.is_some() => self.auth(&line)?,
+                "MAIL" => self.mail(&line)?,
+                "RCPT" => self.rcpt(&line)?,
+                "DATA" => self.data()?,
+                "RSET" => {
+                    let flow = self.reply(Stage::Rset, ok())?;
+                    if flow == Flow::Accepted {
+                        self.reset();
+                    }
+                    flow
+                }
+                "NOOP" => self.reply(Stage::Noop, ok())?,
+                "QUIT" => {
+                    let bye = response(
+                        Severity::PositiveCompletion,
+                        Category::Connections,
+                        Detail::One,
+                        "Bye",
+                    );
+                    self.reply(Stage::Quit, bye)?;
+                    Flow::Closed
+                }
+                _ => {
+                    self.write(&response(
+                        Severity::PermanentNegativeCompletion,
+                        Category::Syntax,
+                        Detail::Two,
+                        "Command not recognized",
+                    ))?;
+                    Flow::Rejected
+                }
+            };
+            if flow == Flow::Closed {
+                return self.close();
+            }
+        }
+        Ok(())
+    }
+
+    fn ehlo(&mut self, extended: bool) -> io::Result<Flow> {
+        let mut lines = vec![self.config.hostname.clone()];
+        if extended {
+            lines.extend(self.config.extensions.iter().map(ToString::to_string));
+            #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+            {
+                if self.config.acceptor.is_some() && !self.encrypted {
+                    lines.push(Extension::StartTls.to_string());
+                }
+            }
+            if self.config.credentials.is_some() {
+                lines.push(format!("AUTH {} {}", Mechanism::Plain, Mechanism::Login));
+            }
+        }
+        let flow = self.reply(
+            Stage::Ehlo,
+            Response::new(
+                Code::new(
+                    Severity::PositiveCompletion,
+                    Category::MailSystem,
+                    Detail::Zero,
+                ),
+                lines,
+            ),
+        )?;
+        if flow == Flow::Accepted {
+            self.reset();
+        }
+        Ok(flow)
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    fn starttls(&mut self) -> io::Result<Flow> {
+        let ready = response(
+            Severity::PositiveCompletion,
+            Category::Connections,
+            Detail::Zero,
+            "Ready to start TLS",
+        );
+        let flow = self.reply(Stage::StartTls, ready)?;
+        if flow != Flow::Accepted {
+            return Ok(flow);
+        }
+
+        let stream = match self.stream.take().map(BufReader::into_inner) {
+            Some(ServerStream::Tcp(stream)) => stream,
+            _ => unreachable!("STARTTLS is only announced over plain connections"),
+        };
+        let acceptor = self
+            .config
+            .acceptor
+            .as_ref()
+            .expect("STARTTLS is announced");
+        self.stream = Some(BufReader::new(acceptor.accept(stream)?));
+        self.encrypted = true;
+        self.user = None;
+        self.reset();
+        Ok(Flow::Accepted)
+    }
+
+    fn auth(&mut self, line: &str) -> io::Result<Flow> {
+        if let Some(reply) = self.config.scripted(Stage::Auth) {
+            let flow = self.send_reply(reply)?;
+            if flow == Flow::Accepted {
+                self.user = self
+                    .config
+                    .credentials
+                    .as_ref()
+                    .map(|credentials| credentials.authentication_identity.clone());
+            }
+            return Ok(flow);
+        }
+
+        let credentials = self.config.credentials.clone().expect("AUTH is announced");
+        let mut words = line.split(' ').skip(1);
+        let mechanism = words.next().unwrap_or_default().to_ascii_uppercase();
+        let initial_response = words.next().map(ToString::to_string);
+
+        let valid = match mechanism.as_str() {
+            "PLAIN" => {
+                let data = match initial_response {
+                    Some(data) => data,
+                    None => self.challenge("")?,
+                };
+                decode(&data) == Mechanism::Plain.response(&credentials, None).ok()
+            }
+            "LOGIN" => {
+                let username = self.challenge("Username:")?;
+                let password = self.challenge("Password:")?;
+                decode(&username).as_ref() == Some(&credentials.authentication_identity)
+                    && decode(&password).as_ref() == Some(&credentials.secret)
+            }
+            _ => {
+                self.write(&response(
+                    Severity::PermanentNegativeCompletion,
+                    Category::Syntax,
+                    Detail::Four,
+                    "Unrecognized authentication type",
+                ))?;
+                return Ok(Flow::Rejected);
+            }
+        };
+
+        if valid {
+            self.user = Some(credentials.authentication_identity);
+            self.write(&response(
+                Severity::PositiveCompletion,
+                Category::Unspecified3,
+                Detail::Five,
+                "Authentication succeeded",
+            ))?;
+            Ok(Flow::Accepted)
+        } else {
+            self.write(&response(
+                Severity::PermanentNegativeCompletion,
+                Category::Unspecified3,
+                Detail::Five,
+                "Authentication credentials invalid",
+            ))?;
+            Ok(Flow::Rejected)
+        }
+    }
+
+    /// Sends a `334` challenge and reads the answer of the client
+    fn challenge(&mut self, challenge: &str) -> io::Result<String> {
+        self.write(&response(
+            Severity::PositiveIntermediate,
+            Category::Unspecified3,
+            Detail::Four,
+            &base64::encode(challenge),
+        ))?;
+        Ok(self.read_line()?.unwrap_or_default())
+    }
+
+    fn mail(&mut self, line: &str) -> io::Result<Flow> {
+        if self.config.credentials.is_some() && self.user.is_none() {
+            self.write(&response(
+                Severity::PermanentNegativeCompletion,
+                Category::Unspecified3,
+                Detail::Zero,
+                "Authentication required",
+            ))?;
+            return Ok(Flow::Rejected);
+        }
+        let from = match path(line, "MAIL FROM:") {
+            Some(Ok(from)) => from,
+            _ => return self.syntax_error(),
+        };
+
+        let flow = self.reply(Stage::Mail, ok())?;
+        if flow == Flow::Accepted {
+            self.from = Some(from);
+            self.to.clear();
+        }
+        Ok(flow)
+    }
+
+    fn rcpt(&mut self, line: &str) -> io::Result<Flow> {
+        if self.from.is_none() {
+            return self.bad_sequence();
+        }
+        let to = match path(line, "RCPT TO:") {
+            Some(Ok(Some(to))) => to,
+            _ => return self.syntax_error(),
+        };
+
+        let rejection = self
+            .config
+            .rejected_recipients
+            .iter()
+            .find(|(address, _)| *address == to)
+            .map(|(_, response)| response.clone());
+        let flow = self.reply(Stage::Rcpt, rejection.unwrap_or_else(ok))?;
+        if flow == Flow::Accepted {
+            self.to.push(to);
+        }
+        Ok(flow)
+    }
+
+    fn data(&mut self) -> io::Result<Flow> {
+        let from = match (&self.from, self.to.is_empty()) {
+            (Some(from), false) => from.clone(),
+            _ => return self.bad_sequence(),
+        };
+        let start = response(
+            Severity::PositiveIntermediate,
+            Category::MailSystem,
+            Detail::Four,
+            "Start mail input; end with <CRLF>.<CRLF>",
+        );
+        let flow = self.reply(Stage::Data, start)?;
+        if flow != Flow::Accepted {
+            return Ok(flow);
+        }
+
+        let mut data = Vec::new();
+        loop {
+            let mut line = Vec::new();
+            if self.stream().read_until(b'\n', &mut line)? == 0 {
+                return Ok(Flow::Closed);
+            }
+            match line.as_slice() {
+                b".\r\n" | b".\n" => break,
+                [b'.', rest @ ..] => data.extend_from_slice(rest),
+                _ => data.extend_from_slice(&line),
+            }
+        }
+
+        // Record the message before replying, so that it can be checked as soon as the
+        // client is done
+        let reply = self
+            .config
+            .scripted(Stage::Message)
+            .unwrap_or_else(|| Reply::Respond(ok()));
+        if matches!(&reply, Reply::Respond(response) if response.is_positive()) {
+            let envelope = Envelope::new(from, std::mem::take(&mut self.to))
+                .expect("the envelope has recipients");
+            self.config.messages.lock().unwrap().push(ReceivedMessage {
+                envelope,
+                data,
+                encrypted: self.encrypted,
+                user: self.user.clone(),
+            });
+        }
+        self.reset();
+        self.send_reply(reply)
+    }
+
+    fn syntax_error(&mut self) -> io::Result<Flow> {
+        self.write(&response(
+            Severity::PermanentNegativeCompletion,
+            Category::Syntax,
+            Detail::One,
+            "Syntax error in parameters or arguments",
+        ))?;
+        Ok(Flow::Rejected)
+    }
+
+    fn bad_sequence(&mut self) -> io::Result<Flow> {
+        self.write(&response(
+            Severity::PermanentNegativeCompletion,
+            Category::Syntax,
+            Detail::Three,
+            "Bad sequence of commands",
+        ))?;
+        Ok(Flow::Rejected)
+    }
+
+    fn reset(&mut self) {
+        self.from = None;
+        self.to.clear();
+    }
+
+    /// Sends the scripted reply for the stage, or the default response
+    fn reply(&mut self, stage: Stage, default: Response) -> io::Result<Flow> {
+        let reply = self
+            .config
+            .scripted(stage)
+            .unwrap_or(Reply::Respond(default));
+        self.send_reply(reply)
+    }
+
+    fn send_reply(&mut self, reply: Reply) -> io::Result<Flow> {
+        match reply {
+            Reply::Respond(response) => {
+                self.write(&response)?;
+                Ok(if response.is_positive() {
+                    Flow::Accepted
+                } else {
+                    Flow::Rejected
+                })
+            }
+            Reply::Close => {
+                let closing = response(
+                    Severity::TransientNegativeCompletion,
+                    Category::Connections,
+                    Detail::One,
+                    &format!(
+                        "{} Service not available, closing transmission channel",
+                        self.config.hostname
+                    ),
+                );
+                self.write(&closing)?;
+                Ok(Flow::Closed)
+            }
+        }
+    }
+
+    fn write(&mut self, response: &Response) -> io::Result<()> {
+        let stream = self.stream().get_mut();
+        stream.write_all(format_response(response).as_bytes())?;
+        stream.flush()
+    }
+
+    /// Reads a command line, without its line break
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.stream().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).to_string()))
+    }
+
+    fn close(mut self) -> io::Result<()> {
+        if let Some(stream) = self.stream.take() {
+            stream.get_ref().shutdown();
+        }
+        Ok(())
+    }
+}
+
+fn response(severity: Severity, category: Category, detail: Detail, text: &str) -> Response {
+    Response::new(
+        Code::new(severity, category, detail),
+        vec![text.to_string()],
+    )
+}
+
+fn ok() -> Response {
+    response(
+        Severity::PositiveCompletion,
+        Category::MailSystem,
+        Detail::Zero,
+        "OK",
+    )
+}
+
+/// Formats a response as sent on the wire
+fn format_response(response: &Response) -> String {
+    if response.message.is_empty() {
+        return format!("{} \r\n", response.code);
+    }
+    let last = response.message.len() - 1;
+    response
+        .message
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let separator = if i == last { ' ' } else { '-' };
+            format!("{}{}{}\r\n", response.code, separator, line)
+        })
+        .collect()
+}
+
+/// Parses the reverse or forward path of a `MAIL` or `RCPT` command
+///
+/// Returns `None` when the command does not start with the prefix, and `Ok(None)` for the
+/// null reverse path `<>`.
+fn path(line: &str, prefix: &str) -> Option<Result<Option<Address>, ()>> {
+    let rest = match line.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => line[prefix.len()..].trim_start(),
+        _ => return None,
+    };
+    let end = rest.find('>')?;
+    if !rest.starts_with('<') {
+        return None;
+    }
+    Some(match &rest[1..end] {
+        "" => Ok(None),
+        address => address.parse().map(Some).map_err(|_| ()),
+    })
+}
+
+fn decode(data: &str) -> Option<String> {
+    base64::decode(data)
+        .ok()
+        .and_then(|data| String::from_utf8(data).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_responses() {
+        assert_eq!(format_response(&ok()), "250 OK\r\n");
+        let ehlo = Response::new(
+            ok().code,
+            vec!["lettre.test".to_string(), "8BITMIME".to_string()],
+        );
+        let formatted = format_response(&ehlo);
+        assert_eq!(formatted, "250-lettre.test\r\n250 8BITMIME\r\n");
+        assert_eq!(formatted.parse::<Response>().unwrap(), ehlo);
+    }
+
+    #[test]
+    fn parse_paths() {
+        assert_eq!(
+            path("MAIL FROM:<user@example.com> BODY=8BITMIME", "MAIL FROM:"),
+            Some(Ok(Some("user@example.com".parse().unwrap())))
+        );
+        assert_eq!(path("mail from: <>", "MAIL FROM:"), Some(Ok(None)));
+        assert_eq!(path("RCPT TO:<invalid>", "RCPT TO:"), Some(Err(())));
+        assert_eq!(path("RCPT TO:user@example.com", "RCPT TO:"), None);
+        assert_eq!(path("RCPT", "RCPT TO:"), None);
+        assert_eq!(path("RCPT T€:<user@example.com>", "RCPT TO:"), None);
+    }
+}
@@ -1,14 +1,27 @@
-use std::time::Duration;
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
 #[cfg(feature = "r2d2")]
 use r2d2::Pool;
 
-#[cfg(feature = "r2d2")]
-use super::PoolConfig;
-use super::{ClientId, Credentials, Error, Mechanism, Response, SmtpConnection, SmtpInfo};
+use super::{
+    client::SessionObserver,
+    failover::FailoverTransportBuilder,
+    lifecycle::{Lifecycle, SHUTDOWN_POLL_INTERVAL},
+    record_response_code, ClientId, Credentials, Error, Mechanism, Response, SmtpConnection,
+    SmtpInfo,
+};
 #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
-use super::{Tls, TlsParameters, SUBMISSIONS_PORT, SUBMISSION_PORT};
-use crate::{address::Envelope, Transport};
+use super::{client::TlsInfo, unbracket, Tls, TlsParameters, SUBMISSIONS_PORT, SUBMISSION_PORT};
+#[cfg(feature = "r2d2")]
+use super::{pool::Keepalive, PoolConfig};
+#[cfg(feature = "r2d2")]
+use crate::transport::metrics;
+use crate::{address::Envelope, transport::instrument::SendScope, Transport};
 
 #[allow(missing_debug_implementations)]
 #[derive(Clone)]
@@ -16,23 +29,122 @@ use crate::{address::Envelope, Transport};
 pub struct SmtpTransport {
     #[cfg(feature = "r2d2")]
     inner: Pool<SmtpClient>,
+    #[cfg(feature = "r2d2")]
+    client: SmtpClient,
+    #[cfg(feature = "r2d2")]
+    _keepalive: Option<Arc<Keepalive>>,
     #[cfg(not(feature = "r2d2"))]
     inner: SmtpClient,
+    lifecycle: Arc<Lifecycle>,
+}
+
+/// Response of the server which accepted the message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendResponse {
+    response: Response,
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    tls_info: Option<TlsInfo>,
+}
+
+impl SendResponse {
+    pub(crate) fn new(response: Response) -> Self {
+        Self {
+            response,
+            #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+            tls_info: None,
+        }
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    pub(crate) fn with_tls_info(mut self, tls_info: Option<TlsInfo>) -> Self {
+        self.tls_info = tls_info;
+        self
+    }
+
+    /// Response of the server to the message content
+    pub fn response(&self) -> &Response {
+        &self.response
+    }
+
+    /// Converts into the response of the server to the message content
+    pub fn into_response(self) -> Response {
+        self.response
+    }
+
+    /// Details of the TLS session the message was sent over,
+    /// or `None` if the connection was not encrypted
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "native-tls", feature = "rustls-tls"))))]
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls_info.as_ref()
+    }
 }
 
 impl Transport for SmtpTransport {
-    type Ok = Response;
+    type Ok = SendResponse;
     type Error = Error;
 
     /// Sends an email
     fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
+        self.send_attempt(envelope, email).0
+    }
+}
+
+impl SmtpTransport {
+    /// Sends an email, also telling if the server answered the `MAIL` command
+    ///
+    /// Once it did, a failure doesn't mean the server didn't receive the message.
+    pub(crate) fn send_attempt(
+        &self,
+        envelope: &Envelope,
+        email: &[u8],
+    ) -> (Result<SendResponse, Error>, bool) {
+        let scope = SendScope::new("smtp", envelope, email);
         #[cfg(feature = "r2d2")]
-        let mut conn = self.inner.get()?;
+        scope.record_relay(&self.client.info.server);
+        #[cfg(not(feature = "r2d2"))]
+        scope.record_relay(&self.inner.info.server);
+
+        let mut started = false;
+        let result = scope.in_scope(|| self.send_inner(envelope, email, &mut started));
+        record_response_code(&scope, result.as_ref().map(SendResponse::response));
+        scope.finish(&result);
+        (result, started)
+    }
+
+    fn send_inner(
+        &self,
+        envelope: &Envelope,
+        email: &[u8],
+        started: &mut bool,
+    ) -> Result<SendResponse, Error> {
+        let _in_flight = self.lifecycle.begin()?;
+
+        #[cfg(feature = "r2d2")]
+        let mut conn = {
+            let start = Instant::now();
+            let conn = self.inner.get();
+            metrics::record_histogram(
+                metrics::POOL_WAIT,
+                start.elapsed().as_secs_f64(),
+                &[("transport", "smtp")],
+            );
+            conn?
+        };
         #[cfg(not(feature = "r2d2"))]
         let mut conn = self.inner.connection()?;
 
-        let result = conn.send(envelope, email)?;
+        let result = SendResponse::new(conn.send_tracked(envelope, email, started)?);
+        // The message was accepted, don't fail because of the TLS details
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        let result = result.with_tls_info(conn.tls_info().ok().flatten());
 
+        #[cfg(feature = "r2d2")]
+        if self.lifecycle.is_closed() {
+            // The transport was shut down during the send,
+            // don't give the connection back to the pool
+            conn.abort();
+        }
         #[cfg(not(feature = "r2d2"))]
         conn.quit()?;
 
@@ -49,7 +161,7 @@ impl SmtpTransport {
     /// to validate TLS certificates.
     #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
     pub fn relay(relay: &str) -> Result<SmtpTransportBuilder, Error> {
-        let tls_parameters = TlsParameters::new(relay.into())?;
+        let tls_parameters = TlsParameters::new(unbracket(relay).into())?;
 
         Ok(Self::builder_dangerous(relay)
             .port(SUBMISSIONS_PORT)
@@ -69,13 +181,74 @@ impl SmtpTransport {
     /// or emails will be sent to the server, protecting from downgrade attacks.
     #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
     pub fn starttls_relay(relay: &str) -> Result<SmtpTransportBuilder, Error> {
-        let tls_parameters = TlsParameters::new(relay.into())?;
+        let tls_parameters = TlsParameters::new(unbracket(relay).into())?;
 
         Ok(Self::builder_dangerous(relay)
             .port(SUBMISSION_PORT)
             .tls(Tls::Required(tls_parameters)))
     }
 
+    /// Tests the connection to the server
+    ///
+    /// Opens a new connection, including encryption and authentication,
+    /// checks that the server answers a `NOOP` and closes it with `QUIT`.
+    /// No email is sent. Fails once the transport has been shut down.
+    pub fn test_connection(&self) -> Result<(), Error> {
+        #[cfg(feature = "r2d2")]
+        let mut conn = self.client.connection()?;
+        #[cfg(not(feature = "r2d2"))]
+        let mut conn = self.inner.connection()?;
+
+        conn.noop()?;
+        conn.quit()?;
+        Ok(())
+    }
+
+    /// Gracefully shuts down the transport
+    ///
+    /// New sends are refused as soon as this method is called. Sends which are
+    /// already in progress are given up to `timeout` to complete, then every idle
+    /// pooled connection is closed with a `QUIT` command.
+    ///
+    /// Returns an error if some sends were still in progress when the timeout
+    /// expired. Their connections are closed as soon as they complete.
+    pub fn shutdown(&self, timeout: Duration) -> Result<(), Error> {
+        self.lifecycle.close();
+
+        let deadline = Instant::now() + timeout;
+        while self.lifecycle.in_flight() > 0 && Instant::now() < deadline {
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+
+        #[cfg(feature = "r2d2")]
+        {
+            // Hold all idle connections at once so that each one is only seen once
+            let mut idle = Vec::new();
+            while let Some(conn) = self.inner.try_get() {
+                idle.push(conn);
+            }
+            for mut conn in idle {
+                // Sends QUIT and marks the connection as broken so that
+                // the pool drops it instead of taking it back
+                conn.abort();
+            }
+        }
+
+        if self.lifecycle.in_flight() > 0 {
+            return Err(Error::Client(
+                "timed out waiting for in-flight sends during shutdown",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Creates a transport sending through several relays
+    ///
+    /// See [`FailoverTransportBuilder`] for the available settings.
+    pub fn failover() -> FailoverTransportBuilder<SmtpTransport> {
+        FailoverTransportBuilder::new()
+    }
+
     /// Creates a new local SMTP client to port 25
     ///
     /// Shortcut for local unencrypted relay (typical local email daemon that will handle relaying)
@@ -121,6 +294,11 @@ pub struct SmtpTransportBuilder {
 
 /// Builder for the SMTP `SmtpTransport`
 impl SmtpTransportBuilder {
+    /// Server the transport connects to
+    pub(crate) fn server(&self) -> &str {
+        &self.info.server
+    }
+
     /// Set the name used during EHLO
     pub fn hello_name(mut self, name: ClientId) -> Self {
         self.info.hello_name = name;
@@ -141,7 +319,17 @@ impl SmtpTransportBuilder {
 
     /// Set the timeout duration
     pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
-        self.info.timeout = timeout;
+        self.info.connection = self.info.connection.timeout(timeout);
+        self
+    }
+
+    /// Set the host to connect to, which defaults to the relay name
+    ///
+    /// Allows connecting to a specific IP address or load balancer while still verifying
+    /// the TLS certificate against the relay name. IPv6 addresses can be written
+    /// with brackets, like `[::1]`.
+    pub fn connect_to<T: Into<String>>(mut self, host: T) -> Self {
+        self.info.connect_to = Some(host.into());
         self
     }
 
@@ -151,6 +339,24 @@ impl SmtpTransportBuilder {
         self
     }
 
+    /// Set the local address to bind outgoing connections to
+    ///
+    /// Only server addresses of the same family (IPv4 or IPv6) as the
+    /// source address will be tried.
+    pub fn source_address(mut self, source_address: IpAddr) -> Self {
+        self.info.connection = self.info.connection.source_address(source_address);
+        self
+    }
+
+    /// Set an observer receiving the commands and responses of every connection
+    ///
+    /// Authentication data and message contents are redacted by default,
+    /// see [`SessionObserver`](super::client::SessionObserver).
+    pub fn session_observer<O: SessionObserver + 'static>(mut self, observer: O) -> Self {
+        self.info.connection = self.info.connection.session_observer(observer);
+        self
+    }
+
     /// Set the TLS settings to use
     #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
     pub fn tls(mut self, tls: Tls) -> Self {
@@ -168,17 +374,40 @@ impl SmtpTransportBuilder {
         self
     }
 
+    /// Builds the client opening the connections, outside of a transport
+    #[cfg(all(test, feature = "r2d2", feature = "smtp-test-server"))]
+    pub(crate) fn client(self) -> SmtpClient {
+        SmtpClient {
+            info: self.info,
+            lifecycle: Arc::new(Lifecycle::default()),
+        }
+    }
+
     /// Build the transport
     ///
     /// If the `r2d2` feature is enabled an `Arc` wrapped pool is be created.
     /// Defaults can be found at [`PoolConfig`]
     pub fn build(self) -> SmtpTransport {
-        let client = SmtpClient { info: self.info };
+        let lifecycle = Arc::new(Lifecycle::default());
+        let client = SmtpClient {
+            info: self.info,
+            lifecycle: Arc::clone(&lifecycle),
+        };
+        #[cfg(feature = "r2d2")]
+        {
+            let pool = self.pool_config.build(client.clone());
+            let keepalive = self.pool_config.spawn_keepalive(&pool, &lifecycle);
+            SmtpTransport {
+                inner: pool,
+                client,
+                _keepalive: keepalive.map(Arc::new),
+                lifecycle,
+            }
+        }
+        #[cfg(not(feature = "r2d2"))]
         SmtpTransport {
-            #[cfg(feature = "r2d2")]
-            inner: self.pool_config.build(client),
-            #[cfg(not(feature = "r2d2"))]
             inner: client,
+            lifecycle,
         }
     }
 }
@@ -187,6 +416,7 @@ impl SmtpTransportBuilder {
 #[derive(Clone)]
 pub struct SmtpClient {
     info: SmtpInfo,
+    lifecycle: Arc<Lifecycle>,
 }
 
 impl SmtpClient {
@@ -194,6 +424,26 @@ impl SmtpClient {
     ///
     /// Handles encryption and authentication
     pub fn connection(&self) -> Result<SmtpConnection, Error> {
+        // Prevents the pool from opening new connections after shutdown
+        if self.lifecycle.is_closed() {
+            return Err(Error::Client("transport has been shut down"));
+        }
+
+        let mut conn = in_span!(
+            self.connect(),
+            "connect",
+            relay = self.info.server.as_str(),
+            port = self.info.port
+        )?;
+
+        if let Some(credentials) = &self.info.credentials {
+            conn.auth(&self.info.authentication, &credentials)?;
+        }
+        Ok(conn)
+    }
+
+    /// Connects to the server and negotiates TLS
+    fn connect(&self) -> Result<SmtpConnection, Error> {
         #[allow(clippy::match_single_binding)]
         let tls_parameters = match self.info.tls {
             #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
@@ -203,10 +453,10 @@ impl SmtpClient {
 
         #[allow(unused_mut)]
         let mut conn = SmtpConnection::connect::<(&str, u16)>(
-            (self.info.server.as_ref(), self.info.port),
-            self.info.timeout,
+            (self.info.host(), self.info.port),
             &self.info.hello_name,
             tls_parameters,
+            &self.info.connection,
         )?;
 
         #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
@@ -222,9 +472,6 @@ impl SmtpClient {
             _ => (),
         }
 
-        if let Some(credentials) = &self.info.credentials {
-            conn.auth(&self.info.authentication, &credentials)?;
-        }
         Ok(conn)
     }
 }
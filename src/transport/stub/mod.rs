@@ -1,10 +1,10 @@
-//! The stub transport only logs message envelope and drops the content. It can be useful for
-//! testing purposes.
+//! The stub transport records the messages it is given instead of sending them. It can be useful
+//! for testing purposes.
 //!
 //! #### Stub Transport
 //!
-//! The stub transport returns provided result and drops the content. It can be useful for
-//! testing purposes.
+//! The stub transport returns provided result and records the messages it is given. It can be
+//! useful for testing purposes.
 //!
 //! ```rust
 //! # #[cfg(feature = "builder")]
@@ -23,51 +23,362 @@
 //! let mut sender = StubTransport::new_ok();
 //! let result = sender.send(&email);
 //! assert!(result.is_ok());
+//!
+//! assert_eq!(sender.message_count(), 1);
+//! let sent = sender.last_message().unwrap();
+//! assert_eq!(sent.envelope(), email.envelope());
+//! assert_eq!(sent.header("Subject").as_deref(), Some("Happy new year"));
 //! # Ok(())
 //! # }
 //! # }
 //! ```
 
+use crate::{
+    address::{Address, Envelope},
+    transport::{instrument::SendScope, metrics::FailureClass},
+    Transport,
+};
+#[cfg(feature = "builder")]
+use crate::{message::utf8_b, transport::headers};
 #[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
-use crate::AsyncTransport;
-use crate::{address::Envelope, Transport};
+use crate::{AsyncTransport, Executor};
 #[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
 use async_trait::async_trait;
-use std::{error::Error as StdError, fmt};
+use std::{
+    error::Error as StdError,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// Error returned by a [`StubTransport`]
+///
+/// It can mimic the reply of an SMTP server, to test how the failures of the SMTP transport
+/// are handled.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Error {
+    reply: Option<(u16, String)>,
+}
 
-#[derive(Debug, Copy, Clone)]
-pub struct Error;
+impl Error {
+    /// Creates an error without SMTP reply
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an error mimicking an SMTP reply
+    ///
+    /// `4xx` codes are transient errors and `5xx` codes permanent ones.
+    pub fn reply<T: Into<String>>(code: u16, message: T) -> Self {
+        Self {
+            reply: Some((code, message.into())),
+        }
+    }
+
+    /// Returns the code of the mimicked SMTP reply
+    pub fn code(&self) -> Option<u16> {
+        self.reply.as_ref().map(|(code, _)| *code)
+    }
+
+    /// Returns the message of the mimicked SMTP reply
+    pub fn message(&self) -> Option<&str> {
+        self.reply.as_ref().map(|(_, message)| message.as_str())
+    }
+
+    /// Tells if the error mimics a transient SMTP error (4xx)
+    pub fn is_transient(&self) -> bool {
+        matches!(self.code(), Some(400..=499))
+    }
+
+    /// Tells if the error mimics a permanent SMTP error (5xx)
+    pub fn is_permanent(&self) -> bool {
+        matches!(self.code(), Some(500..=599))
+    }
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str("stub error")
+        match &self.reply {
+            Some((code, message)) => write!(f, "stub error: {} {}", code, message),
+            None => f.write_str("stub error"),
+        }
     }
 }
 
 impl StdError for Error {}
 
-/// This transport logs the message envelope and returns the given response
-#[derive(Debug, Clone, Copy)]
+impl FailureClass for Error {
+    fn failure_class(&self) -> &'static str {
+        if self.is_transient() {
+            "transient"
+        } else if self.is_permanent() {
+            "permanent"
+        } else {
+            "client"
+        }
+    }
+}
+
+/// Rule deciding the outcome of a send, from the number of previous sends and the envelope
+type Rule = Arc<dyn Fn(usize, &Envelope) -> Option<Result<(), Error>> + Send + Sync>;
+
+/// A message given to a [`StubTransport`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentMessage {
+    envelope: Envelope,
+    raw: Vec<u8>,
+}
+
+impl SentMessage {
+    /// Envelope the message was sent with
+    pub fn envelope(&self) -> &Envelope {
+        &self.envelope
+    }
+
+    /// Formatted message, as given to the transport
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// Headers of the message, in order
+    ///
+    /// Folded values are unfolded and encoded words are decoded.
+    #[cfg(feature = "builder")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "builder")))]
+    pub fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = headers::headers(&self.raw);
+        for (_, value) in &mut headers {
+            *value = decode_words(value);
+        }
+        headers
+    }
+
+    /// Decoded value of the first header with the name, ignoring case
+    #[cfg(feature = "builder")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "builder")))]
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.headers()
+            .into_iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+}
+
+/// Decodes the encoded words of a header value
+#[cfg(feature = "builder")]
+fn decode_words(value: &str) -> String {
+    value
+        .split(' ')
+        .map(|word| {
+            if word.starts_with("=?") && word.ends_with("?=") {
+                utf8_b::decode(word).unwrap_or_else(|| word.to_string())
+            } else {
+                word.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// This transport records the messages it is given and returns the given response
+///
+/// Clones share the recorded messages, so a clone can be given to the code under test and
+/// the messages inspected from the original, across threads.
+///
+/// The outcome of each send can be scripted, to test how failures are handled:
+///
+/// ```rust
+/// # #[cfg(feature = "builder")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use lettre::{
+///     transport::stub::{Error, StubTransport},
+///     Message, Transport,
+/// };
+///
+/// let sender = StubTransport::new_ok()
+///     .fail_domain("example.org", Error::reply(550, "No such domain"))
+///     .fail_first(2, Error::reply(421, "Try again later"));
+///
+/// let email = Message::builder()
+///     .from("NoBody <nobody@domain.tld>".parse()?)
+///     .to("Hei <hei@domain.tld>".parse()?)
+///     .subject("Happy new year")
+///     .body(String::from("Be happy!"))?;
+/// assert!(sender.send(&email).unwrap_err().is_transient());
+/// assert!(sender.send(&email).unwrap_err().is_transient());
+/// assert!(sender.send(&email).is_ok());
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "builder"))]
+/// # fn main() {}
+/// ```
+#[derive(Clone)]
 pub struct StubTransport {
     response: Result<(), Error>,
+    rules: Vec<Rule>,
+    latency: Option<Duration>,
+    #[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+    sleep: Option<Sleep>,
+    sends: Arc<AtomicUsize>,
+    messages: Arc<Mutex<Vec<SentMessage>>>,
+}
+
+impl fmt::Debug for StubTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StubTransport")
+            .field("response", &self.response)
+            .field("rules", &self.rules.len())
+            .field("latency", &self.latency)
+            .field("messages", &self.message_count())
+            .finish()
+    }
 }
 
 impl StubTransport {
     /// Creates a new transport that always returns the given Result
     pub fn new(response: Result<(), Error>) -> StubTransport {
-        StubTransport { response }
+        StubTransport {
+            response,
+            rules: Vec::new(),
+            latency: None,
+            #[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+            sleep: None,
+            sends: Arc::new(AtomicUsize::new(0)),
+            messages: Arc::new(Mutex::new(Vec::new())),
+        }
     }
 
     /// Creates a new transport that always returns a success response
     pub fn new_ok() -> StubTransport {
-        StubTransport { response: Ok(()) }
+        StubTransport::new(Ok(()))
     }
 
     /// Creates a new transport that always returns an error
     pub fn new_error() -> StubTransport {
-        StubTransport {
-            response: Err(Error),
-        }
+        StubTransport::new(Err(Error::new()))
+    }
+
+    /// Decides the outcome of each send with a function
+    ///
+    /// The function is given the number of previous sends and the envelope, and returns
+    /// `None` to leave the decision to the next rules.
+    ///
+    /// Rules are tried in the order they were added, and the response of the transport is
+    /// returned when none of them decides.
+    pub fn rule<F>(mut self, rule: F) -> Self
+    where
+        F: Fn(usize, &Envelope) -> Option<Result<(), Error>> + Send + Sync + 'static,
+    {
+        self.rules.push(Arc::new(rule));
+        self
+    }
+
+    /// Returns the outcomes in order for the next sends
+    pub fn outcomes(self, outcomes: Vec<Result<(), Error>>) -> Self {
+        self.rule(move |sends, _| outcomes.get(sends).cloned())
+    }
+
+    /// Fails the first sends with the error
+    pub fn fail_first(self, count: usize, error: Error) -> Self {
+        self.rule(move |sends, _| {
+            if sends < count {
+                Some(Err(error.clone()))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Fails the sends to a recipient of the domain with the error
+    pub fn fail_domain<T: Into<String>>(self, domain: T, error: Error) -> Self {
+        let domain = domain.into();
+        self.rule(move |_, envelope| {
+            if envelope
+                .to()
+                .iter()
+                .any(|address| address.domain().eq_ignore_ascii_case(&domain))
+            {
+                Some(Err(error.clone()))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Waits for the duration before returning from each send
+    ///
+    /// Async sends spawn a thread to time the duration, unless an executor is set with
+    /// [`StubTransport::executor`].
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Times the latency of async sends with the timer of the executor
+    #[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1")))
+    )]
+    pub fn executor<E: Executor>(mut self) -> Self {
+        self.sleep = Some(|duration| E::sleep(duration));
+        self
+    }
+
+    /// Returns the messages given to the transport, in order
+    ///
+    /// Messages are recorded whether their sending succeeded or not.
+    pub fn messages(&self) -> Vec<SentMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    /// Returns the number of messages given to the transport
+    pub fn message_count(&self) -> usize {
+        self.messages.lock().unwrap().len()
+    }
+
+    /// Returns the last message given to the transport
+    pub fn last_message(&self) -> Option<SentMessage> {
+        self.messages.lock().unwrap().last().cloned()
+    }
+
+    /// Returns the messages with the address among the envelope recipients
+    pub fn messages_to(&self, address: &Address) -> Vec<SentMessage> {
+        self.messages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| message.envelope.to().contains(address))
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets the recorded messages
+    pub fn clear(&self) {
+        self.messages.lock().unwrap().clear();
+    }
+
+    /// Records the message and decides the outcome of its sending
+    fn record(&self, envelope: &Envelope, email: &[u8]) -> Result<(), Error> {
+        self.messages.lock().unwrap().push(SentMessage {
+            envelope: envelope.clone(),
+            raw: email.to_vec(),
+        });
+        let sends = self.sends.fetch_add(1, Ordering::SeqCst);
+        self.rules
+            .iter()
+            .find_map(|rule| rule(sends, envelope))
+            .unwrap_or_else(|| self.response.clone())
     }
 }
 
@@ -75,8 +386,17 @@ impl Transport for StubTransport {
     type Ok = ();
     type Error = Error;
 
-    fn send_raw(&self, _envelope: &Envelope, _email: &[u8]) -> Result<Self::Ok, Self::Error> {
-        self.response
+    fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
+        let scope = SendScope::new("stub", envelope, email);
+        let result = scope.in_scope(|| {
+            let result = self.record(envelope, email);
+            if let Some(latency) = self.latency {
+                thread::sleep(latency);
+            }
+            result
+        });
+        scope.finish(&result);
+        result
     }
 }
 
@@ -86,7 +406,64 @@ impl AsyncTransport for StubTransport {
     type Ok = ();
     type Error = Error;
 
-    async fn send_raw(&self, _envelope: &Envelope, _email: &[u8]) -> Result<Self::Ok, Self::Error> {
-        self.response
+    async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
+        let scope = SendScope::new("stub", envelope, email);
+        let result = scope
+            .run(async {
+                let result = self.record(envelope, email);
+                match (self.latency, self.sleep) {
+                    (Some(latency), Some(sleep)) => sleep(latency).await,
+                    (Some(latency), None) => Delay::new(latency).await,
+                    (None, _) => {}
+                }
+                result
+            })
+            .await;
+        scope.finish(&result);
+        result
+    }
+}
+
+/// Sleep of an executor
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+type Sleep = fn(Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Future completing after a duration
+///
+/// The duration is timed by a thread, to work with any executor.
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+struct Delay {
+    state: Arc<Mutex<(bool, Option<Waker>)>>,
+}
+
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+impl Delay {
+    fn new(duration: Duration) -> Self {
+        let state = Arc::new(Mutex::new((false, None::<Waker>)));
+        let timer = Arc::clone(&state);
+        thread::spawn(move || {
+            thread::sleep(duration);
+            let mut state = timer.lock().unwrap();
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        });
+        Self { state }
+    }
+}
+
+#[cfg(any(feature = "tokio02", feature = "tokio1", feature = "async-std1"))]
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.0 {
+            Poll::Ready(())
+        } else {
+            state.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
     }
 }
@@ -0,0 +1,78 @@
+#[cfg(test)]
+#[cfg(feature = "builder")]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use lettre::{
+        transport::{
+            metrics::{self, Recorder},
+            stub::StubTransport,
+        },
+        Message, Transport,
+    };
+
+    type Events = Arc<Mutex<Vec<(&'static str, Vec<(&'static str, String)>)>>>;
+
+    struct TestRecorder(Events);
+
+    impl TestRecorder {
+        fn push(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+            let labels = labels
+                .iter()
+                .map(|(key, value)| (*key, value.to_string()))
+                .collect();
+            self.0.lock().unwrap().push((name, labels));
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn increment_counter(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+            self.push(name, labels);
+        }
+
+        fn record_histogram(
+            &self,
+            name: &'static str,
+            value: f64,
+            labels: &[(&'static str, &str)],
+        ) {
+            assert!(value >= 0.0);
+            self.push(name, labels);
+        }
+    }
+
+    #[test]
+    fn metrics_recorder() {
+        let events = Events::default();
+        metrics::set_recorder(TestRecorder(Arc::clone(&events))).unwrap();
+        assert!(metrics::set_recorder(TestRecorder(Events::default())).is_err());
+
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!"))
+            .unwrap();
+        StubTransport::new_ok().send(&email).unwrap();
+        StubTransport::new_error().send(&email).unwrap_err();
+
+        let stub = vec![("transport", "stub".to_string())];
+        let events = events.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![
+                (metrics::SENDS, stub.clone()),
+                (metrics::SEND_DURATION, stub.clone()),
+                (metrics::SENDS, stub.clone()),
+                (metrics::SEND_DURATION, stub),
+                (
+                    metrics::SEND_FAILURES,
+                    vec![
+                        ("transport", "stub".to_string()),
+                        ("class", "client".to_string())
+                    ]
+                ),
+            ]
+        );
+    }
+}
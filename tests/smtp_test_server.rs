@@ -0,0 +1,349 @@
+#[cfg(test)]
+#[cfg(all(feature = "smtp-test-server", feature = "builder"))]
+mod test {
+    use lettre::{
+        transport::smtp::{
+            authentication::{Credentials, Mechanism},
+            response::{Category, Code, Detail, Response, Severity},
+            test_server::{Reply, Stage, TestServer},
+            Error, SmtpTransportBuilder,
+        },
+        Message, SmtpTransport, Transport,
+    };
+
+    #[cfg(feature = "tokio1")]
+    use tokio1_crate as tokio;
+
+    fn email(to: &str) -> Message {
+        Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to(to.parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!\r\n.dot"))
+            .unwrap()
+    }
+
+    fn builder(server: &TestServer) -> SmtpTransportBuilder {
+        let builder = SmtpTransport::builder_dangerous("127.0.0.1").port(server.port());
+        // Don't retry failed connections until the default pool timeout
+        #[cfg(feature = "r2d2")]
+        let builder = builder.pool_config(
+            lettre::transport::smtp::PoolConfig::new()
+                .connection_timeout(std::time::Duration::from_millis(100)),
+        );
+        builder
+    }
+
+    fn sender(server: &TestServer) -> SmtpTransport {
+        builder(server).build()
+    }
+
+    fn response(severity: Severity, text: &str) -> Response {
+        Response::new(
+            Code::new(severity, Category::MailSystem, Detail::Zero),
+            vec![text.to_string()],
+        )
+    }
+
+    #[test]
+    fn test_server_records_messages() {
+        let server = TestServer::start().unwrap();
+        let email = email("Hei <hei@domain.tld>");
+        sender(&server).send(&email).unwrap();
+
+        let messages = server.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].envelope(), email.envelope());
+        assert_eq!(
+            messages[0].data(),
+            &[&email.formatted()[..], b"\r\n"].concat()[..]
+        );
+        assert!(!messages[0].is_encrypted());
+        assert_eq!(messages[0].user(), None);
+
+        server.clear();
+        assert!(server.messages().is_empty());
+    }
+
+    #[test]
+    fn test_server_rejects_recipient() {
+        let server = TestServer::builder()
+            .reject_recipient(
+                "unknown@domain.tld".parse().unwrap(),
+                response(Severity::PermanentNegativeCompletion, "No such user"),
+            )
+            .start()
+            .unwrap();
+
+        let result = sender(&server).send(&email("Unknown <unknown@domain.tld>"));
+        assert!(matches!(result, Err(Error::Permanent(_))));
+        assert!(server.messages().is_empty());
+    }
+
+    #[test]
+    fn test_server_scripted_replies() {
+        let server = TestServer::builder()
+            .reply(
+                Stage::Message,
+                Reply::Respond(response(
+                    Severity::TransientNegativeCompletion,
+                    "Try again later",
+                )),
+            )
+            .reply(Stage::Mail, Reply::Close)
+            .start()
+            .unwrap();
+        let sender = sender(&server);
+        let email = email("Hei <hei@domain.tld>");
+
+        match sender.send(&email) {
+            Err(Error::Transient(response)) => assert!(response.has_code(421)),
+            _ => panic!("expected the server to close the connection"),
+        }
+
+        let result = sender.send(&email);
+        assert!(matches!(result, Err(Error::Transient(_))));
+        assert!(server.messages().is_empty());
+
+        sender.send(&email).unwrap();
+        assert_eq!(server.messages().len(), 1);
+    }
+
+    #[test]
+    fn test_server_authentication() {
+        let server = TestServer::builder()
+            .credentials(Credentials::new("user".to_string(), "password".to_string()))
+            .start()
+            .unwrap();
+        let email = email("Hei <hei@domain.tld>");
+
+        assert!(sender(&server).send(&email).is_err());
+
+        for mechanism in &[Mechanism::Plain, Mechanism::Login] {
+            builder(&server)
+                .credentials(Credentials::new("user".to_string(), "password".to_string()))
+                .authentication(vec![*mechanism])
+                .build()
+                .send(&email)
+                .unwrap();
+        }
+        let result = builder(&server)
+            .credentials(Credentials::new("user".to_string(), "wrong".to_string()))
+            .build()
+            .send(&email);
+        assert!(result.is_err());
+
+        let messages = server.messages();
+        assert_eq!(messages.len(), 2);
+        assert!(messages
+            .iter()
+            .all(|message| message.user() == Some("user")));
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[test]
+    fn test_server_starttls() {
+        use lettre::transport::smtp::client::{Tls, TlsParameters};
+
+        let server = TestServer::builder().starttls().start().unwrap();
+        let tls_parameters = TlsParameters::builder("lettre.test".to_string())
+            .dangerous_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        builder(&server)
+            .tls(Tls::Required(tls_parameters))
+            .build()
+            .send(&email("Hei <hei@domain.tld>"))
+            .unwrap();
+
+        let messages = server.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].is_encrypted());
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[test]
+    fn test_server_failover_tls_info() {
+        use lettre::transport::smtp::client::{Tls, TlsParameters};
+
+        let server = TestServer::builder().starttls().start().unwrap();
+        let tls_parameters = TlsParameters::builder("lettre.test".to_string())
+            .dangerous_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let response = SmtpTransport::failover()
+            .relay(builder(&server).tls(Tls::Required(tls_parameters)))
+            .build()
+            .send(&email("Hei <hei@domain.tld>"))
+            .unwrap();
+        assert!(response.tls_info().is_some());
+    }
+
+    /// Starts a relay closing the connection when it receives the `MAIL` command, like
+    /// one which dropped a pooled connection
+    fn dropping_relay() -> u16 {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+            thread,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                let _ = stream.write_all(b"220 relay.test\r\n");
+                let _ = reader.read_line(&mut line);
+                let _ = stream.write_all(b"250 relay.test\r\n");
+                let _ = reader.read_line(&mut line);
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_server_failover_dropped_connection() {
+        let server = TestServer::start().unwrap();
+        let dropping = SmtpTransport::builder_dangerous("127.0.0.1").port(dropping_relay());
+        #[cfg(feature = "r2d2")]
+        let dropping = dropping
+            .pool_config(lettre::transport::smtp::PoolConfig::new().test_on_checkout(false));
+        let sender = SmtpTransport::failover()
+            .relay(dropping)
+            .relay(builder(&server))
+            .build();
+
+        sender.send(&email("Hei <hei@domain.tld>")).unwrap();
+        assert_eq!(server.messages().len(), 1);
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    fn mx_sender(server: &TestServer) -> lettre::transport::smtp::mx::MxTransport {
+        use lettre::transport::smtp::mx::{MxTransport, StaticResolver};
+
+        let resolver = StaticResolver::new()
+            .mx("domain.tld", 10, "mx.domain.tld")
+            .ip("mx.domain.tld", "127.0.0.1".parse().unwrap());
+        MxTransport::builder(resolver).port(server.port()).build()
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[test]
+    fn test_server_mx_opportunistic_tls() {
+        // The test server certificate is self-signed
+        let server = TestServer::builder().starttls().start().unwrap();
+        let response = mx_sender(&server)
+            .send(&email("Hei <hei@domain.tld>"))
+            .unwrap();
+        assert!(response.is_complete());
+        let delivery = response.deliveries()[0].result().as_ref().unwrap();
+        assert!(delivery.tls_info().is_some());
+
+        let messages = server.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].is_encrypted());
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[test]
+    fn test_server_mx_opportunistic_tls_fallback() {
+        let server = TestServer::builder()
+            .starttls()
+            .reply(
+                Stage::StartTls,
+                Reply::Respond(response(
+                    Severity::TransientNegativeCompletion,
+                    "TLS not available",
+                )),
+            )
+            .start()
+            .unwrap();
+        let response = mx_sender(&server)
+            .send(&email("Hei <hei@domain.tld>"))
+            .unwrap();
+        assert!(response.is_complete());
+        let delivery = response.deliveries()[0].result().as_ref().unwrap();
+        assert!(delivery.tls_info().is_none());
+
+        let messages = server.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(!messages[0].is_encrypted());
+    }
+
+    #[cfg(feature = "tokio1")]
+    #[tokio::test]
+    async fn test_server_tokio1() {
+        use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+        let server = TestServer::start().unwrap();
+        let email = email("Hei <hei@domain.tld>");
+        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+            .port(server.port())
+            .build::<Tokio1Executor>()
+            .send(email.clone())
+            .await
+            .unwrap();
+
+        let messages = server.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].envelope(), email.envelope());
+    }
+
+    // TLS parameters use native-tls when it is enabled
+    #[cfg(any(
+        feature = "tokio1-native-tls",
+        all(feature = "tokio1-rustls-tls", not(feature = "native-tls"))
+    ))]
+    #[tokio::test]
+    async fn test_server_tokio1_tls_info() {
+        use lettre::{
+            transport::smtp::client::{Tls, TlsParameters},
+            AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+        };
+
+        let server = TestServer::builder().starttls().start().unwrap();
+        let tls_parameters = TlsParameters::builder("lettre.test".to_string())
+            .dangerous_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let response = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+            .port(server.port())
+            .tls(Tls::Required(tls_parameters))
+            .build::<Tokio1Executor>()
+            .send(email("Hei <hei@domain.tld>"))
+            .await
+            .unwrap();
+        assert!(response.response().is_positive());
+        assert!(response.tls_info().is_some());
+    }
+
+    #[test]
+    fn test_server_test_connection_after_shutdown() {
+        let server = TestServer::start().unwrap();
+        let sender = sender(&server);
+        sender.test_connection().unwrap();
+
+        sender.shutdown(std::time::Duration::from_secs(1)).unwrap();
+        assert!(sender.test_connection().is_err());
+    }
+
+    #[cfg(feature = "tokio1")]
+    #[tokio::test]
+    async fn test_server_tokio1_test_connection_after_shutdown() {
+        use lettre::{AsyncSmtpTransport, Tokio1Executor};
+
+        let server = TestServer::start().unwrap();
+        let sender = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+            .port(server.port())
+            .build::<Tokio1Executor>();
+        sender.test_connection().await.unwrap();
+
+        sender
+            .shutdown(std::time::Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(sender.test_connection().await.is_err());
+    }
+}
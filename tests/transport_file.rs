@@ -4,7 +4,7 @@ mod test {
     use lettre::{transport::file::FileTransport, Message};
     use std::{
         env::temp_dir,
-        fs::{read_to_string, remove_file},
+        fs::{read, read_dir, read_to_string, remove_dir_all, remove_file},
     };
 
     #[cfg(feature = "tokio02")]
@@ -96,6 +96,244 @@ mod test {
         remove_file(json_file).unwrap();
     }
 
+    #[test]
+    fn file_transport_maildir() {
+        use lettre::Transport;
+        let maildir = temp_dir().join("lettre-maildir");
+        let sender = FileTransport::new(&maildir).maildir();
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .date("Tue, 15 Nov 1994 08:12:31 GMT".parse().unwrap())
+            .body(String::from("Be happy!"))
+            .unwrap();
+
+        let first = sender.send(&email).unwrap();
+        let second = sender.send(&email).unwrap();
+        assert_ne!(first, second);
+
+        let eml_file = maildir.join("new").join(&first);
+        assert_eq!(read(&eml_file).unwrap(), email.formatted());
+        assert!(read_dir(maildir.join("tmp")).unwrap().next().is_none());
+        assert!(maildir.join("cur").is_dir());
+
+        remove_dir_all(maildir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "file-transport-envelope")]
+    fn file_transport_maildir_with_envelope() {
+        use lettre::Transport;
+        let maildir = temp_dir().join("lettre-maildir-envelope");
+        let sender = FileTransport::with_envelope(&maildir).maildir();
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .date("Tue, 15 Nov 1994 08:12:31 GMT".parse().unwrap())
+            .body(String::from("Be happy!"))
+            .unwrap();
+
+        let id = sender.send(&email).unwrap();
+        assert!(maildir
+            .join("envelope")
+            .join(format!("{}.json", id))
+            .is_file());
+
+        let (e, m) = sender.read(&id).unwrap();
+        assert_eq!(&e, email.envelope());
+        assert_eq!(m, email.formatted());
+
+        // Mail clients move seen messages to `cur`, with flags
+        std::fs::rename(
+            maildir.join("new").join(&id),
+            maildir.join("cur").join(format!("{}:2,S", id)),
+        )
+        .unwrap();
+        let (e, m) = sender.read(&id).unwrap();
+        assert_eq!(&e, email.envelope());
+        assert_eq!(m, email.formatted());
+
+        remove_dir_all(maildir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "file-transport-envelope")]
+    fn file_transport_naming() {
+        use lettre::{transport::file::Naming, Transport};
+        let dir = temp_dir().join("lettre-naming");
+        std::fs::create_dir_all(&dir).unwrap();
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .message_id(Some("<naming.test@domain.tld>".to_string()))
+            .body(String::from("Be happy!"))
+            .unwrap();
+
+        let sender = FileTransport::with_envelope(&dir).naming(Naming::MessageId);
+        let id = sender.send(&email).unwrap();
+        assert_eq!(id, "naming.test@domain.tld");
+        assert!(dir.join("naming.test@domain.tld.eml").is_file());
+
+        let sender = FileTransport::with_envelope(&dir).naming(Naming::Daily);
+        let id = sender.send(&email).unwrap();
+        assert_eq!(id.matches('/').count(), 3);
+        assert!(dir.join(format!("{}.json", id)).is_file());
+
+        let (e, m) = sender.read(&id).unwrap();
+        assert_eq!(&e, email.envelope());
+        assert_eq!(m, email.formatted());
+
+        let sender = FileTransport::new(&dir).naming(Naming::Timestamp);
+        let first = sender.send(&email).unwrap();
+        let second = sender.send(&email).unwrap();
+        assert!(first[..23] <= second[..23]);
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "file-transport-envelope")]
+    fn file_transport_outbox() {
+        use lettre::{transport::file::Naming, Transport};
+        let dir = temp_dir().join("lettre-outbox");
+        let failed = temp_dir().join("lettre-outbox-failed");
+        std::fs::create_dir_all(&dir).unwrap();
+        let outbox = FileTransport::with_envelope(&dir).naming(Naming::Daily);
+        let email = |to: &str| {
+            Message::builder()
+                .from("NoBody <nobody@domain.tld>".parse().unwrap())
+                .to(to.parse().unwrap())
+                .subject("Happy new year")
+                .body(String::from("Be happy!"))
+                .unwrap()
+        };
+
+        let first = outbox.send(&email("Hei <hei@domain.tld>")).unwrap();
+        let second = outbox.send(&email("Yuin <yuin@domain.tld>")).unwrap();
+        let mut ids = vec![first.clone(), second.clone()];
+        ids.sort();
+        // Messages without a readable envelope are skipped
+        std::fs::write(dir.join("stray.eml"), "Subject: stray\r\n\r\n").unwrap();
+        std::fs::write(dir.join("broken.eml"), "Subject: broken\r\n\r\n").unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+
+        let messages = outbox.list().unwrap();
+        assert_eq!(
+            messages
+                .iter()
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<_>>(),
+            ids
+        );
+        let (_, envelope) = messages.iter().find(|(id, _)| *id == second).unwrap();
+        assert_eq!(envelope.to(), &["yuin@domain.tld".parse().unwrap()]);
+
+        outbox.move_to(&first, &failed).unwrap();
+        let failed_outbox = FileTransport::with_envelope(&failed).naming(Naming::Daily);
+        assert_eq!(failed_outbox.list().unwrap().len(), 1);
+        let (envelope, _) = failed_outbox.read(&first).unwrap();
+        assert_eq!(envelope.to(), &["hei@domain.tld".parse().unwrap()]);
+
+        outbox.remove(&second).unwrap();
+        assert!(outbox.list().unwrap().is_empty());
+        assert!(outbox.read(&second).is_err());
+
+        remove_dir_all(dir).unwrap();
+        remove_dir_all(failed).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "file-transport-envelope")]
+    fn file_transport_maildir_outbox() {
+        use lettre::Transport;
+        let maildir = temp_dir().join("lettre-maildir-outbox");
+        let outbox = FileTransport::with_envelope(&maildir).maildir();
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!"))
+            .unwrap();
+
+        let first = outbox.send(&email).unwrap();
+        let second = outbox.send(&email).unwrap();
+        std::fs::rename(
+            maildir.join("new").join(&first),
+            maildir.join("cur").join(format!("{}:2,S", first)),
+        )
+        .unwrap();
+
+        let ids: Vec<_> = outbox
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&first) && ids.contains(&second));
+
+        outbox.remove(&first).unwrap();
+        outbox.remove(&second).unwrap();
+        assert!(outbox.list().unwrap().is_empty());
+
+        remove_dir_all(maildir).unwrap();
+    }
+
+    #[test]
+    fn file_transport_mbox() {
+        use lettre::Transport;
+        let mbox = temp_dir().join("lettre-test.mbox");
+        let _ = remove_file(&mbox);
+        let sender = FileTransport::new(&mbox).mbox();
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .date("Tue, 15 Nov 1994 08:12:31 GMT".parse().unwrap())
+            .body(String::from("Be happy!\r\nFrom me"))
+            .unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let sender = FileTransport::new(&mbox).mbox();
+                let email = email.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        sender.send(&email).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let offset: usize = sender.send(&email).unwrap().parse().unwrap();
+
+        let content = read_to_string(&mbox).unwrap();
+        let entry = &content[offset..];
+        assert!(entry.starts_with("From nobody@domain.tld "));
+        assert!(entry.ends_with(concat!(
+            "\n",
+            "From: NoBody <nobody@domain.tld>\n",
+            "To: Hei <hei@domain.tld>\n",
+            "Subject: Happy new year\n",
+            "Date: Tue, 15 Nov 1994 08:12:31 GMT\n",
+            "Content-Transfer-Encoding: 7bit\n",
+            "\n",
+            "Be happy!\n",
+            ">From me\n",
+            "\n"
+        )));
+        // Every entry was written whole
+        assert_eq!(content.len(), entry.len() * 41);
+        assert_eq!(content.matches("\nFrom nobody@domain.tld ").count(), 40);
+
+        remove_file(mbox).unwrap();
+    }
+
     #[cfg(feature = "async-std1")]
     #[async_std::test]
     async fn file_transport_asyncstd1() {
@@ -169,4 +407,92 @@ mod test {
         );
         remove_file(eml_file).unwrap();
     }
+
+    #[cfg(feature = "tokio02")]
+    #[tokio::test]
+    async fn file_transport_maildir_tokio02() {
+        use lettre::{AsyncFileTransport, AsyncTransport, Tokio02Executor};
+
+        let maildir = temp_dir().join("lettre-maildir-tokio02");
+        let sender = AsyncFileTransport::<Tokio02Executor>::new(&maildir).maildir();
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .date("Tue, 15 Nov 1994 08:12:31 GMT".parse().unwrap())
+            .body(String::from("Be happy!"))
+            .unwrap();
+
+        let id = sender.send(email.clone()).await.unwrap();
+
+        let eml_file = maildir.join("new").join(&id);
+        assert_eq!(read(&eml_file).unwrap(), email.formatted());
+        assert!(read_dir(maildir.join("tmp")).unwrap().next().is_none());
+
+        remove_dir_all(maildir).unwrap();
+    }
+
+    #[cfg(feature = "tokio02")]
+    #[tokio::test]
+    async fn file_transport_mbox_tokio02() {
+        use lettre::{AsyncFileTransport, AsyncTransport, Tokio02Executor};
+
+        let mbox = temp_dir().join("lettre-test-tokio02.mbox");
+        let _ = remove_file(&mbox);
+        let sender = AsyncFileTransport::<Tokio02Executor>::new(&mbox).mbox();
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .date("Tue, 15 Nov 1994 08:12:31 GMT".parse().unwrap())
+            .body(String::from("Be happy!"))
+            .unwrap();
+
+        assert_eq!(sender.send(email.clone()).await.unwrap(), "0");
+        let offset: usize = sender.send(email).await.unwrap().parse().unwrap();
+
+        let content = read_to_string(&mbox).unwrap();
+        assert_eq!(&content[..offset], &content[offset..]);
+        assert!(content.starts_with("From nobody@domain.tld "));
+        assert!(!temp_dir().join("lettre-test-tokio02.mbox.lock").exists());
+
+        remove_file(mbox).unwrap();
+    }
+
+    #[cfg(all(feature = "tokio02", feature = "file-transport-envelope"))]
+    #[tokio::test]
+    async fn file_transport_outbox_tokio02() {
+        use lettre::{AsyncFileTransport, AsyncTransport, Tokio02Executor};
+
+        let dir = temp_dir().join("lettre-outbox-tokio02");
+        let archive = temp_dir().join("lettre-outbox-tokio02-archive");
+        std::fs::create_dir_all(&dir).unwrap();
+        let outbox = AsyncFileTransport::<Tokio02Executor>::with_envelope(&dir);
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!"))
+            .unwrap();
+
+        let first = outbox.send(email.clone()).await.unwrap();
+        let second = outbox.send(email.clone()).await.unwrap();
+        std::fs::write(dir.join("stray.eml"), "Subject: stray\r\n\r\n").unwrap();
+
+        let messages = outbox.list().await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages
+            .iter()
+            .all(|(_, envelope)| envelope == email.envelope()));
+
+        outbox.move_to(&first, &archive).await.unwrap();
+        assert!(archive.join(format!("{}.eml", first)).is_file());
+        assert!(archive.join(format!("{}.json", first)).is_file());
+
+        outbox.remove(&second).await.unwrap();
+        assert!(outbox.list().await.unwrap().is_empty());
+
+        remove_dir_all(dir).unwrap();
+        remove_dir_all(archive).unwrap();
+    }
 }
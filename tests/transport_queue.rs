@@ -0,0 +1,254 @@
+#[cfg(test)]
+#[cfg(all(feature = "queue-transport", feature = "builder"))]
+mod test {
+    use lettre::{
+        transport::{
+            queue::QueueTransport,
+            stub::{self, StubTransport},
+        },
+        Message, Transport,
+    };
+    use std::{env::temp_dir, fs::remove_dir_all, path::PathBuf, time::Duration};
+
+    #[cfg(feature = "tokio02")]
+    use tokio02_crate as tokio;
+
+    fn message(to: &str) -> Message {
+        Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to(to.parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!"))
+            .unwrap()
+    }
+
+    fn queue_dir(name: &str) -> PathBuf {
+        let dir = temp_dir().join(name);
+        let _ = remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn queue_transport() {
+        let dir = queue_dir("lettre-queue-send");
+        let queue = QueueTransport::new(&dir);
+        let mut worker = queue.worker(StubTransport::new_ok());
+        assert_eq!(worker.run_once().unwrap().sent(), 0);
+
+        let id = queue.send(&message("Hei <hei@domain.tld>")).unwrap();
+        let pending = queue.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].0, id);
+        assert!(dir
+            .join("queue")
+            .join("envelope")
+            .join(format!("{}.json", id))
+            .is_file());
+        assert_eq!(worker.transport().message_count(), 0);
+
+        let drained = worker.run_once().unwrap();
+        assert_eq!(drained.sent(), 1);
+        assert!(queue.pending().unwrap().is_empty());
+
+        let sent = worker.transport().last_message().unwrap();
+        assert_eq!(sent.envelope().to()[0].to_string(), "hei@domain.tld");
+        assert_eq!(sent.header("Subject").as_deref(), Some("Happy new year"));
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn queue_transport_retry() {
+        let dir = queue_dir("lettre-queue-retry");
+        let queue = QueueTransport::new(&dir);
+        let transport = StubTransport::new_ok().fail_first(2, stub::Error::reply(451, "Later"));
+        let mut worker = queue
+            .worker(transport)
+            .retry_delay(Duration::from_millis(0), Duration::from_millis(0));
+
+        queue.send(&message("Hei <hei@domain.tld>")).unwrap();
+        assert_eq!(worker.run_once().unwrap().retried(), 1);
+        assert_eq!(worker.run_once().unwrap().retried(), 1);
+        assert_eq!(queue.pending().unwrap().len(), 1);
+
+        assert_eq!(worker.run_once().unwrap().sent(), 1);
+        assert_eq!(worker.transport().message_count(), 3);
+        assert!(queue.pending().unwrap().is_empty());
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn queue_transport_waiting() {
+        let dir = queue_dir("lettre-queue-waiting");
+        let queue = QueueTransport::new(&dir);
+        let mut worker = queue.worker(StubTransport::new_error());
+
+        queue.send(&message("Hei <hei@domain.tld>")).unwrap();
+        assert_eq!(worker.run_once().unwrap().retried(), 1);
+
+        let drained = worker.run_once().unwrap();
+        assert_eq!(drained.waiting(), 1);
+        assert_eq!(drained.retried(), 0);
+        assert_eq!(worker.transport().message_count(), 1);
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn queue_transport_dead_letters() {
+        let dir = queue_dir("lettre-queue-dead-letters");
+        let queue = QueueTransport::new(&dir);
+        let transport = StubTransport::new_ok()
+            .fail_domain("invalid.tld", stub::Error::reply(550, "No such user"))
+            .fail_domain("busy.tld", stub::Error::reply(452, "Mailbox full"));
+        let mut worker = queue
+            .worker(transport)
+            .retry_delay(Duration::from_millis(0), Duration::from_millis(0))
+            .max_attempts(2);
+
+        let invalid = queue.send(&message("Hei <hei@invalid.tld>")).unwrap();
+        let busy = queue.send(&message("Hei <hei@busy.tld>")).unwrap();
+        queue.send(&message("Hei <hei@domain.tld>")).unwrap();
+
+        let drained = worker.run_once().unwrap();
+        assert_eq!(drained.sent(), 1);
+        assert_eq!(drained.retried(), 1);
+        assert_eq!(drained.dead_lettered(), 1);
+
+        let drained = worker.run_once().unwrap();
+        assert_eq!(drained.dead_lettered(), 1);
+        assert!(queue.pending().unwrap().is_empty());
+
+        let dead_letters = queue.dead_letters();
+        let ids: Vec<String> = dead_letters
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids, vec![invalid.clone(), busy]);
+        let (envelope, _) = dead_letters.read(&invalid).unwrap();
+        assert_eq!(envelope.to()[0].to_string(), "hei@invalid.tld");
+
+        queue.requeue(&invalid).unwrap();
+        assert_eq!(queue.pending().unwrap()[0].0, invalid);
+        assert_eq!(dead_letters.list().unwrap().len(), 1);
+        assert!(!dir
+            .join("dead-letter")
+            .join("envelope")
+            .join(format!("{}.json", invalid))
+            .exists());
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn queue_transport_permanent_failures() {
+        let dir = queue_dir("lettre-queue-permanent-failures");
+        let queue = QueueTransport::new(&dir);
+        let transport = StubTransport::new_ok().fail_first(1, stub::Error::reply(550, "Denied"));
+        let mut worker = queue
+            .worker(transport)
+            .permanent_failures(|_| false)
+            .retry_delay(Duration::from_millis(0), Duration::from_millis(0));
+
+        queue.send(&message("Hei <hei@domain.tld>")).unwrap();
+        assert_eq!(worker.run_once().unwrap().retried(), 1);
+        assert_eq!(worker.run_once().unwrap().sent(), 1);
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn queue_transport_restart() {
+        let dir = queue_dir("lettre-queue-restart");
+        let queue = QueueTransport::new(&dir);
+        let id = queue.send(&message("Hei <hei@domain.tld>")).unwrap();
+        let attempts = dir.join("queue").join("attempts").join(&id);
+
+        for _ in 0..2 {
+            let mut worker = queue.worker(StubTransport::new_error()).max_attempts(3);
+            assert_eq!(worker.run_once().unwrap().retried(), 1);
+        }
+        assert_eq!(std::fs::read_to_string(&attempts).unwrap(), "2");
+
+        let mut worker = queue.worker(StubTransport::new_error()).max_attempts(3);
+        assert_eq!(worker.run_once().unwrap().dead_lettered(), 1);
+        assert!(!attempts.exists());
+
+        queue.requeue(&id).unwrap();
+        let mut worker = queue.worker(StubTransport::new_error()).max_attempts(3);
+        assert_eq!(worker.run_once().unwrap().retried(), 1);
+
+        let mut worker = queue.worker(StubTransport::new_ok());
+        assert_eq!(worker.run_once().unwrap().sent(), 1);
+        assert!(!attempts.exists());
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn queue_transport_orphaned_message() {
+        let dir = queue_dir("lettre-queue-orphaned");
+        let queue = QueueTransport::new(&dir);
+        let mut worker = queue.worker(StubTransport::new_ok());
+
+        let orphaned = queue.send(&message("Hei <hei@domain.tld>")).unwrap();
+        queue.send(&message("Yuin <yuin@domain.tld>")).unwrap();
+        // Left without an envelope, like by a crash in the middle of a move
+        std::fs::remove_file(
+            dir.join("queue")
+                .join("envelope")
+                .join(format!("{}.json", orphaned)),
+        )
+        .unwrap();
+
+        let drained = worker.run_once().unwrap();
+        assert_eq!(drained.sent(), 1);
+        assert_eq!(drained.dead_lettered(), 1);
+        let sent = worker.transport().last_message().unwrap();
+        assert_eq!(sent.envelope().to()[0].to_string(), "yuin@domain.tld");
+        assert!(queue.pending().unwrap().is_empty());
+        assert!(dir
+            .join("dead-letter")
+            .join("new")
+            .join(&orphaned)
+            .is_file());
+        queue.dead_letters().remove(&orphaned).unwrap();
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "tokio02")]
+    #[tokio::test]
+    async fn queue_transport_tokio02() {
+        use lettre::{transport::queue::AsyncQueueTransport, AsyncTransport, Tokio02Executor};
+
+        let dir = queue_dir("lettre-queue-tokio02");
+        let queue = AsyncQueueTransport::<Tokio02Executor>::new(&dir);
+        let transport = StubTransport::new_ok()
+            .fail_first(1, stub::Error::reply(451, "Later"))
+            .fail_domain("invalid.tld", stub::Error::reply(550, "No such user"));
+        let mut worker = queue
+            .worker(transport)
+            .retry_delay(Duration::from_millis(0), Duration::from_millis(0));
+
+        queue.send(message("Hei <hei@domain.tld>")).await.unwrap();
+        let invalid = queue.send(message("Hei <hei@invalid.tld>")).await.unwrap();
+
+        let drained = worker.run_once().await.unwrap();
+        assert_eq!(drained.retried(), 1);
+        assert_eq!(drained.dead_lettered(), 1);
+
+        assert_eq!(worker.run_once().await.unwrap().sent(), 1);
+        assert!(queue.pending().await.unwrap().is_empty());
+
+        let dead_letters = queue.dead_letters().list().await.unwrap();
+        assert_eq!(dead_letters[0].0, invalid);
+        queue.requeue(&invalid).await.unwrap();
+        assert_eq!(queue.pending().await.unwrap().len(), 1);
+
+        remove_dir_all(dir).unwrap();
+    }
+}
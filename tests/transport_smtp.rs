@@ -1,7 +1,13 @@
 #[cfg(test)]
 #[cfg(all(feature = "smtp-transport", feature = "builder"))]
 mod test {
-    use lettre::{Message, SmtpTransport, Transport};
+    use lettre::{
+        transport::smtp::{
+            client::{Direction, Transcript},
+            mx::{MxTransport, StaticResolver},
+        },
+        Message, SmtpTransport, Transport,
+    };
 
     #[test]
     fn smtp_transport_simple() {
@@ -12,10 +18,130 @@ mod test {
             .subject("Happy new year")
             .body(String::from("Be happy!"))
             .unwrap();
+        let response = SmtpTransport::builder_dangerous("127.0.0.1")
+            .port(2525)
+            .build()
+            .send(&email)
+            .unwrap();
+        assert!(response.response().is_positive());
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        assert!(response.tls_info().is_none());
+    }
+
+    #[test]
+    fn smtp_transport_connect_to() {
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!"))
+            .unwrap();
+        SmtpTransport::builder_dangerous("mail.invalid")
+            .connect_to("127.0.0.1")
+            .port(2525)
+            .build()
+            .send(&email)
+            .unwrap();
+        SmtpTransport::builder_dangerous("[127.0.0.1]")
+            .port(2525)
+            .build()
+            .send(&email)
+            .unwrap();
+
+        // The relay keeps its name
+        let response = SmtpTransport::failover()
+            .relay(
+                SmtpTransport::builder_dangerous("mail.invalid")
+                    .connect_to("127.0.0.1")
+                    .port(2525),
+            )
+            .build()
+            .send(&email)
+            .unwrap();
+        assert_eq!(response.relay(), "mail.invalid");
+    }
+
+    #[test]
+    fn smtp_transport_source_address() {
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!"))
+            .unwrap();
         SmtpTransport::builder_dangerous("127.0.0.1")
             .port(2525)
+            .source_address("127.0.0.1".parse().unwrap())
             .build()
             .send(&email)
             .unwrap();
+
+        // No IPv6 address to connect to from an IPv4 relay
+        let result = SmtpTransport::builder_dangerous("127.0.0.1")
+            .port(2525)
+            .source_address("::1".parse().unwrap())
+            .build()
+            .test_connection();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn smtp_transport_transcript() {
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!"))
+            .unwrap();
+        let transcript = Transcript::new();
+        SmtpTransport::builder_dangerous("127.0.0.1")
+            .port(2525)
+            .session_observer(transcript.clone())
+            .build()
+            .send(&email)
+            .unwrap();
+
+        let entries = transcript.entries();
+        assert_eq!(entries[0].direction(), Direction::Server);
+        assert!(entries[0].text().starts_with("220"));
+        assert!(entries[1].text().starts_with("EHLO "));
+        assert!(entries
+            .iter()
+            .any(|entry| entry.text() == "RCPT TO:<hei@domain.tld>"));
+        assert!(entries
+            .iter()
+            .any(|entry| entry.text().starts_with("<message, ")));
+        assert!(entries
+            .windows(2)
+            .all(|pair| pair[0].timestamp() <= pair[1].timestamp()));
+    }
+
+    #[test]
+    fn smtp_transport_mx() {
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@example.com>".parse().unwrap())
+            .to("Yuin <yuin@example.org>".parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!"))
+            .unwrap();
+        let resolver = StaticResolver::new()
+            .mx("example.com", 20, "mx2.example.com")
+            .mx("example.com", 10, "mx1.example.com")
+            .ip("mx2.example.com", "127.0.0.1".parse().unwrap());
+        let sender = MxTransport::builder(resolver).port(2525).build();
+
+        let response = sender.send(&email).unwrap();
+        assert!(!response.is_complete());
+
+        let deliveries = response.deliveries();
+        assert_eq!(deliveries.len(), 2);
+        assert_eq!(deliveries[0].domain(), "example.com");
+        assert_eq!(
+            deliveries[0].result().as_ref().unwrap().relay(),
+            "mx2.example.com"
+        );
+        assert_eq!(deliveries[1].domain(), "example.org");
+        assert!(deliveries[1].result().is_err());
     }
 }
@@ -1,8 +1,8 @@
 #[cfg(all(test, feature = "smtp-transport", feature = "r2d2"))]
 mod test {
-    use lettre::{address::Envelope, SmtpTransport, Transport};
+    use lettre::{address::Envelope, transport::smtp::PoolConfig, SmtpTransport, Transport};
 
-    use std::{sync::mpsc, thread};
+    use std::{sync::mpsc, thread, time::Duration};
 
     fn envelope() -> Envelope {
         Envelope::new(
@@ -56,4 +56,74 @@ mod test {
             .send_raw(&envelope(), b"test")
             .expect("Send failed from main thread");
     }
+
+    #[test]
+    fn send_after_shutdown() {
+        let mailer = SmtpTransport::builder_dangerous("127.0.0.1")
+            .port(2525)
+            .build();
+
+        mailer
+            .send_raw(&envelope(), b"test")
+            .expect("Send failed before shutdown");
+
+        mailer.shutdown(Duration::from_secs(5)).unwrap();
+
+        assert!(mailer.send_raw(&envelope(), b"test").is_err());
+    }
+
+    #[cfg(feature = "smtp-test-server")]
+    #[test]
+    fn keepalive() {
+        use lettre::transport::smtp::{client::Transcript, test_server::TestServer};
+
+        let server = TestServer::start().unwrap();
+        let transcript = Transcript::new();
+        let mailer = SmtpTransport::builder_dangerous("127.0.0.1")
+            .port(server.port())
+            .session_observer(transcript.clone())
+            .pool_config(
+                PoolConfig::new()
+                    .test_on_checkout(false)
+                    .keepalive(Some(Duration::from_millis(50))),
+            )
+            .build();
+
+        mailer
+            .send_raw(&envelope(), b"test")
+            .expect("Send failed before keepalive");
+        thread::sleep(Duration::from_millis(200));
+
+        let noops = || {
+            transcript
+                .entries()
+                .iter()
+                .filter(|entry| entry.text().trim_end() == "NOOP")
+                .count()
+        };
+        assert!(noops() > 0, "no NOOP reached the server");
+
+        mailer
+            .send_raw(&envelope(), b"test")
+            .expect("Send failed after keepalive");
+        assert_eq!(server.messages().len(), 2);
+    }
+
+    #[test]
+    fn failover() {
+        let sender = SmtpTransport::failover()
+            // Nothing listens on this port
+            .relay(
+                SmtpTransport::builder_dangerous("127.0.0.1")
+                    .port(9)
+                    .pool_config(PoolConfig::new().connection_timeout(Duration::from_secs(1))),
+            )
+            .relay(SmtpTransport::builder_dangerous("localhost").port(2525))
+            .build();
+
+        let response = sender
+            .send_raw(&envelope(), b"test")
+            .expect("Send failed on all relays");
+        assert_eq!(response.relay(), "localhost");
+    }
 }
@@ -23,6 +23,97 @@ mod test {
         sender_ko.send(&email).unwrap_err();
     }
 
+    #[test]
+    fn stub_transport_records_messages() {
+        use lettre::Transport;
+        use std::thread;
+
+        let sender = StubTransport::new_ok();
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Тема сообщения")
+            .body(String::from("Be happy!"))
+            .unwrap();
+
+        let clone = sender.clone();
+        let thread_email = email.clone();
+        thread::spawn(move || clone.send(&thread_email).unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(sender.message_count(), 1);
+
+        let sent = sender.last_message().unwrap();
+        assert_eq!(sent.envelope(), email.envelope());
+        assert_eq!(sent.raw(), &email.formatted()[..]);
+        assert_eq!(sent.header("subject").as_deref(), Some("Тема сообщения"));
+        assert_eq!(
+            sent.header("From").as_deref(),
+            Some("NoBody <nobody@domain.tld>")
+        );
+        assert_eq!(sent.headers()[0].0, "From");
+
+        assert_eq!(
+            sender.messages_to(&"hei@domain.tld".parse().unwrap()),
+            vec![sent]
+        );
+        assert!(sender
+            .messages_to(&"nobody@domain.tld".parse().unwrap())
+            .is_empty());
+
+        sender.clear();
+        assert!(sender.messages().is_empty());
+        assert!(sender.last_message().is_none());
+    }
+
+    #[test]
+    fn stub_transport_scripted_outcomes() {
+        use lettre::{transport::stub::Error, Transport};
+        use std::time::{Duration, Instant};
+
+        let email = |to: &str| {
+            Message::builder()
+                .from("NoBody <nobody@domain.tld>".parse().unwrap())
+                .to(to.parse().unwrap())
+                .subject("Happy new year")
+                .body(String::from("Be happy!"))
+                .unwrap()
+        };
+        let sender = StubTransport::new_ok()
+            .fail_domain("Example.org", Error::reply(550, "No such domain"))
+            .outcomes(vec![Err(Error::reply(421, "Try again later")), Ok(())])
+            .fail_first(3, Error::new())
+            .latency(Duration::from_millis(20));
+
+        let start = Instant::now();
+        let error = sender.send(&email("Hei <hei@domain.tld>")).unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(error.is_transient());
+        assert_eq!(error.code(), Some(421));
+        assert_eq!(error.message(), Some("Try again later"));
+        assert_eq!(error.to_string(), "stub error: 421 Try again later");
+
+        sender.send(&email("Hei <hei@domain.tld>")).unwrap();
+        let error = sender.send(&email("Hei <hei@domain.tld>")).unwrap_err();
+        assert_eq!(error, Error::new());
+        sender.send(&email("Hei <hei@domain.tld>")).unwrap();
+
+        let error = sender.send(&email("Hei <hei@example.org>")).unwrap_err();
+        assert!(error.is_permanent());
+        assert_eq!(sender.message_count(), 5);
+
+        let sender = StubTransport::new_error().rule(|sends, envelope| {
+            if sends % 2 == 0 && envelope.to().len() == 1 {
+                Some(Ok(()))
+            } else {
+                None
+            }
+        });
+        sender.send(&email("Hei <hei@domain.tld>")).unwrap();
+        sender.send(&email("Hei <hei@domain.tld>")).unwrap_err();
+        sender.send(&email("Hei <hei@domain.tld>")).unwrap();
+    }
+
     #[cfg(feature = "async-std1")]
     #[async_std::test]
     async fn stub_transport_asyncstd1() {
@@ -43,6 +134,29 @@ mod test {
         sender_ko.send(email).await.unwrap_err();
     }
 
+    #[cfg(feature = "async-std1")]
+    #[async_std::test]
+    async fn stub_transport_scripted_outcomes_asyncstd1() {
+        use lettre::{transport::stub::Error, AsyncStd1Transport};
+        use std::time::{Duration, Instant};
+
+        let sender = StubTransport::new_ok()
+            .fail_first(1, Error::reply(451, "Local error"))
+            .latency(Duration::from_millis(20));
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!"))
+            .unwrap();
+
+        let start = Instant::now();
+        assert!(sender.send(email.clone()).await.unwrap_err().is_transient());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        sender.send(email).await.unwrap();
+        assert_eq!(sender.message_count(), 2);
+    }
+
     #[cfg(feature = "tokio02")]
     #[tokio::test]
     async fn stub_transport_tokio02() {
@@ -61,5 +175,28 @@ mod test {
 
         sender_ok.send(email.clone()).await.unwrap();
         sender_ko.send(email).await.unwrap_err();
+        assert_eq!(sender_ok.message_count(), 1);
+        assert_eq!(sender_ko.message_count(), 1);
+    }
+
+    #[cfg(feature = "tokio02")]
+    #[tokio::test]
+    async fn stub_transport_executor_latency_tokio02() {
+        use lettre::{Tokio02Executor, Tokio02Transport};
+        use std::time::{Duration, Instant};
+
+        let sender = StubTransport::new_ok()
+            .latency(Duration::from_millis(20))
+            .executor::<Tokio02Executor>();
+        let email = Message::builder()
+            .from("NoBody <nobody@domain.tld>".parse().unwrap())
+            .to("Hei <hei@domain.tld>".parse().unwrap())
+            .subject("Happy new year")
+            .body(String::from("Be happy!"))
+            .unwrap();
+
+        let start = Instant::now();
+        sender.send(email).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
     }
 }